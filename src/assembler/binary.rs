@@ -83,7 +83,8 @@ impl RawRegion {
 
 #[derive(Clone, Debug)]
 pub struct BinaryBreakpoint {
-    pub location: Location,
+    pub location: Location, // inside the macro body for expanded instructions
+    pub expansion: Option<Location>, // the macro call
     pub pcs: Vec<u32>,
 }
 
@@ -156,6 +157,51 @@ impl Binary {
         source_breakpoints(&self.breakpoints, source, id)
     }
 
+    // Closest label at or before address, and the offset from that label.
+    pub fn label_before(&self, address: u32) -> Option<(&str, u32)> {
+        self.labels.iter()
+            .filter(|(_, value)| **value <= address)
+            .max_by(|(a_name, a), (b_name, b)| a.cmp(b).then_with(|| b_name.cmp(a_name)))
+            .map(|(name, value)| (name.as_str(), address - value))
+    }
+
+    // Just past the last instruction of the region with the entry point.
+    pub fn text_end(&self) -> Option<u32> {
+        self.regions.iter()
+            .find(|region| self.entry >= region.address && self.entry < region.wrapping_pc())
+            .map(|region| region.wrapping_pc())
+    }
+
+    pub fn location_for(&self, pc: u32) -> Option<Location> {
+        self.breakpoints.iter()
+            .find(|breakpoint| breakpoint.pcs.contains(&pc))
+            .map(|breakpoint| breakpoint.location)
+    }
+
+    // Little endian word as assembled, None outside of every region.
+    pub fn word_at(&self, address: u32) -> Option<u32> {
+        let region = self.regions.iter()
+            .find(|region| address >= region.address && address < region.wrapping_pc())?;
+
+        let start = (address - region.address) as usize;
+        let bytes: [u8; 4] = region.data.get(start .. start + 4)?.try_into().ok()?;
+
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    // pc -> location of the source statement that emitted it
+    pub fn location_map(&self) -> HashMap<u32, Location> {
+        let mut result = HashMap::new();
+
+        for breakpoint in &self.breakpoints {
+            for pc in &breakpoint.pcs {
+                result.entry(*pc).or_insert(breakpoint.location);
+            }
+        }
+
+        result
+    }
+
     pub fn new() -> Binary {
         Binary {
             entry: Text.default_address(),
@@ -20,6 +20,7 @@ enum SymbolType {
 fn do_symbol(
     name: &str,
     location: Location,
+    expansion: Option<Location>,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
     map: &HashMap<&str, &Instruction>,
@@ -41,7 +42,7 @@ fn do_symbol(
             Ok(SymbolType::Label)
         }
         _ => {
-            do_instruction(name, location, iter, builder, map)?;
+            do_instruction(name, location, expansion, iter, builder, map)?;
 
             Ok(SymbolType::Instruction)
         }
@@ -82,7 +83,7 @@ pub fn assemble(items: &[Token], instructions: &[Instruction]) -> Result<Binary,
                 do_directive(directive, token.location, &mut cursor, &mut builder)?
             }
             Symbol(name) => {
-                let result = do_symbol(name.get(), token.location, &mut cursor, &mut builder, &map)?;
+                let result = do_symbol(name.get(), token.location, token.expansion, &mut cursor, &mut builder, &map)?;
 
                 if let SymbolType::Instruction = result {
                     last_directive = None;
@@ -864,6 +864,7 @@ fn dispatch_instruction(
 pub fn do_instruction(
     instruction: &str,
     location: Location,
+    expansion: Option<Location>,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
     map: &HashMap<&str, &Instruction>,
@@ -878,7 +879,7 @@ pub fn do_instruction(
         reason: MissingRegion,
     })?;
 
-    let mut breakpoint = BinaryBreakpoint { location, pcs: vec![] };
+    let mut breakpoint = BinaryBreakpoint { location, expansion, pcs: vec![] };
 
     for (word, branch) in emit.instructions {
         let pc = pc_for_region(&region.raw, Some(location))?;
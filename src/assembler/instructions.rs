@@ -35,7 +35,7 @@ pub struct Instruction<'a> {
     pub encoding: Encoding,
 }
 
-pub const INSTRUCTIONS: [Instruction; 61] = [
+pub const INSTRUCTIONS: [Instruction; 62] = [
     Instruction {
         name: "sll",
         opcode: Func(0),
@@ -276,6 +276,11 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
         opcode: Func(12),
         encoding: Parameterless,
     },
+    Instruction {
+        name: "break",
+        opcode: Func(13),
+        encoding: Parameterless,
+    },
     Instruction {
         name: "lb",
         opcode: Op(32),
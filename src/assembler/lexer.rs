@@ -117,7 +117,7 @@ impl<'a> TokenKind<'a> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Location {
     pub source: usize,
     pub index: usize
@@ -127,6 +127,7 @@ pub struct Location {
 pub struct Token<'a> {
     pub location: Location,
     pub kind: TokenKind<'a>,
+    pub expansion: Option<Location>, // the outermost macro call this token was expanded from
 }
 
 #[derive(Debug)]
@@ -405,7 +406,7 @@ pub fn lex_with_source(mut input: &str, source: usize) -> Result<Vec<Token>, Lex
             });
         }
 
-        result.push(Token { location, kind });
+        result.push(Token { location, kind, expansion: None });
         input = next;
     }
 
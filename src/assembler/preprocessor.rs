@@ -209,6 +209,7 @@ fn consume_include<'a, P: TokenProvider<'a>>(
 
 fn expand_macro<'a, P: TokenProvider<'a>>(
     macro_info: Rc<Macro<'a>>,
+    location: Location,
     parameters: Vec<Vec<Token<'a>>>,
     provider: &P,
     cache: &mut Cache<'a>,
@@ -261,6 +262,7 @@ fn expand_macro<'a, P: TokenProvider<'a>>(
                     result.push(Token {
                         location: token.location,
                         kind: kind.clone(),
+                        expansion: None,
                     });
                 }
                 
@@ -280,12 +282,18 @@ fn expand_macro<'a, P: TokenProvider<'a>>(
         result.push(Token {
             location: token.location,
             kind: mapped_kind,
+            expansion: None,
         });
     }
 
-    let result = preprocess_cached(provider, &result, cache)
+    let mut result = preprocess_cached(provider, &result, cache)
         .map_err(|err| err.reason)?;
 
+    // Nested expansions are overwritten, stepping treats the outermost call as one statement.
+    for token in &mut result {
+        token.expansion = Some(location);
+    }
+
     cache.expanding.remove(&macro_info.name);
 
     Ok(result)
@@ -304,6 +312,7 @@ fn handle_symbol<'a, P: TokenProvider<'a>>(
             .map(|kind| Token {
                 location,
                 kind: kind.clone(),
+                expansion: None,
             })
             .collect());
     }
@@ -312,7 +321,7 @@ fn handle_symbol<'a, P: TokenProvider<'a>>(
     let (position, token) = iter.peek_adjacent();
 
     let Some(last) = token else {
-        return Ok(vec![Token { location, kind: Symbol(name.clone()) }])
+        return Ok(vec![Token { location, kind: Symbol(name.clone()), expansion: None }])
     };
 
     let start = iter.get_position();
@@ -325,6 +334,7 @@ fn handle_symbol<'a, P: TokenProvider<'a>>(
             return Ok(vec![Token {
                 location,
                 kind: Symbol(name.clone()),
+                expansion: None,
             }])
         }
     }
@@ -337,6 +347,7 @@ fn handle_symbol<'a, P: TokenProvider<'a>>(
         return Ok(vec![Token {
             location,
             kind: Symbol(name.clone()),
+            expansion: None,
         }]);
     };
 
@@ -366,7 +377,7 @@ fn handle_symbol<'a, P: TokenProvider<'a>>(
         }
     }
 
-    expand_macro(macro_info.clone(), parameters, provider, cache)
+    expand_macro(macro_info.clone(), location, parameters, provider, cache)
 }
 
 fn preprocess_cached<'a, P: TokenProvider<'a>>(
@@ -46,6 +46,7 @@ impl<'a> TokenProvider<'a> for HoldingProvider<'a> {
     }
 }
 
+#[derive(Clone)]
 pub struct FileProviderSource {
     pub id: usize,
     pub path: Rc<PathBuf>,
@@ -65,6 +66,11 @@ impl FileProviderPool {
         }
     }
 
+    // Every file read so far, index is the source id.
+    pub fn sources(&self) -> Vec<FileProviderSource> {
+        self.sources.borrow().clone()
+    }
+
     pub fn provider_sourced(&self, source: String, path: Rc<PathBuf>) -> Result<FileInfo, LexerError> {
         let (id, tokens) = {
             let source = Rc::new(source);
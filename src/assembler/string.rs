@@ -8,7 +8,7 @@ use crate::assembler::string::SourceError::{Assembler, Lexer, Preprocessor};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::path::PathBuf;
-use crate::assembler::source::{FileProviderPool, HoldingProvider};
+use crate::assembler::source::{FileProviderPool, FileProviderSource, HoldingProvider};
 
 #[derive(Debug)]
 pub enum SourceError {
@@ -77,3 +77,18 @@ pub fn assemble_from_path(source: String, path: PathBuf) -> Result<Binary, Sourc
 
     Ok(binary)
 }
+
+// Like assemble_from_path, but also returns the included files so locations can be resolved.
+pub fn assemble_with_sources(source: String, path: PathBuf) -> Result<(Binary, Vec<FileProviderSource>), SourceError> {
+    let pool = FileProviderPool::new();
+
+    let binary = {
+        let provider = pool.provider_sourced(source, path.into())?.to_provider();
+
+        let items = preprocess(&provider)?;
+
+        assemble(&items, &INSTRUCTIONS)?
+    };
+
+    Ok((binary, pool.sources()))
+}
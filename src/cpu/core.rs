@@ -1,5 +1,5 @@
 use crate::cpu::decoder::Decoder;
-use crate::cpu::error::Error::{CpuInvalid, CpuSyscall, CpuTrap};
+use crate::cpu::error::Error::{CpuBreak, CpuInvalid, CpuSyscall, CpuTrap};
 use crate::cpu::error::Result;
 use crate::cpu::{Memory, State};
 
@@ -543,4 +543,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     fn syscall(&mut self) -> Result<()> {
         Err(CpuSyscall)
     }
+
+    fn r#break(&mut self) -> Result<()> {
+        Err(CpuBreak)
+    }
 }
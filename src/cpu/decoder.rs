@@ -70,6 +70,7 @@ pub trait Decoder<T> {
 
     fn trap(&mut self) -> T;
     fn syscall(&mut self) -> T;
+    fn r#break(&mut self) -> T;
 
     fn dispatch_rtype(&mut self, instruction: u32) -> Option<T> {
         let func = instruction & 0x3F;
@@ -89,6 +90,7 @@ pub trait Decoder<T> {
             8 => self.jr(s),
             9 => self.jalr(s),
             12 => self.syscall(),
+            13 => self.r#break(),
             16 => self.mfhi(d),
             17 => self.mthi(s),
             18 => self.mflo(d),
@@ -350,4 +350,8 @@ impl<Provider: LabelProvider> Decoder<String> for Disassembler<Provider> {
     fn syscall(&mut self) -> String {
         "syscall".to_string()
     }
+
+    fn r#break(&mut self) -> String {
+        "break".to_string()
+    }
 }
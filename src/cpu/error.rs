@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter};
+use num_derive::{ToPrimitive, FromPrimitive};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Error {
@@ -7,6 +8,31 @@ pub enum Error {
     CpuInvalid(u32),
     CpuTrap,
     CpuSyscall, // Intended to be caught by higher level.
+    CpuBreak, // The program asked to stop, see Termination.
+}
+
+// Error without its payload, for matching on the kind of failure.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive)]
+pub enum ErrorKind {
+    MemoryAlign,
+    MemoryUnmapped,
+    CpuInvalid,
+    CpuTrap,
+    CpuSyscall,
+    CpuBreak,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::MemoryAlign(_) => ErrorKind::MemoryAlign,
+            Error::MemoryUnmapped(_) => ErrorKind::MemoryUnmapped,
+            Error::CpuInvalid(_) => ErrorKind::CpuInvalid,
+            Error::CpuTrap => ErrorKind::CpuTrap,
+            Error::CpuSyscall => ErrorKind::CpuSyscall,
+            Error::CpuBreak => ErrorKind::CpuBreak,
+        }
+    }
 }
 
 impl Display for Error {
@@ -23,6 +49,7 @@ impl Display for Error {
             }
             Error::CpuTrap => write!(f, "The instruction was given invalid parameters (CPU Trap was thrown)."),
             Error::CpuSyscall => write!(f, "CPU Syscall was not handled"),
+            Error::CpuBreak => write!(f, "A break instruction was executed"),
         }
     }
 }
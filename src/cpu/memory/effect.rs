@@ -0,0 +1,8 @@
+// A change outside of registers and memory (ex. an input cursor or console output)
+// that history can undo and redo along with the instruction that caused it.
+pub trait SideEffect: Send {
+    fn undo(&mut self);
+    fn redo(&mut self);
+}
+
+pub type Effects = Vec<Box<dyn SideEffect>>;
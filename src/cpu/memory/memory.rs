@@ -1,11 +1,24 @@
 use crate::cpu::error::Result;
+use crate::cpu::memory::effect::Effects;
 use byteorder;
 use byteorder::{ByteOrder, LittleEndian};
+use num_derive::{ToPrimitive, FromPrimitive};
 
 pub trait Memory {
     fn get(&self, address: u32) -> Result<u8>;
     fn set(&mut self, address: u32, value: u8) -> Result<()>;
 
+    // Side effects of devices since the last call, for history.
+    fn take_effects(&mut self) -> Effects {
+        vec![]
+    }
+
+    // Number of 64 KiB backing pages that currently hold data, for the page limit.
+    fn resident_pages(&self) -> usize;
+
+    // Approximate bytes held by this memory, including bookkeeping, ex. for the history budget.
+    fn footprint(&self) -> usize;
+
     fn get_u16(&self, address: u32) -> Result<u16> {
         Ok(LittleEndian::read_u16(
             [self.get(address)?, self.get(address + 1)?].as_slice(),
@@ -41,6 +54,31 @@ pub trait Memory {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    pub fn bytes(&self) -> u32 {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+        }
+    }
+
+    pub fn read<Mem: Memory>(&self, memory: &Mem, address: u32) -> Result<u32> {
+        match self {
+            Width::Byte => memory.get(address).map(|value| value as u32),
+            Width::Half => memory.get_u16(address).map(|value| value as u32),
+            Width::Word => memory.get_u32(address),
+        }
+    }
+}
+
 pub struct Region {
     pub start: u32,
     pub data: Vec<u8>,
@@ -49,3 +87,24 @@ pub struct Region {
 pub trait Mountable {
     fn mount(&mut self, region: Region);
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PageKind {
+    Data(Vec<u8>),
+    Filled(u8), // mapped, but never written
+    Device, // owned by a listener, restored by whoever mounts the device
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub address: u32,
+    pub kind: PageKind,
+}
+
+// Memory that can be listed and rebuilt a page at a time, for save states.
+pub trait Paged {
+    fn pages(&self) -> Vec<Page>;
+
+    // Unmaps everything but devices, then maps pages.
+    fn restore_pages(&mut self, pages: Vec<Page>);
+}
@@ -1,6 +1,7 @@
+pub mod effect;
 pub mod region;
 pub mod section;
 pub mod watched;
 pub mod memory;
 
-pub use memory::{Memory, Mountable, Region};
+pub use memory::{Memory, Mountable, Page, PageKind, Paged, Region, Width};
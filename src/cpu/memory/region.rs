@@ -1,6 +1,7 @@
 use crate::cpu::error::Error::{MemoryAlign, MemoryUnmapped};
 use crate::cpu::error::Result;
 use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::section::SECTION_SIZE;
 use crate::cpu::Memory;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
@@ -35,6 +36,17 @@ impl Default for RegionMemory {
 }
 
 impl Memory for RegionMemory {
+    // Regions aren't paged, each counts as the pages it would take up.
+    fn resident_pages(&self) -> usize {
+        self.regions.iter().map(|region| region.data.len().div_ceil(SECTION_SIZE)).sum()
+    }
+
+    fn footprint(&self) -> usize {
+        size_of::<Self>() + self.regions.iter()
+            .map(|region| size_of::<Region>() + region.data.len())
+            .sum::<usize>()
+    }
+
     fn get(&self, address: u32) -> Result<u8> {
         for region in &self.regions {
             if region.contains(address) {
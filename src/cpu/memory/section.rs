@@ -1,7 +1,8 @@
 use crate::cpu::error::Error::{MemoryAlign, MemoryUnmapped};
 use crate::cpu::error::Result;
 use crate::cpu::memory::section::Section::{Data, Empty, Writable};
-use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::{Mountable, Page, PageKind, Paged, Region};
+use crate::cpu::memory::effect::Effects;
 use crate::cpu::Memory;
 use std::fmt::{Debug, Formatter};
 use Section::Listen;
@@ -11,13 +12,18 @@ const SECTION_SELECTOR_START: u32 = 16;
 const SECTION_SELECTOR_MASK: u32 = !0u32 << SECTION_SELECTOR_START;
 const SECTION_INDEX_MASK: u32 = !0u32 >> (32 - SECTION_SELECTOR_START);
 const SECTION_COUNT: usize = 1 << (32 - SECTION_SELECTOR_START);
-const SECTION_SIZE: usize = 1 << SECTION_SELECTOR_START;
+pub const SECTION_SIZE: usize = 1 << SECTION_SELECTOR_START;
 
-const INITIAL_BYTE: u8 = 0xCC;
+pub const INITIAL_BYTE: u8 = 0xCC; // 0xCCCCCCCC does not decode, running it faults
 
 pub trait ListenResponder {
     fn read(&self, address: u32) -> Result<u8>;
     fn write(&mut self, address: u32, value: u8) -> Result<()>;
+
+    // Undo information for reads and writes since the last call (ex. a consumed key).
+    fn take_effects(&mut self) -> Effects {
+        vec![]
+    }
 }
 
 #[derive(Clone)]
@@ -58,6 +64,8 @@ impl<T: ListenResponder> Debug for Section<T> {
 
 pub struct SectionMemory<T: ListenResponder> {
     sections: Box<[Section<T>; SECTION_COUNT]>,
+    pages: usize, // count of Data sections
+    listeners: Vec<usize>, // selectors of Listen sections
 }
 
 impl<T: ListenResponder + Clone> Clone for SectionMemory<T> {
@@ -68,7 +76,7 @@ impl<T: ListenResponder + Clone> Clone for SectionMemory<T> {
             .try_into()
             .unwrap();
 
-        SectionMemory { sections }
+        SectionMemory { sections, pages: self.pages, listeners: self.listeners.clone() }
     }
 }
 
@@ -81,7 +89,7 @@ impl<T: ListenResponder> SectionMemory<T> {
             .try_into()
             .unwrap();
 
-        SectionMemory { sections }
+        SectionMemory { sections, pages: 0, listeners: vec![] }
     }
 
     fn allocate_data(value: u8) -> Box<[u8; SECTION_SIZE]> {
@@ -89,7 +97,7 @@ impl<T: ListenResponder> SectionMemory<T> {
     }
 
     fn create_section(&mut self, selector: usize) -> &mut [u8; SECTION_SIZE] {
-        self.sections[selector] = Data(Self::allocate_data(INITIAL_BYTE));
+        self.replace_section(selector, Data(Self::allocate_data(INITIAL_BYTE)));
 
         match &mut self.sections[selector] {
             Data(data) => data.as_mut(),
@@ -108,9 +116,25 @@ impl<T: ListenResponder> SectionMemory<T> {
         }
     }
 
+    fn replace_section(&mut self, selector: usize, section: Section<T>) {
+        match self.sections[selector] {
+            Data(_) => self.pages -= 1,
+            Listen(_) => self.listeners.retain(|listener| *listener != selector),
+            _ => { }
+        }
+
+        match section {
+            Data(_) => self.pages += 1,
+            Listen(_) => self.listeners.push(selector),
+            _ => { }
+        }
+
+        self.sections[selector] = section
+    }
+
     // selector is NOT an address! Leading 16-bits.
     pub fn mount_listen(&mut self, selector: usize, listener: T) {
-        self.sections[selector] = Listen(listener);
+        self.replace_section(selector, Listen(listener));
     }
 
     pub fn mount_writable(&mut self, selector: usize, value: u8) {
@@ -135,6 +159,27 @@ const fn split(address: u32) -> (usize, usize) {
 }
 
 impl<T: ListenResponder> Memory for SectionMemory<T> {
+    fn take_effects(&mut self) -> Effects {
+        let mut effects = vec![];
+
+        for selector in &self.listeners {
+            if let Listen(responder) = &mut self.sections[*selector] {
+                effects.extend(responder.take_effects())
+            }
+        }
+
+        effects
+    }
+
+    fn resident_pages(&self) -> usize {
+        self.pages
+    }
+
+    // The section table is cloned with the memory, so it counts even when nothing is mapped.
+    fn footprint(&self) -> usize {
+        size_of::<Self>() + SECTION_COUNT * size_of::<Section<T>>() + self.pages * SECTION_SIZE
+    }
+
     fn get(&self, address: u32) -> Result<u8> {
         let (section, index) = split(address);
 
@@ -161,7 +206,7 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 let mut data = Self::allocate_data(*default);
                 data[index] = value;
 
-                self.sections[section] = Data(data);
+                self.replace_section(section, Data(data));
 
                 Ok(())
             }
@@ -244,7 +289,7 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 data[index] = a;
                 data[index + 1] = b;
 
-                self.sections[section] = Data(data);
+                self.replace_section(section, Data(data));
 
                 Ok(())
             }
@@ -288,7 +333,7 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 data[index + 2] = c;
                 data[index + 3] = d;
 
-                self.sections[section] = Data(data);
+                self.replace_section(section, Data(data));
 
                 Ok(())
             }
@@ -296,6 +341,49 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
     }
 }
 
+impl<T: ListenResponder> Paged for SectionMemory<T> {
+    fn pages(&self) -> Vec<Page> {
+        self.sections.iter()
+            .enumerate()
+            .filter_map(|(selector, section)| {
+                let kind = match section {
+                    Empty => return None,
+                    Data(data) => PageKind::Data(data.to_vec()),
+                    Listen(_) => PageKind::Device,
+                    Writable(value) => PageKind::Filled(*value),
+                };
+
+                Some(Page { address: (selector << SECTION_SELECTOR_START) as u32, kind })
+            })
+            .collect()
+    }
+
+    fn restore_pages(&mut self, pages: Vec<Page>) {
+        for selector in 0 .. SECTION_COUNT {
+            if !matches!(self.sections[selector], Empty | Listen(_)) {
+                self.replace_section(selector, Empty)
+            }
+        }
+
+        for page in pages {
+            let (selector, _) = split(page.address);
+
+            match page.kind {
+                PageKind::Data(bytes) => {
+                    let mut data = Self::allocate_data(INITIAL_BYTE);
+                    let length = bytes.len().min(SECTION_SIZE);
+
+                    data[.. length].copy_from_slice(&bytes[.. length]);
+
+                    self.replace_section(selector, Data(data))
+                }
+                PageKind::Filled(value) => self.replace_section(selector, Writable(value)),
+                PageKind::Device => { }
+            }
+        }
+    }
+}
+
 impl<T: ListenResponder> Mountable for SectionMemory<T> {
     fn mount(&mut self, region: Region) {
         let (start_selector, start_index) = split(region.start);
@@ -1,7 +1,8 @@
 use smallvec::SmallVec;
 use crate::cpu::Memory;
 use crate::cpu::error::Result;
-use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::{Mountable, Page, Paged, Region};
+use crate::cpu::memory::effect::Effects;
 use crate::cpu::memory::watched::BackupValue::{Byte, Short, Word, Null};
 
 #[derive(Clone)]
@@ -60,6 +61,18 @@ impl<T: Memory> Memory for WatchedMemory<T> {
         self.backing.set(address, value)
     }
 
+    fn take_effects(&mut self) -> Effects {
+        self.backing.take_effects()
+    }
+
+    fn resident_pages(&self) -> usize {
+        self.backing.resident_pages()
+    }
+
+    fn footprint(&self) -> usize {
+        size_of::<Self>() + self.backing.footprint()
+    }
+
     fn get_u16(&self, address: u32) -> Result<u16> {
         self.backing.get_u16(address)
     }
@@ -85,6 +98,18 @@ impl<T: Memory> Memory for WatchedMemory<T> {
     }
 }
 
+impl<T: Memory + Paged> Paged for WatchedMemory<T> {
+    fn pages(&self) -> Vec<Page> {
+        self.backing.pages()
+    }
+
+    fn restore_pages(&mut self, pages: Vec<Page>) {
+        self.log.clear();
+
+        self.backing.restore_pages(pages)
+    }
+}
+
 impl<T: Memory + Mountable> Mountable for WatchedMemory<T> {
     fn mount(&mut self, region: Region) {
         self.backing.mount(region)
@@ -0,0 +1,4 @@
+pub mod protocol;
+pub mod server;
+
+pub use server::DapServer;
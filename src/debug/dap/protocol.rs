@@ -0,0 +1,120 @@
+use std::io;
+use std::io::{BufRead, Write};
+use serde_json::{json, Value};
+
+// Reads a Content-Length framed message. None once the stream is closed.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None)
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Numbers outgoing messages. Shared between the request loop and the thread running the program.
+pub struct Outgoing<W: Write> {
+    writer: W,
+    seq: u64,
+}
+
+impl<W: Write> Outgoing<W> {
+    pub fn new(writer: W) -> Outgoing<W> {
+        Outgoing { writer, seq: 1 }
+    }
+
+    pub fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let body = message.to_string();
+
+        write!(self.writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+
+        self.writer.flush()
+    }
+
+    pub fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    pub fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body
+        }))
+    }
+
+    pub fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message
+        }))
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// readMemory returns its data as base64.
+pub fn base64(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter()
+            .enumerate()
+            .fold(0u32, |value, (index, byte)| value | (*byte as u32) << (16 - index * 8));
+
+        for index in 0 .. 4 {
+            if index <= chunk.len() {
+                result.push(BASE64[(value >> (18 - index * 6)) as usize & 0x3F] as char)
+            } else {
+                result.push('=')
+            }
+        }
+    }
+
+    result
+}
+
+// Accepts decimal, 0x hex and negative values.
+pub fn parse_value(text: &str) -> Option<u32> {
+    let text = text.trim();
+
+    if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(negative) = text.strip_prefix('-') {
+        negative.parse::<u32>().ok().map(|value| value.wrapping_neg())
+    } else {
+        text.parse().ok()
+    }
+}
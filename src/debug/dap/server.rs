@@ -0,0 +1,703 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use crate::assembler::binary::{Binary, RegionFlags};
+use crate::assembler::line_details::LineDetails;
+use crate::assembler::string::assemble_with_sources;
+use crate::cpu::error::Error::CpuSyscall;
+use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+use crate::cpu::Memory;
+use crate::debug::dap::protocol::{base64, parse_value, read_message, Outgoing};
+use crate::execution::breakpoints::{Breakpoint, Condition};
+use crate::execution::console::{Console, ConsoleSyscall};
+use crate::execution::elf::setup::create_simple_state;
+use crate::execution::events::EventKind;
+use crate::execution::expression::{Expression, Watch};
+use crate::execution::patch::{PatchOptions, PcMap, SourceEdit};
+use crate::execution::speed::Speed;
+use crate::execution::stepping::StatementMap;
+use crate::execution::termination::Termination;
+use crate::execution::executor::{DebugFrame, ExecutorMode};
+use crate::execution::executor::ExecutorMode::{Invalid, LimitReached, Paused, Running};
+use crate::execution::trackers::call_stack::CallStackTracker;
+use crate::execution::Executor;
+use crate::unit::register::{RegisterId, RegisterName};
+
+type DapExecutor = Executor<SectionMemory<DefaultResponder>, CallStackTracker>;
+
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REFERENCE: u64 = 1;
+const MEMORY_REFERENCE: u64 = 2;
+
+// How often a read syscall waiting on input checks for a pause request.
+const INPUT_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Continue,
+    StepOver,
+    StepIn,
+    StepOut,
+    StepOverInstruction,
+    StepInstruction,
+}
+
+// Everything the thread running the program needs.
+struct RunContext<W: Write> {
+    executor: Arc<DapExecutor>,
+    outgoing: Arc<Mutex<Outgoing<W>>>,
+    running: Arc<AtomicBool>,
+    pause_requested: Arc<AtomicBool>,
+    statements: Arc<StatementMap>,
+    console: Console,
+}
+
+impl<W: Write> RunContext<W> {
+    fn run(&self, action: Action) -> io::Result<()> {
+        let start = self.executor.frame().registers.pc;
+
+        let mut frame = match action {
+            Action::Continue => {
+                self.executor.override_mode(Running);
+                self.executor.run(true)
+            }
+            Action::StepOver => self.executor.step_over_line(&self.statements),
+            Action::StepIn => self.executor.step_into_macro(&self.statements),
+            Action::StepOut => self.executor.step_out(),
+            Action::StepOverInstruction => self.executor.step_over(),
+            Action::StepInstruction => self.executor.step(),
+        };
+
+        let mut waiting = false;
+
+        loop {
+            if frame.mode != Invalid(CpuSyscall) {
+                return self.stopped(&frame)
+            }
+
+            // Output syscalls are handled here, the executor stops on exit syscalls.
+            let printed = self.console.output_length();
+            let result = self.console.syscall(&self.executor);
+            let output = self.console.output_since(printed);
+
+            if !output.is_empty() {
+                let output = String::from_utf8_lossy(&output);
+
+                self.outgoing.lock().event("output", json!({ "category": "stdout", "output": output }))?
+            }
+
+            match result {
+                ConsoleSyscall::Handled | ConsoleSyscall::LimitReached(_) => {
+                    waiting = false;
+
+                    self.executor.syscall_handled();
+
+                    // Ex. the output limit, or a watched buffer the syscall wrote to.
+                    let handled = self.executor.frame();
+
+                    if handled.mode != Running && handled.mode != Paused {
+                        return self.stopped(&handled)
+                    }
+
+                    // The syscall was the instruction being stepped.
+                    // Source steps keep their target, and stop here if the syscall ended the statement.
+                    if action == Action::StepInstruction || (action == Action::StepOverInstruction && frame.registers.pc == start) {
+                        return self.stop("step", None)
+                    }
+
+                    frame = self.executor.run(false)
+                }
+                ConsoleSyscall::Fault(error) => return self.stop("exception", Some(error.to_string())),
+                // The syscall runs again once the client sends input, see DapServer.
+                ConsoleSyscall::NeedsInput => {
+                    if !waiting {
+                        waiting = true;
+
+                        self.executor.notify(EventKind::WaitingForInput);
+                        self.outgoing.lock().event("output", json!({
+                            "category": "console", "output": "Waiting for input...\n"
+                        }))?
+                    }
+
+                    if self.pause_requested.swap(false, Ordering::Relaxed) {
+                        return self.stop("pause", None)
+                    }
+
+                    self.console.wait_for_input(INPUT_POLL);
+                }
+                ConsoleSyscall::Unsupported(v0) => return self.stop("exception", Some(format!("Unsupported syscall {v0}")))
+            }
+        }
+    }
+
+    fn stopped(&self, frame: &DebugFrame) -> io::Result<()> {
+        match frame.mode {
+            ExecutorMode::Exited { code } => self.exit(code),
+            ExecutorMode::Halted => self.exit(0),
+            Invalid(error) => self.stop("exception", Some(error.to_string())),
+            LimitReached(limit) => self.stop("exception", Some(format!("{limit:?} limit reached"))),
+            ExecutorMode::Breakpoint => self.stop("breakpoint", None),
+            Paused | Running => {
+                let reason = if self.pause_requested.swap(false, Ordering::Relaxed) { "pause" } else { "step" };
+
+                self.stop(reason, None)
+            }
+        }
+    }
+
+    fn stop(&self, reason: &str, description: Option<String>) -> io::Result<()> {
+        self.running.store(false, Ordering::Relaxed);
+
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+
+        self.outgoing.lock().event("stopped", body)
+    }
+
+    fn exit(&self, code: u32) -> io::Result<()> {
+        self.running.store(false, Ordering::Relaxed);
+
+        let mut outgoing = self.outgoing.lock();
+
+        outgoing.event("exited", json!({ "exitCode": code }))?;
+        outgoing.event("terminated", json!({ }))
+    }
+}
+
+struct SourceFile {
+    path: PathBuf,
+    text: String,
+}
+
+struct Session {
+    executor: Arc<DapExecutor>,
+    program: PathBuf,
+    binary: Binary,
+    sources: Vec<SourceFile>, // index is the source id
+    breakpoints: HashMap<usize, Vec<Breakpoint>>, // source id -> line breakpoints
+    statements: Arc<StatementMap>,
+    console: Console,
+    stop_on_entry: bool,
+}
+
+impl Session {
+    // Line breakpoints of every source replace the executor's address breakpoints.
+    fn apply_breakpoints(&self) {
+        self.executor.with_breakpoints(|breakpoints| {
+            breakpoints.set_addresses(HashSet::new());
+
+            for breakpoint in self.breakpoints.values().flatten() {
+                breakpoints.add(breakpoint.clone());
+            }
+        })
+    }
+
+    fn source_id(&self, path: &Path) -> Option<usize> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        self.sources.iter().position(|source| source.path == path)
+    }
+
+    fn registers(&self) -> Value {
+        let registers = self.executor.frame().registers;
+
+        let ids = (0 .. 32u8)
+            .map(|index| RegisterId::Line(RegisterName::from(index)))
+            .chain([RegisterId::Pc, RegisterId::Hi, RegisterId::Lo]);
+
+        let variables: Vec<Value> = ids
+            .map(|id| json!({
+                "name": id.to_string(),
+                "value": format!("0x{:08x}", id.get(&registers)),
+                "variablesReference": 0
+            }))
+            .collect();
+
+        json!({ "variables": variables })
+    }
+
+    // Labels outside of executable regions, with the word stored there.
+    fn memory(&self) -> Value {
+        let executable = |address: u32| self.binary.regions.iter().any(|region| {
+            region.flags.contains(RegionFlags::EXECUTABLE)
+                && address >= region.address
+                && address < region.wrapping_pc()
+        });
+
+        let mut labels: Vec<(&String, u32)> = self.binary.labels.iter()
+            .map(|(name, address)| (name, *address))
+            .filter(|(_, address)| !executable(*address))
+            .collect();
+
+        labels.sort_by_key(|(name, address)| (*address, *name));
+
+        let variables: Vec<Value> = self.executor.with_memory(|memory| {
+            labels.into_iter()
+                .map(|(name, address)| json!({
+                    "name": name,
+                    "value": memory.get_u32(address)
+                        .map_or_else(|error| error.to_string(), |value| format!("0x{value:08x}")),
+                    "memoryReference": format!("0x{address:08x}"),
+                    "variablesReference": 0
+                }))
+                .collect()
+        });
+
+        json!({ "variables": variables })
+    }
+}
+
+// A Debug Adapter Protocol server for a single launch.
+// Frontends launch with { "program": "path/to/file.asm", "stopOnEntry": bool, "instructionsPerSecond": number,
+// "input": string }.
+// The custom "hotPatch" request reassembles the program while stopped and continues with the new code,
+// with { "data": bool, "force": bool } as in PatchOptions.
+// Breakpoint conditions and evaluate requests use the watch expression syntax, ex. "word[$sp + 4] == 3".
+// Read syscalls take input from the launch "input" string, the custom "input" request ({ "text": string }, as is),
+// or a "repl" evaluate request while the program runs (a line, ex. typed in the debug console).
+pub struct DapServer<W: Write + Send + 'static> {
+    outgoing: Arc<Mutex<Outgoing<W>>>,
+    session: Option<Session>,
+    running: Arc<AtomicBool>,
+    pause_requested: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl<W: Write + Send + 'static> DapServer<W> {
+    pub fn new(writer: W) -> DapServer<W> {
+        DapServer {
+            outgoing: Arc::new(Mutex::new(Outgoing::new(writer))),
+            session: None,
+            running: Arc::new(AtomicBool::new(false)),
+            pause_requested: Arc::new(AtomicBool::new(false)),
+            done: false
+        }
+    }
+
+    // Serves requests until the client disconnects.
+    pub fn serve<R: BufRead>(&mut self, mut reader: R) -> io::Result<()> {
+        while !self.done {
+            let Some(message) = read_message(&mut reader)? else { break };
+
+            if message["type"] == "request" {
+                self.handle(&message)?
+            }
+        }
+
+        if let Some(session) = &self.session {
+            // Also wakes a read syscall waiting on input.
+            self.pause_requested.store(true, Ordering::Relaxed);
+            session.executor.pause()
+        }
+
+        Ok(())
+    }
+
+    pub fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsEvaluateForHovers": true
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => Ok(json!({ })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": false }
+            ] })),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "evaluate" if arguments["context"] == "repl" && self.running.load(Ordering::Relaxed) => {
+                self.input(&format!("{}\n", arguments["expression"].as_str().unwrap_or_default()))
+                    .map(|_| json!({ "result": "", "variablesReference": 0 }))
+            }
+            "evaluate" => self.check_stopped().and_then(|_| self.evaluate(arguments)),
+            "input" => self.input(arguments["text"].as_str().unwrap_or_default()).map(|_| json!({ })),
+            "continue" | "next" | "stepIn" | "stepOut" => self.check_stopped()
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "pause" => self.pause(),
+            "hotPatch" => self.check_stopped().and_then(|_| self.hot_patch(arguments)),
+            "disconnect" | "terminate" => {
+                self.done = true;
+
+                Ok(json!({ }))
+            }
+            _ => Err(format!("Unsupported request {command}"))
+        };
+
+        let success = result.is_ok();
+
+        match result {
+            Ok(body) => self.outgoing.lock().respond(request, body)?,
+            Err(message) => self.outgoing.lock().fail(request, &message)?
+        }
+
+        if !success {
+            return Ok(())
+        }
+
+        // Events that have to follow the response.
+        match command {
+            "launch" => self.outgoing.lock().event("initialized", json!({ }))?,
+            "configurationDone" => {
+                if self.session.as_ref().is_some_and(|session| session.stop_on_entry) {
+                    self.outgoing.lock().event("stopped", json!({
+                        "reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true
+                    }))?
+                } else {
+                    self.start(Action::Continue)
+                }
+            }
+            "continue" => self.start(Action::Continue),
+            "next" if arguments["granularity"] == "instruction" => self.start(Action::StepOverInstruction),
+            "stepIn" if arguments["granularity"] == "instruction" => self.start(Action::StepInstruction),
+            "next" => self.start(Action::StepOver),
+            "stepIn" => self.start(Action::StepIn),
+            "stepOut" => self.start(Action::StepOut),
+            _ => { }
+        }
+
+        Ok(())
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session.as_ref().ok_or_else(|| "No program was launched".to_string())
+    }
+
+    fn check_stopped(&self) -> Result<(), String> {
+        self.session()?;
+
+        if self.running.load(Ordering::Relaxed) {
+            return Err("The program is already running".into())
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self, action: Action) {
+        let Some(session) = &self.session else { return };
+
+        let context = RunContext {
+            executor: session.executor.clone(),
+            outgoing: self.outgoing.clone(),
+            running: self.running.clone(),
+            pause_requested: self.pause_requested.clone(),
+            statements: session.statements.clone(),
+            console: session.console.clone(),
+        };
+
+        self.running.store(true, Ordering::Relaxed);
+
+        // Runs on its own thread so pause requests can be read while the program runs.
+        thread::spawn(move || context.run(action));
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("Missing program")?;
+        let path = PathBuf::from(program);
+
+        let text = fs::read_to_string(&path).map_err(|error| format!("{program}: {error}"))?;
+        let (binary, sources) = assemble_with_sources(text, path.clone()).map_err(|error| error.to_string())?;
+
+        let state = create_simple_state::<DefaultResponder>(&binary.create_elf(), 0x100000);
+        let executor = Arc::new(Executor::new(state, CallStackTracker::new(binary.entry)));
+
+        executor.set_termination(Termination::for_binary(&binary));
+
+        if let Some(rate) = arguments["instructionsPerSecond"].as_u64() {
+            executor.set_speed(Speed::PerSecond(rate.min(u32::MAX as u64) as u32))
+        }
+
+        let sources = sources.into_iter()
+            .map(|source| SourceFile {
+                path: fs::canonicalize(&*source.path).unwrap_or_else(|_| (*source.path).clone()),
+                text: (*source.source).clone()
+            })
+            .collect();
+
+        let statements = Arc::new(StatementMap::new(&binary));
+
+        let console = Console::new();
+
+        if let Some(input) = arguments["input"].as_str() {
+            console.push_input(input.as_bytes())
+        }
+
+        self.session = Some(Session {
+            executor,
+            program: path,
+            binary,
+            sources,
+            breakpoints: HashMap::new(),
+            statements,
+            console,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false)
+        });
+
+        Ok(json!({ }))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("No program was launched")?;
+
+        let path = arguments["source"]["path"].as_str().ok_or("Missing source path")?;
+        let id = session.source_id(Path::new(path));
+
+        let requested: Vec<(usize, Option<&str>)> = arguments["breakpoints"].as_array()
+            .map(|breakpoints| breakpoints.iter()
+                .filter_map(|breakpoint| {
+                    let line = breakpoint["line"].as_u64()? as usize;
+                    let condition = breakpoint["condition"].as_str().filter(|condition| !condition.trim().is_empty());
+
+                    Some((line, condition))
+                })
+                .collect())
+            .unwrap_or_default();
+
+        let available = id
+            .map(|id| session.binary.source_breakpoints(&session.sources[id].text, id))
+            .unwrap_or_default();
+
+        let mut added = vec![];
+
+        let breakpoints: Vec<Value> = requested.into_iter()
+            .map(|(line, condition)| {
+                // Lines start at 1, a line without code moves to the next line that has some.
+                let found = available.iter()
+                    .filter(|breakpoint| breakpoint.line + 1 >= line && !breakpoint.pcs.is_empty())
+                    .min_by_key(|breakpoint| breakpoint.line);
+
+                let Some(found) = found else { return json!({ "verified": false, "line": line }) };
+
+                let mut breakpoint = Breakpoint::new(found.pcs[0]);
+
+                if let Some(condition) = condition {
+                    match Expression::parse(condition, |name| session.binary.labels.get(name).copied()) {
+                        Ok(expression) => breakpoint = breakpoint.with_condition(Condition::expression(expression)),
+                        Err(error) => return json!({
+                            "verified": false, "line": found.line + 1, "message": error.to_string()
+                        })
+                    }
+                }
+
+                added.push(breakpoint);
+
+                json!({ "verified": true, "line": found.line + 1 })
+            })
+            .collect();
+
+        if let Some(id) = id {
+            session.breakpoints.insert(id, added);
+        }
+
+        session.apply_breakpoints();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let registers = session.executor.frame().registers;
+        let backtrace = session.executor.with_tracker(|tracker| tracker.backtrace(&registers, &session.binary));
+
+        let frames: Vec<Value> = backtrace.frames.iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let mut value = json!({
+                    "id": index,
+                    "name": frame.symbol(),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:08x}", frame.pc)
+                });
+
+                let source = frame.location
+                    .and_then(|location| session.sources.get(location.source).map(|source| (location, source)));
+
+                if let Some((location, source)) = source {
+                    let line = LineDetails::from_offset(&source.text, location.index);
+
+                    value["line"] = json!(line.line_number + 1);
+                    value["column"] = json!(line.line_offset + 1);
+                    value["source"] = json!({
+                        "name": source.path.file_name().map(|name| name.to_string_lossy()),
+                        "path": source.path
+                    });
+                }
+
+                value
+            })
+            .collect();
+
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => Ok(session.registers()),
+            Some(MEMORY_REFERENCE) => Ok(session.memory()),
+            _ => Ok(json!({ "variables": [] }))
+        }
+    }
+
+    fn set_variable(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        if arguments["variablesReference"].as_u64() != Some(REGISTERS_REFERENCE) {
+            return Err("Only registers can be set".into())
+        }
+
+        let name = arguments["name"].as_str().unwrap_or_default();
+        let register = RegisterId::from_name(name).ok_or_else(|| format!("Unknown register {name}"))?;
+
+        let value = arguments["value"].as_str()
+            .and_then(parse_value)
+            .ok_or("Expected a number")?;
+
+        session.executor.with_state(|state| register.set(&mut state.registers, value));
+
+        Ok(json!({ "value": format!("0x{value:08x}") }))
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let address = arguments["memoryReference"].as_str()
+            .and_then(parse_value)
+            .ok_or("Invalid memory reference")?;
+
+        let address = address.wrapping_add(arguments["offset"].as_i64().unwrap_or(0) as u32);
+        let count = arguments["count"].as_u64().unwrap_or(0) as u32;
+
+        let bytes: Vec<u8> = session.executor.with_memory(|memory| {
+            (0 .. count)
+                .map_while(|offset| memory.get(address.wrapping_add(offset)).ok())
+                .collect()
+        });
+
+        Ok(json!({
+            "address": format!("0x{address:08x}"),
+            "data": base64(&bytes),
+            "unreadableBytes": count - bytes.len() as u32
+        }))
+    }
+
+    fn hot_patch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("No program was launched")?;
+
+        let text = fs::read_to_string(&session.program)
+            .map_err(|error| format!("{}: {error}", session.program.display()))?;
+        let (binary, sources) = assemble_with_sources(text, session.program.clone())
+            .map_err(|error| error.to_string())?;
+
+        let sources: Vec<SourceFile> = sources.into_iter()
+            .map(|source| SourceFile {
+                path: fs::canonicalize(&*source.path).unwrap_or_else(|_| (*source.path).clone()),
+                text: (*source.source).clone()
+            })
+            .collect();
+
+        // Sources are matched by path, ids can change when includes are added or removed.
+        let edits: HashMap<usize, SourceEdit> = session.sources.iter()
+            .enumerate()
+            .filter_map(|(id, old)| {
+                let new_id = sources.iter().position(|new| new.path == old.path)?;
+
+                Some((id, SourceEdit::new(new_id, &old.text, &sources[new_id].text)))
+            })
+            .collect();
+
+        let map = PcMap::new(&session.binary, &binary, &edits);
+
+        let options = PatchOptions::new()
+            .with_data(arguments["data"].as_bool().unwrap_or(false))
+            .with_force(arguments["force"].as_bool().unwrap_or(false));
+
+        let frames = session.executor.with_tracker(|tracker| tracker.frames().to_vec());
+        let report = session.executor.hot_patch(&session.binary, &binary, &map, &frames, &options);
+
+        let conflicts: Vec<String> = report.conflicts.iter().map(|conflict| conflict.to_string()).collect();
+
+        if report.applied {
+            // Line breakpoints moved with their statements, the sources they are keyed by may have new ids.
+            session.breakpoints = session.breakpoints.drain()
+                .filter_map(|(id, mut breakpoints)| {
+                    let id = edits.get(&id).map(|edit| edit.source)?;
+
+                    for breakpoint in &mut breakpoints {
+                        breakpoint.address = map.get(breakpoint.address).unwrap_or(breakpoint.address)
+                    }
+
+                    Some((id, breakpoints))
+                })
+                .collect();
+
+            session.statements = Arc::new(StatementMap::new(&binary));
+            session.binary = binary;
+            session.sources = sources;
+        }
+
+        Ok(json!({ "applied": report.applied, "conflicts": conflicts }))
+    }
+
+    // Watch expressions, see Watch for the syntax. Labels come from the running binary.
+    fn evaluate(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let text = arguments["expression"].as_str().ok_or("Missing expression")?;
+
+        let watch = Watch::parse(text, |name| session.binary.labels.get(name).copied())
+            .map_err(|error| error.to_string())?;
+
+        let result = session.executor.with_state(|state| watch.evaluate(state))
+            .map_err(|error| error.to_string())?;
+
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    fn input(&self, text: &str) -> Result<(), String> {
+        self.session()?.console.push_input(text.as_bytes());
+
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<Value, String> {
+        let session = self.session()?;
+
+        if self.running.load(Ordering::Relaxed) {
+            self.pause_requested.store(true, Ordering::Relaxed);
+
+            session.executor.pause();
+        }
+
+        Ok(json!({ }))
+    }
+}
@@ -0,0 +1,5 @@
+pub mod packet;
+pub mod server;
+pub mod target;
+
+pub use server::GdbServer;
@@ -0,0 +1,218 @@
+use std::io;
+use std::io::{BufReader, Bytes, Read};
+
+const INTERRUPT: u8 = 0x03;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incoming {
+    Packet(Vec<u8>), // unescaped payload
+    Corrupt, // checksum mismatch, should be answered with '-'
+    Interrupt, // Ctrl-C while the target is running
+    Ack,
+    Nack,
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Frames a payload as $payload#checksum, escaping the characters that need it.
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(payload.len());
+
+    for byte in payload {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+            _ => escaped.push(*byte)
+        }
+    }
+
+    let mut result = Vec::with_capacity(escaped.len() + 4);
+
+    result.push(b'$');
+    result.extend_from_slice(&escaped);
+    result.extend_from_slice(format!("#{:02x}", checksum(&escaped)).as_bytes());
+
+    result
+}
+
+pub struct PacketReader<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(reader: R) -> PacketReader<R> {
+        PacketReader { bytes: BufReader::new(reader).bytes() }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        self.bytes.next().transpose()
+    }
+
+    // None once the connection is closed.
+    pub fn receive(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            let Some(byte) = self.byte()? else { return Ok(None) };
+
+            match byte {
+                b'$' => return self.packet().map(Some),
+                b'+' => return Ok(Some(Incoming::Ack)),
+                b'-' => return Ok(Some(Incoming::Nack)),
+                INTERRUPT => return Ok(Some(Incoming::Interrupt)),
+                _ => { } // noise between packets
+            }
+        }
+    }
+
+    fn packet(&mut self) -> io::Result<Incoming> {
+        let mut raw = vec![];
+
+        loop {
+            let Some(byte) = self.byte()? else {
+                return Err(io::ErrorKind::UnexpectedEof.into())
+            };
+
+            if byte == b'#' {
+                break
+            }
+
+            raw.push(byte)
+        }
+
+        let mut digits = [0u8; 2];
+
+        for digit in &mut digits {
+            *digit = self.byte()?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        }
+
+        let expected = std::str::from_utf8(&digits).ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+
+        if expected != Some(checksum(&raw)) {
+            return Ok(Incoming::Corrupt)
+        }
+
+        let mut payload = Vec::with_capacity(raw.len());
+        let mut bytes = raw.into_iter();
+
+        while let Some(byte) = bytes.next() {
+            if byte == b'}' {
+                if let Some(next) = bytes.next() {
+                    payload.push(next ^ 0x20)
+                }
+            } else {
+                payload.push(byte)
+            }
+        }
+
+        Ok(Incoming::Packet(payload))
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None
+    }
+
+    (0 .. text.len()).step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index .. index + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn parse_number(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// Registers are sent in target byte order, Titan is little endian.
+pub fn register_hex(value: u32) -> String {
+    to_hex(&value.to_le_bytes())
+}
+
+pub fn parse_register(text: &str) -> Option<u32> {
+    let bytes: [u8; 4] = from_hex(text)?.try_into().ok()?;
+
+    Some(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive_all(bytes: &[u8]) -> Vec<Incoming> {
+        let mut reader = PacketReader::new(bytes);
+        let mut result = vec![];
+
+        while let Some(incoming) = reader.receive().unwrap() {
+            result.push(incoming)
+        }
+
+        result
+    }
+
+    #[test]
+    fn encode_frames_with_checksum() {
+        assert_eq!(encode(b"OK"), b"$OK#9a");
+        assert_eq!(encode(b""), b"$#00");
+    }
+
+    #[test]
+    fn encode_escapes_special_characters() {
+        // The checksum covers the escaped bytes.
+        let expected = [b"$a}\x04}\x03}]}\x0a#".as_slice(), format!("{:02x}", checksum(b"a}\x04}\x03}]}\x0a")).as_bytes()].concat();
+
+        assert_eq!(encode(b"a$#}*"), expected);
+    }
+
+    #[test]
+    fn receive_round_trips_encode() {
+        let payload = b"M10010000,4:$#}*".to_vec();
+
+        assert_eq!(receive_all(&encode(&payload)), vec![Incoming::Packet(payload)]);
+    }
+
+    #[test]
+    fn receive_control_bytes() {
+        let mut bytes = b"+-".to_vec();
+        bytes.push(INTERRUPT);
+        bytes.extend_from_slice(b"noise$g#67");
+
+        assert_eq!(
+            receive_all(&bytes),
+            vec![Incoming::Ack, Incoming::Nack, Incoming::Interrupt, Incoming::Packet(b"g".to_vec())]
+        );
+    }
+
+    #[test]
+    fn receive_bad_checksum() {
+        assert_eq!(receive_all(b"$g#00$g#zz$g#67"), vec![Incoming::Corrupt, Incoming::Corrupt, Incoming::Packet(b"g".to_vec())]);
+    }
+
+    #[test]
+    fn receive_truncated_packet() {
+        let mut reader = PacketReader::new(b"$g#6".as_slice());
+
+        assert_eq!(reader.receive().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+
+        assert_eq!(parse_number("10010000"), Some(0x10010000));
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn registers_are_little_endian() {
+        assert_eq!(register_hex(0x12345678), "78563412");
+        assert_eq!(parse_register("78563412"), Some(0x12345678));
+        assert_eq!(parse_register("785634"), None);
+    }
+}
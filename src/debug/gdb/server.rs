@@ -0,0 +1,572 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::thread::JoinHandle;
+use crate::cpu::error::Error;
+use crate::cpu::memory::watched::WatchedMemory;
+use crate::cpu::Memory;
+use crate::debug::gdb::packet::{encode, from_hex, parse_number, parse_register, register_hex, to_hex, Incoming, PacketReader};
+use crate::debug::gdb::target::{read_register, target_description, write_register, REGISTER_COUNT};
+use crate::execution::console::{Console, ConsoleSyscall};
+use crate::execution::breakpoints::{BreakpointId, Hit, WatchKind, WatchTarget, Watchpoint, WatchpointHit};
+use crate::execution::executor::{DebugFrame, ExecutorMode};
+use crate::execution::executor::ExecutorMode::{Invalid, LimitReached, Paused, Running};
+use crate::execution::trackers::history::HistoryTracker;
+use crate::execution::Executor;
+
+pub type DebugExecutor<Mem> = Executor<WatchedMemory<Mem>, HistoryTracker<Mem>>;
+
+// Signal numbers as GDB defines them, not the host's.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+const SIGSYS: u8 = 12;
+const SIGXCPU: u8 = 24;
+
+const OUTPUT_CHUNK: usize = 0x400; // bytes per console output packet, hex doubles it
+
+const FEATURES: &str = "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;\
+    ReverseStep+;ReverseContinue+;QStartNoAckMode+";
+
+fn signal(error: Error) -> u8 {
+    match error {
+        Error::MemoryAlign(_) => SIGBUS,
+        Error::MemoryUnmapped(_) => SIGSEGV,
+        Error::CpuInvalid(_) => SIGILL,
+        Error::CpuTrap => SIGFPE, // overflow and trap instructions
+        Error::CpuSyscall => SIGSYS, // the console can't serve it, ex. a read without input
+        Error::CpuBreak => SIGTRAP,
+    }
+}
+
+struct MemoryWatch {
+    address: u32,
+    length: u32,
+    kind: WatchKind,
+    id: BreakpointId,
+}
+
+// A GDB remote serial protocol stub for a single executor and connection.
+// Supports registers, memory, breakpoints, watchpoints, stepping and reverse execution.
+// Exit syscalls end the program (see Termination), print and read syscalls go through the console.
+// Printed text is sent to GDB as console output, and monitor input <text> feeds read syscalls.
+pub struct GdbServer<Mem: Memory + Clone> {
+    executor: Arc<DebugExecutor<Mem>>,
+    console: Console,
+    output: Vec<u8>, // printed since the last reply
+    breakpoints: HashSet<u32>,
+    watchpoints: Vec<MemoryWatch>,
+    interrupted: Arc<AtomicBool>,
+    last_stop: String,
+    no_ack: bool,
+    done: bool,
+}
+
+impl<Mem: Memory + Clone + Send + 'static> GdbServer<Mem> {
+    pub fn new(executor: Arc<DebugExecutor<Mem>>) -> GdbServer<Mem> {
+        GdbServer {
+            executor,
+            console: Console::new(),
+            output: vec![],
+            breakpoints: HashSet::new(),
+            watchpoints: vec![],
+            interrupted: Arc::new(AtomicBool::new(false)),
+            last_stop: format!("S{SIGTRAP:02x}"),
+            no_ack: false,
+            done: false
+        }
+    }
+
+    // Shares the program's input and output, ex. to push input before connecting.
+    pub fn with_console(mut self, console: Console) -> Self {
+        self.console = console;
+
+        self
+    }
+
+    // Accepts a single connection (ex. target remote localhost:1234) and serves it.
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+
+        stream.set_nodelay(true)?;
+
+        let (receiver, reader) = self.spawn_reader(stream.try_clone()?);
+        let result = self.serve_packets(receiver, &stream);
+
+        // Unblocks the reader thread, the client may still have the connection open after a detach.
+        let _ = stream.shutdown(Shutdown::Both);
+        let _ = reader.join();
+
+        result
+    }
+
+    // Serves until the client detaches, kills the target or disconnects.
+    // Use stdin and stdout for target remote | titan gdb.
+    // The reader thread can't be woken from a blocking read, it ends once reader closes,
+    // and holds no reference to the executor in the meantime.
+    pub fn serve<R: Read + Send + 'static, W: Write>(&mut self, reader: R, writer: W) -> io::Result<()> {
+        let (receiver, _) = self.spawn_reader(reader);
+
+        self.serve_packets(receiver, writer)
+    }
+
+    // Packets are read on another thread so Ctrl-C can pause a running executor.
+    fn spawn_reader<R: Read + Send + 'static>(&self, reader: R) -> (Receiver<Incoming>, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel();
+
+        let executor = Arc::downgrade(&self.executor);
+        let interrupted = self.interrupted.clone();
+
+        let handle = thread::spawn(move || {
+            let mut reader = PacketReader::new(reader);
+
+            while let Ok(Some(incoming)) = reader.receive() {
+                if incoming == Incoming::Interrupt {
+                    let Some(executor) = executor.upgrade() else { break };
+
+                    interrupted.store(true, Ordering::Relaxed);
+                    executor.pause();
+                } else if sender.send(incoming).is_err() {
+                    break
+                }
+            }
+        });
+
+        (receiver, handle)
+    }
+
+    fn serve_packets<W: Write>(&mut self, receiver: Receiver<Incoming>, mut writer: W) -> io::Result<()> {
+        self.done = false;
+
+        while let Ok(incoming) = receiver.recv() {
+            match incoming {
+                Incoming::Packet(payload) => {
+                    if !self.no_ack {
+                        writer.write_all(b"+")?;
+                    }
+
+                    let reply = self.handle(&String::from_utf8_lossy(&payload));
+
+                    // Console output packets have to come before the stop reply.
+                    for chunk in std::mem::take(&mut self.output).chunks(OUTPUT_CHUNK) {
+                        writer.write_all(&encode(format!("O{}", to_hex(chunk)).as_bytes()))?;
+                    }
+
+                    if let Some(reply) = reply {
+                        writer.write_all(&encode(reply.as_bytes()))?;
+                    }
+
+                    writer.flush()?;
+
+                    if self.done {
+                        break
+                    }
+                }
+                Incoming::Corrupt if !self.no_ack => {
+                    writer.write_all(b"-")?;
+                    writer.flush()?;
+                }
+                _ => { }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the reply to a packet, None if no reply should be sent.
+    // An empty reply tells GDB the packet is not supported.
+    pub fn handle(&mut self, packet: &str) -> Option<String> {
+        let Some(command) = packet.chars().next() else {
+            return Some(String::new())
+        };
+
+        // Non-ASCII commands (ex. U+FFFD from an invalid byte) fall through to the empty reply.
+        let rest = &packet[command.len_utf8()..];
+
+        let reply = match command {
+            '?' => Some(self.last_stop.clone()),
+            'g' => Some(self.read_registers()),
+            'G' => self.write_registers(rest),
+            'p' => self.read_register(rest),
+            'P' => self.write_register(rest),
+            'm' => self.read_memory(rest),
+            'M' => self.write_memory(rest),
+            'c' => Some(self.resume(rest, false)),
+            's' => Some(self.resume(rest, true)),
+            // Signals can't be delivered, only the address is used.
+            'C' | 'S' => Some(self.resume(rest.split_once(';').map_or("", |(_, address)| address), command == 'S')),
+            'b' => Some(self.reverse(rest)),
+            'Z' => self.breakpoint(rest, true),
+            'z' => self.breakpoint(rest, false),
+            'H' | 'T' => Some("OK".into()),
+            'D' => {
+                self.done = true;
+
+                Some("OK".into())
+            }
+            'k' => {
+                self.done = true;
+
+                return None
+            }
+            'q' | 'Q' => Some(self.query(packet)),
+            'v' => Some(self.v_packet(packet)),
+            _ => Some(String::new())
+        };
+
+        Some(reply.unwrap_or_else(|| "E01".into()))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return FEATURES.into()
+        }
+
+        if let Some(command) = packet.strip_prefix("qRcmd,") {
+            return self.monitor(command).unwrap_or_else(|| "E01".into())
+        }
+
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return self.read_target_description(range).unwrap_or_else(|| "E01".into())
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+
+                "OK".into()
+            }
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            "qSymbol::" => "OK".into(),
+            _ => String::new()
+        }
+    }
+
+    fn v_packet(&mut self, packet: &str) -> String {
+        if packet == "vCont?" {
+            return "vCont;c;C;s;S".into()
+        }
+
+        if let Some(actions) = packet.strip_prefix("vCont;") {
+            // There is only one thread, so the first action applies to it.
+            return match actions.chars().next() {
+                Some('c' | 'C') => self.resume("", false),
+                Some('s' | 'S') => self.resume("", true),
+                _ => String::new()
+            }
+        }
+
+        if packet.starts_with("vKill") {
+            self.done = true;
+
+            return "OK".into()
+        }
+
+        String::new()
+    }
+
+    // Commands sent with monitor, hex encoded.
+    fn monitor(&mut self, command: &str) -> Option<String> {
+        let command = String::from_utf8(from_hex(command)?).ok()?;
+
+        if let Some(text) = command.strip_prefix("input ") {
+            self.console.push_input(format!("{text}\n").as_bytes());
+
+            return Some("OK".into())
+        }
+
+        self.output.extend_from_slice(b"Unknown command, try: monitor input <text>\n");
+
+        Some("OK".into())
+    }
+
+    fn read_target_description(&self, range: &str) -> Option<String> {
+        let (offset, length) = range.split_once(',')?;
+        let (offset, length) = (parse_number(offset)? as usize, parse_number(length)? as usize);
+
+        let description = target_description();
+        let start = offset.min(description.len());
+        let end = offset.saturating_add(length).min(description.len());
+
+        let prefix = if end == description.len() { 'l' } else { 'm' };
+
+        Some(format!("{prefix}{}", &description[start .. end]))
+    }
+
+    fn read_registers(&self) -> String {
+        let registers = self.executor.frame().registers;
+
+        (0 .. REGISTER_COUNT)
+            .map(|index| read_register(&registers, index).map_or("xxxxxxxx".into(), register_hex))
+            .collect()
+    }
+
+    fn write_registers(&self, data: &str) -> Option<String> {
+        let values = (0 .. data.len() / 8)
+            .map(|index| data.get(index * 8 .. index * 8 + 8))
+            .collect::<Option<Vec<&str>>>()?;
+
+        self.executor.with_state(|state| {
+            for (index, value) in values.into_iter().enumerate().take(REGISTER_COUNT) {
+                // Unavailable registers come back as x's.
+                if let Some(value) = parse_register(value) {
+                    write_register(&mut state.registers, index, value);
+                }
+            }
+        });
+
+        Some("OK".into())
+    }
+
+    fn read_register(&self, index: &str) -> Option<String> {
+        let index = parse_number(index)? as usize;
+
+        if index >= REGISTER_COUNT {
+            return None
+        }
+
+        let registers = self.executor.frame().registers;
+
+        Some(read_register(&registers, index).map_or("xxxxxxxx".into(), register_hex))
+    }
+
+    fn write_register(&self, assignment: &str) -> Option<String> {
+        let (index, value) = assignment.split_once('=')?;
+        let (index, value) = (parse_number(index)? as usize, parse_register(value)?);
+
+        let written = self.executor.with_state(|state| write_register(&mut state.registers, index, value));
+
+        written.then(|| "OK".into())
+    }
+
+    fn read_memory(&self, range: &str) -> Option<String> {
+        let (address, length) = range.split_once(',')?;
+        let (address, length) = (parse_number(address)?, parse_number(length)?);
+
+        let bytes = self.executor.with_memory(|memory| {
+            (0 .. length)
+                .map_while(|offset| memory.get(address.wrapping_add(offset)).ok())
+                .collect::<Vec<u8>>()
+        });
+
+        // Partial reads are allowed, but a read that fails right away is an error.
+        (length == 0 || !bytes.is_empty()).then(|| to_hex(&bytes))
+    }
+
+    fn write_memory(&self, data: &str) -> Option<String> {
+        let (range, bytes) = data.split_once(':')?;
+        let (address, _) = range.split_once(',')?;
+        let (address, bytes) = (parse_number(address)?, from_hex(bytes)?);
+
+        self.executor.with_memory(|memory| {
+            for (offset, byte) in bytes.into_iter().enumerate() {
+                memory.set(address.wrapping_add(offset as u32), byte).ok()?
+            }
+
+            Some("OK".into())
+        })
+    }
+
+    fn resume(&mut self, address: &str, step: bool) -> String {
+        if let Some(address) = parse_number(address) {
+            self.executor.with_state(|state| state.registers.pc = address);
+        }
+
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        // Only the first instruction can be sitting on the breakpoint that stopped us.
+        let mut skip_breakpoint = true;
+
+        loop {
+            self.executor.override_mode(Running);
+
+            let frame = if step {
+                self.executor.step()
+            } else {
+                self.executor.run(skip_breakpoint)
+            };
+
+            if frame.mode != Invalid(Error::CpuSyscall) {
+                return self.stop_reply(&frame)
+            }
+
+            let printed = self.console.output_length();
+            let result = self.console.syscall(&*self.executor);
+
+            self.output.extend(self.console.output_since(printed));
+
+            match result {
+                ConsoleSyscall::Handled | ConsoleSyscall::LimitReached(_) => self.executor.syscall_handled(),
+                ConsoleSyscall::Fault(error) => return self.stop_reply(&DebugFrame { mode: Invalid(error), ..frame }),
+                ConsoleSyscall::NeedsInput | ConsoleSyscall::Unsupported(_) => return self.stop_reply(&frame)
+            }
+
+            // Ex. the output limit, or a watched buffer the syscall wrote to.
+            let handled = self.executor.frame();
+
+            if handled.mode != Running && handled.mode != Paused {
+                return self.stop_reply(&handled)
+            }
+
+            // The syscall was the step, or Ctrl-C came in while it ran.
+            if step || self.interrupted.load(Ordering::Relaxed) {
+                self.executor.override_mode(Paused);
+
+                return self.stop_reply(&self.executor.frame())
+            }
+
+            skip_breakpoint = false
+        }
+    }
+
+    fn reverse(&mut self, direction: &str) -> String {
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        let reply = match direction {
+            "s" => {
+                if self.executor.reverse_step() {
+                    format!("S{SIGTRAP:02x}")
+                } else {
+                    "T05replaylog:begin;".into()
+                }
+            }
+            "c" => {
+                let frame = self.executor.reverse_continue();
+
+                if frame.mode == ExecutorMode::Breakpoint || self.interrupted.load(Ordering::Relaxed) {
+                    return self.stop_reply(&frame)
+                }
+
+                "T05replaylog:begin;".into()
+            }
+            _ => return String::new()
+        };
+
+        self.last_stop = reply.clone();
+
+        reply
+    }
+
+    fn stop_reply(&mut self, frame: &DebugFrame) -> String {
+        let reply = match frame.mode {
+            ExecutorMode::Breakpoint => match frame.hit {
+                Some(Hit::Watchpoint(hit)) => self.watch_reply(&hit),
+                _ => "T05swbreak:;".into()
+            },
+            Invalid(error) => format!("S{:02x}", signal(error)),
+            LimitReached(_) => format!("S{SIGXCPU:02x}"),
+            ExecutorMode::Exited { code } => format!("W{:02x}", code as u8),
+            ExecutorMode::Halted => "W00".into(),
+            Paused | Running => {
+                let signal = if self.interrupted.load(Ordering::Relaxed) { SIGINT } else { SIGTRAP };
+
+                format!("S{signal:02x}")
+            }
+        };
+
+        self.last_stop = reply.clone();
+
+        reply
+    }
+
+    fn watch_reply(&self, hit: &WatchpointHit) -> String {
+        let WatchTarget::Memory { address, .. } = hit.target else {
+            return format!("S{SIGTRAP:02x}")
+        };
+
+        let kind = self.watchpoints.iter()
+            .find(|watch| watch.id == hit.id)
+            .map_or(WatchKind::Write, |watch| watch.kind);
+
+        let name = match kind {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        };
+
+        format!("T{SIGTRAP:02x}{name}:{address:x};")
+    }
+
+    fn breakpoint(&mut self, arguments: &str, insert: bool) -> Option<String> {
+        // Conditions and commands after ';' are evaluated by GDB itself.
+        let arguments = arguments.split(';').next()?;
+
+        let mut parts = arguments.split(',');
+        let (kind, address, length) = (parts.next()?, parse_number(parts.next()?)?, parse_number(parts.next()?)?);
+
+        let kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+
+                self.executor.set_breakpoints(self.breakpoints.clone());
+
+                return Some("OK".into())
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new())
+        };
+
+        if insert {
+            let id = self.executor.add_watchpoint(Watchpoint::Memory { address, length, kind });
+
+            self.watchpoints.push(MemoryWatch { address, length, kind, id });
+        } else {
+            let index = self.watchpoints.iter().position(|watch| {
+                watch.address == address && watch.length == length && watch.kind == kind
+            })?;
+
+            let watch = self.watchpoints.remove(index);
+
+            self.executor.remove_breakpoint(watch.id);
+        }
+
+        Some("OK".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::State;
+    use crate::debug::gdb::packet::encode;
+    use crate::execution::trackers::history::DEFAULT_BUDGET;
+    use super::*;
+
+    fn server() -> GdbServer<SectionMemory<DefaultResponder>> {
+        let state = State::new(0x400000, WatchedMemory::new(SectionMemory::new()));
+
+        GdbServer::new(Arc::new(Executor::new(state, HistoryTracker::new(DEFAULT_BUDGET))))
+    }
+
+    #[test]
+    fn non_ascii_packets_are_unsupported() {
+        let mut server = server();
+
+        assert_eq!(server.handle("\u{FFFD}0"), Some(String::new()));
+        assert_eq!(server.handle("m\u{FFFD},4"), Some("E01".into()));
+
+        // The same through the wire, a correctly checksummed packet starting with an invalid byte.
+        let mut output = vec![];
+        server.serve(std::io::Cursor::new(encode(&[0xFF, b'0'])), &mut output).unwrap();
+
+        assert_eq!(output, b"+$#00");
+    }
+}
@@ -0,0 +1,79 @@
+use crate::cpu::state::Registers;
+
+// GDB's numbering for 32-bit MIPS: r0-r31, status, lo, hi, badvaddr, cause, pc, f0-f31, fcsr, fir.
+pub const REGISTER_COUNT: usize = 72;
+
+const STATUS: usize = 32;
+const LO: usize = 33;
+const HI: usize = 34;
+const BAD_VADDR: usize = 35;
+const CAUSE: usize = 36;
+const PC: usize = 37;
+
+// None for registers Titan doesn't model (the FPU).
+pub fn read_register(registers: &Registers, index: usize) -> Option<u32> {
+    match index {
+        0 ..= 31 => Some(registers.line[index]),
+        LO => Some(registers.lo),
+        HI => Some(registers.hi),
+        PC => Some(registers.pc),
+        STATUS | BAD_VADDR | CAUSE => Some(0),
+        _ => None
+    }
+}
+
+// Returns false if the register can't be written.
+pub fn write_register(registers: &mut Registers, index: usize, value: u32) -> bool {
+    match index {
+        0 => { } // $zero ignores writes
+        1 ..= 31 => registers.line[index] = value,
+        LO => registers.lo = value,
+        HI => registers.hi = value,
+        PC => registers.pc = value,
+        STATUS | BAD_VADDR | CAUSE => { }
+        _ => return false
+    }
+
+    true
+}
+
+fn register(xml: &mut String, name: &str, regnum: usize, kind: &str) {
+    xml.push_str(&format!("<reg name=\"{name}\" bitsize=\"32\" regnum=\"{regnum}\" type=\"{kind}\"/>"))
+}
+
+// Description returned for qXfer:features:read:target.xml.
+pub fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+        <target version=\"1.0\"><architecture>mips</architecture>"
+    );
+
+    xml.push_str("<feature name=\"org.gnu.gdb.mips.cpu\">");
+
+    for index in 0 .. 32 {
+        register(&mut xml, &format!("r{index}"), index, "int");
+    }
+
+    register(&mut xml, "lo", LO, "int");
+    register(&mut xml, "hi", HI, "int");
+    register(&mut xml, "pc", PC, "code_ptr");
+
+    xml.push_str("</feature><feature name=\"org.gnu.gdb.mips.cp0\">");
+
+    register(&mut xml, "status", STATUS, "int");
+    register(&mut xml, "badvaddr", BAD_VADDR, "data_ptr");
+    register(&mut xml, "cause", CAUSE, "int");
+
+    xml.push_str("</feature><feature name=\"org.gnu.gdb.mips.fpu\">");
+
+    for index in 0 .. 32 {
+        register(&mut xml, &format!("f{index}"), PC + 1 + index, "ieee_single");
+    }
+
+    register(&mut xml, "fcsr", PC + 33, "int");
+    register(&mut xml, "fir", PC + 34, "int");
+
+    xml.push_str("</feature></target>");
+
+    xml
+}
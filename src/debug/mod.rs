@@ -0,0 +1,2 @@
+pub mod dap;
+pub mod gdb;
@@ -0,0 +1,590 @@
+use std::collections::{HashMap, HashSet};
+use num_derive::{ToPrimitive, FromPrimitive};
+use crate::cpu::error::{Error, ErrorKind};
+use crate::cpu::memory::Width;
+use crate::cpu::state::Registers;
+use crate::cpu::{Memory, State};
+use crate::execution::expression::Expression;
+use crate::unit::analysis::{InstructionClass, MemoryAccess};
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::unit::register::RegisterId;
+use crate::unit::register::RegisterName::V0;
+
+pub type BreakpointId = usize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ToPrimitive, FromPrimitive)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConditionValue {
+    Register(RegisterId),
+    Memory { address: u32, width: Width },
+    Expression(Expression),
+}
+
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub value: ConditionValue,
+    pub comparison: Comparison,
+    pub operand: u32,
+    pub signed: bool,
+}
+
+impl Condition {
+    pub fn register(register: RegisterId, comparison: Comparison, operand: u32) -> Condition {
+        Condition { value: ConditionValue::Register(register), comparison, operand, signed: false }
+    }
+
+    pub fn memory(address: u32, width: Width, comparison: Comparison, operand: u32) -> Condition {
+        Condition { value: ConditionValue::Memory { address, width }, comparison, operand, signed: false }
+    }
+
+    // Stops when the expression is non-zero, ex. "$t0 > 5 && byte[msg] == 'a'".
+    pub fn expression(expression: Expression) -> Condition {
+        Condition { value: ConditionValue::Expression(expression), comparison: Comparison::NotEqual, operand: 0, signed: false }
+    }
+
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+
+        self
+    }
+
+    fn compare<T: Ord>(&self, a: T, b: T) -> bool {
+        match self.comparison {
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::Less => a < b,
+            Comparison::LessEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterEqual => a >= b,
+        }
+    }
+
+    // Unmapped or misaligned memory (or an expression that fails, ex. dividing by zero) never satisfies a condition.
+    pub fn evaluate<Mem: Memory>(&self, state: &State<Mem>) -> bool {
+        let value = match &self.value {
+            ConditionValue::Register(register) => register.get(&state.registers),
+            ConditionValue::Memory { address, width } => {
+                let Ok(value) = width.read(&state.memory, *address) else { return false };
+
+                value
+            }
+            ConditionValue::Expression(expression) => {
+                let Ok(value) = expression.evaluate(state) else { return false };
+
+                value
+            }
+        };
+
+        if self.signed {
+            self.compare(value as i32, self.operand as i32)
+        } else {
+            self.compare(value, self.operand)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub address: u32,
+    pub condition: Option<Condition>,
+    pub hit_count: Option<u64>, // only stop once the breakpoint has been hit this many times
+    pub log: Option<String>, // logpoint message, logged instead of stopping
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn new(address: u32) -> Breakpoint {
+        Breakpoint {
+            address,
+            condition: None,
+            hit_count: None,
+            log: None,
+            hits: 0
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+
+        self
+    }
+
+    pub fn with_hit_count(mut self, count: u64) -> Self {
+        self.hit_count = Some(count);
+
+        self
+    }
+
+    pub fn with_log(mut self, message: String) -> Self {
+        self.log = Some(message);
+
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Catchpoint {
+    Syscall(Option<u32>), // $v0 value, or any syscall
+    Trap,
+    Error(ErrorKind),
+    Mnemonic(String), // ex. "jal"
+    Class(InstructionClass),
+}
+
+impl Catchpoint {
+    fn is_instruction_check(&self) -> bool {
+        matches!(self, Catchpoint::Syscall(_) | Catchpoint::Mnemonic(_) | Catchpoint::Class(_))
+    }
+
+    fn matches_instruction(&self, instruction: &Instruction, registers: &Registers) -> bool {
+        match self {
+            Catchpoint::Syscall(number) => {
+                *instruction == Instruction::Syscall
+                    && number.is_none_or(|number| registers.get(V0) == number)
+            }
+            Catchpoint::Mnemonic(name) => instruction.name() == name,
+            Catchpoint::Class(class) => instruction.class() == *class,
+            _ => false
+        }
+    }
+
+    fn matches_error(&self, error: Error) -> bool {
+        match self {
+            Catchpoint::Trap => error == Error::CpuTrap,
+            Catchpoint::Error(kind) => error.kind() == *kind,
+            _ => false
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ToPrimitive, FromPrimitive)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // read or write
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory { address: u32, length: u32, kind: WatchKind },
+    Register(RegisterId), // stops when the value changes
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchTarget {
+    Memory { address: u32, width: Width, store: bool },
+    Register(RegisterId),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub id: BreakpointId,
+    pub pc: u32, // instruction that triggered the watchpoint
+    pub instruction: u32,
+    pub target: WatchTarget,
+    pub old: u32,
+    pub new: u32, // same as old for reads
+}
+
+// Captured before an instruction runs, when watchpoints are present.
+pub struct WatchContext {
+    pc: u32,
+    instruction: u32,
+    registers: Registers,
+    access: Option<MemoryAccess>,
+    previous: Option<u32>,
+    watched: Vec<(BreakpointId, Vec<Option<u8>>)>, // bytes under write watchpoints, before a syscall
+}
+
+impl Watchpoint {
+    fn matches_access(&self, access: &MemoryAccess) -> bool {
+        match *self {
+            Watchpoint::Memory { address, length, kind } => {
+                let kind_matches = match kind {
+                    WatchKind::Read => !access.store,
+                    WatchKind::Write => access.store,
+                    WatchKind::Access => true,
+                };
+
+                kind_matches && access.overlaps(address, length)
+            }
+            Watchpoint::Register(_) => false
+        }
+    }
+
+    fn watches_writes(&self) -> bool {
+        matches!(self, Watchpoint::Memory { kind: WatchKind::Write | WatchKind::Access, .. })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Hit {
+    Breakpoint(BreakpointId),
+    Catchpoint(BreakpointId),
+    Watchpoint(WatchpointHit),
+}
+
+// Replaces "{$reg}" with the decimal value of the register, or "{$reg:x}" with hex.
+pub fn format_log(message: &str, registers: &Registers) -> String {
+    let mut result = String::new();
+    let mut input = message;
+
+    while let Some(start) = input.find('{') {
+        result.push_str(&input[..start]);
+
+        let rest = &input[start + 1..];
+        let Some(end) = rest.find('}') else {
+            input = &input[start..];
+
+            break
+        };
+
+        let (name, hex) = match rest[..end].strip_suffix(":x") {
+            Some(name) => (name, true),
+            None => (&rest[..end], false),
+        };
+
+        match RegisterId::from_name(name.trim()) {
+            Some(register) if hex => result.push_str(&format!("0x{:08x}", register.get(registers))),
+            Some(register) => result.push_str(&format!("{}", register.get(registers))),
+            None => result.push_str(&input[start..start + end + 2]),
+        }
+
+        input = &rest[end + 1..];
+    }
+
+    result.push_str(input);
+
+    result
+}
+
+#[derive(Clone, Debug)]
+pub enum Point {
+    Breakpoint(Breakpoint),
+    Catchpoint(Catchpoint),
+    Watchpoint(Watchpoint),
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    next_id: BreakpointId,
+    addresses: HashMap<u32, Vec<(BreakpointId, Breakpoint)>>,
+    catchpoints: Vec<(BreakpointId, Catchpoint)>,
+    watchpoints: Vec<(BreakpointId, Watchpoint)>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Self::default()
+    }
+
+    fn allocate_id(&mut self) -> BreakpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        id
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = self.allocate_id();
+
+        self.addresses.entry(breakpoint.address).or_default().push((id, breakpoint));
+
+        id
+    }
+
+    pub fn add_catchpoint(&mut self, catchpoint: Catchpoint) -> BreakpointId {
+        let id = self.allocate_id();
+
+        self.catchpoints.push((id, catchpoint));
+
+        id
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> BreakpointId {
+        let id = self.allocate_id();
+
+        self.watchpoints.push((id, watchpoint));
+
+        id
+    }
+
+    // Removes a breakpoint, catchpoint or watchpoint.
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let points = self.catchpoints.len() + self.watchpoints.len();
+        self.catchpoints.retain(|(other, _)| *other != id);
+        self.watchpoints.retain(|(other, _)| *other != id);
+
+        if points != self.catchpoints.len() + self.watchpoints.len() {
+            return true
+        }
+
+        let mut removed = false;
+
+        for list in self.addresses.values_mut() {
+            if let Some(index) = list.iter().position(|(other, _)| *other == id) {
+                list.remove(index);
+                removed = true;
+
+                break
+            }
+        }
+
+        self.addresses.retain(|_, list| !list.is_empty());
+
+        removed
+    }
+
+    // Replaces every address breakpoint with an unconditional one, keeping catchpoints.
+    pub fn set_addresses(&mut self, addresses: HashSet<u32>) {
+        self.addresses.clear();
+
+        for address in addresses {
+            self.add(Breakpoint::new(address));
+        }
+    }
+
+    // Moves address breakpoints, ex. after a hot patch. Those that map to None stay put and are returned.
+    pub fn remap<F: Fn(u32) -> Option<u32>>(&mut self, map: F) -> Vec<(BreakpointId, u32)> {
+        let mut lost = vec![];
+
+        for (address, list) in std::mem::take(&mut self.addresses) {
+            let target = map(address).unwrap_or_else(|| {
+                lost.extend(list.iter().map(|(id, _)| (*id, address)));
+
+                address
+            });
+
+            for (id, mut breakpoint) in list {
+                breakpoint.address = target;
+
+                self.addresses.entry(target).or_default().push((id, breakpoint));
+            }
+        }
+
+        lost
+    }
+
+    // Every breakpoint, catchpoint and watchpoint in the order they were added, ex. for a save state.
+    pub fn points(&self) -> Vec<(BreakpointId, Point)> {
+        let mut result: Vec<(BreakpointId, Point)> = self.breakpoints()
+            .map(|(id, breakpoint)| (id, Point::Breakpoint(breakpoint.clone())))
+            .chain(self.catchpoints.iter().map(|(id, catchpoint)| (*id, Point::Catchpoint(catchpoint.clone()))))
+            .chain(self.watchpoints.iter().map(|(id, watchpoint)| (*id, Point::Watchpoint(*watchpoint))))
+            .collect();
+
+        result.sort_by_key(|(id, _)| *id);
+
+        result
+    }
+
+    // Replaces everything with points, keeping their ids. New points get ids after next_id.
+    pub fn restore(&mut self, next_id: BreakpointId, points: Vec<(BreakpointId, Point)>) {
+        self.clear();
+
+        self.next_id = points.iter()
+            .map(|(id, _)| id + 1)
+            .fold(next_id, BreakpointId::max);
+
+        for (id, point) in points {
+            match point {
+                Point::Breakpoint(breakpoint) => self.addresses.entry(breakpoint.address).or_default().push((id, breakpoint)),
+                Point::Catchpoint(catchpoint) => self.catchpoints.push((id, catchpoint)),
+                Point::Watchpoint(watchpoint) => self.watchpoints.push((id, watchpoint)),
+            }
+        }
+    }
+
+    pub fn next_id(&self) -> BreakpointId {
+        self.next_id
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+        self.catchpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.addresses.contains_key(&address)
+    }
+
+    pub fn get(&self, id: BreakpointId) -> Option<&Breakpoint> {
+        self.addresses.values()
+            .flat_map(|list| list.iter())
+            .find(|(other, _)| *other == id)
+            .map(|(_, breakpoint)| breakpoint)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item=(BreakpointId, &Breakpoint)> {
+        self.addresses.values()
+            .flat_map(|list| list.iter())
+            .map(|(id, breakpoint)| (*id, breakpoint))
+    }
+
+    pub fn catchpoints(&self) -> &[(BreakpointId, Catchpoint)] {
+        &self.catchpoints
+    }
+
+    pub fn watchpoints(&self) -> &[(BreakpointId, Watchpoint)] {
+        &self.watchpoints
+    }
+
+    pub fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    // Run before the instruction at pc executes. Logpoint messages are appended to logs.
+    pub fn check<Mem: Memory>(&mut self, state: &State<Mem>, logs: &mut Vec<String>) -> Option<Hit> {
+        let pc = state.registers.pc;
+        let mut hit = None;
+
+        if let Some(list) = self.addresses.get_mut(&pc) {
+            for (id, breakpoint) in list {
+                if breakpoint.condition.as_ref().is_some_and(|c| !c.evaluate(state)) {
+                    continue
+                }
+
+                breakpoint.hits += 1;
+
+                if breakpoint.hit_count.is_some_and(|count| breakpoint.hits < count) {
+                    continue
+                }
+
+                if let Some(message) = &breakpoint.log {
+                    logs.push(format_log(message, &state.registers))
+                } else if hit.is_none() {
+                    hit = Some(Hit::Breakpoint(*id))
+                }
+            }
+        }
+
+        if hit.is_some() || !self.catchpoints.iter().any(|(_, c)| c.is_instruction_check()) {
+            return hit
+        }
+
+        let instruction = state.memory.get_u32(pc).ok()
+            .and_then(|value| InstructionDecoder::decode(pc, value))?;
+
+        self.catchpoints.iter()
+            .find(|(_, catchpoint)| catchpoint.matches_instruction(&instruction, &state.registers))
+            .map(|(id, _)| Hit::Catchpoint(*id))
+    }
+
+    // Like check, but without counting hits or logging. Used when moving backwards in time.
+    pub fn matches<Mem: Memory>(&self, state: &State<Mem>) -> Option<Hit> {
+        self.addresses.get(&state.registers.pc)?.iter()
+            .find(|(_, breakpoint)| {
+                breakpoint.log.is_none()
+                    && breakpoint.condition.as_ref().is_none_or(|c| c.evaluate(state))
+            })
+            .map(|(id, _)| Hit::Breakpoint(*id))
+    }
+
+    // Run after an instruction failed with error.
+    pub fn check_error(&self, error: Error) -> Option<Hit> {
+        self.catchpoints.iter()
+            .find(|(_, catchpoint)| catchpoint.matches_error(error))
+            .map(|(id, _)| Hit::Catchpoint(*id))
+    }
+
+    // Run before the instruction at pc executes, only needed when there are watchpoints.
+    pub fn prepare_watch<Mem: Memory>(&self, state: &State<Mem>) -> WatchContext {
+        let pc = state.registers.pc;
+        let instruction = state.memory.get_u32(pc).unwrap_or(0);
+        let decoded = InstructionDecoder::decode(pc, instruction);
+
+        let access = decoded.as_ref()
+            .and_then(|decoded| decoded.memory_access(&state.registers))
+            .filter(|access| self.watchpoints.iter().any(|(_, w)| w.matches_access(access)));
+
+        let previous = access.and_then(|access| access.width.read(&state.memory, access.address).ok());
+
+        // Syscall handlers write memory without a store instruction (ex. read string), so the watched bytes are kept.
+        let watched = if decoded == Some(Instruction::Syscall) {
+            self.watchpoints.iter()
+                .filter_map(|(id, watchpoint)| match watchpoint {
+                    Watchpoint::Memory { address, length, .. } if watchpoint.watches_writes() => {
+                        let bytes = (0 .. *length)
+                            .map(|offset| state.memory.get(address.wrapping_add(offset)).ok())
+                            .collect();
+
+                        Some((*id, bytes))
+                    }
+                    _ => None
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        WatchContext { pc, instruction, registers: state.registers, access, previous, watched }
+    }
+
+    // Run after the instruction from prepare_watch executed successfully,
+    // or for a syscall, once its handler is done (see Executor::syscall_handled).
+    // Syscalls only trigger register and write watchpoints, reads by the handler (ex. print string) are not seen.
+    pub fn check_watch<Mem: Memory>(&self, context: &WatchContext, state: &State<Mem>) -> Option<Hit> {
+        for (id, watchpoint) in &self.watchpoints {
+            let hit = |target: WatchTarget, old: u32, new: u32| WatchpointHit {
+                id: *id, pc: context.pc, instruction: context.instruction, target, old, new
+            };
+
+            match watchpoint {
+                Watchpoint::Register(register) => {
+                    let (old, new) = (register.get(&context.registers), register.get(&state.registers));
+
+                    if old != new {
+                        return Some(Hit::Watchpoint(hit(WatchTarget::Register(*register), old, new)))
+                    }
+                }
+                Watchpoint::Memory { address, .. } => {
+                    if let Some((_, bytes)) = context.watched.iter().find(|(other, _)| other == id) {
+                        let changed = bytes.iter().enumerate().find_map(|(offset, old)| {
+                            let address = address.wrapping_add(offset as u32);
+                            let new = state.memory.get(address).ok();
+
+                            (new != *old).then(|| (address, old.unwrap_or(0), new.unwrap_or(0)))
+                        });
+
+                        if let Some((address, old, new)) = changed {
+                            let target = WatchTarget::Memory { address, width: Width::Byte, store: true };
+
+                            return Some(Hit::Watchpoint(hit(target, old as u32, new as u32)))
+                        }
+
+                        continue
+                    }
+
+                    let Some(access) = context.access.filter(|access| watchpoint.matches_access(access)) else {
+                        continue
+                    };
+
+                    let old = context.previous.unwrap_or(0);
+                    let new = if access.store {
+                        access.width.read(&state.memory, access.address).unwrap_or(old)
+                    } else {
+                        old
+                    };
+
+                    let target = WatchTarget::Memory {
+                        address: access.address, width: access.width, store: access.store
+                    };
+
+                    return Some(Hit::Watchpoint(hit(target, old, new)))
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::{Condvar, Mutex};
+use crate::cpu::error::Error;
+use crate::cpu::memory::effect::{Effects, SideEffect};
+use crate::cpu::{Memory, State};
+use crate::execution::trackers::Tracker;
+use crate::execution::Executor;
+use crate::execution::limits::Limit;
+use crate::unit::register::RegisterName::{A0, A1, V0};
+
+// Longest string a print string syscall reads while looking for the NUL.
+const MAX_STRING_LENGTH: u32 = 0x10000;
+
+// What Console::syscall did with the syscall in $v0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsoleSyscall {
+    Handled,
+    NeedsInput, // a read with no input (or no full line) yet, nothing was consumed
+    Unsupported(u32),
+    Fault(Error), // ex. print string from unmapped memory
+    LimitReached(Limit), // handled, but the output went over a limit, the run should stop
+}
+
+#[derive(Default)]
+struct ConsoleState {
+    input: Vec<u8>,
+    cursor: usize, // input[..cursor] was consumed by the program
+    output: Vec<u8>,
+    effects: Effects, // not yet handed to history
+}
+
+enum ConsoleEdit {
+    Read(usize),
+    Write(Vec<u8>),
+}
+
+struct ConsoleEffect {
+    state: Arc<Mutex<ConsoleState>>,
+    edit: ConsoleEdit,
+}
+
+impl SideEffect for ConsoleEffect {
+    fn undo(&mut self) {
+        let mut state = self.state.lock();
+
+        match &self.edit {
+            ConsoleEdit::Read(count) => state.cursor -= count,
+            ConsoleEdit::Write(bytes) => {
+                let length = state.output.len() - bytes.len();
+
+                state.output.truncate(length)
+            }
+        }
+    }
+
+    fn redo(&mut self) {
+        let mut state = self.state.lock();
+
+        match &self.edit {
+            ConsoleEdit::Read(count) => state.cursor += count,
+            ConsoleEdit::Write(bytes) => state.output.extend_from_slice(bytes)
+        }
+    }
+}
+
+// Program input and output that rewinds with history.
+// Reads and writes are recorded as side effects, Console::syscall passes them on with Executor::record_effect.
+#[derive(Clone, Default)]
+pub struct Console {
+    state: Arc<Mutex<ConsoleState>>,
+    input_pushed: Arc<Condvar>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Self::default()
+    }
+
+    // Input typed by the user. This is not a side effect, it stays when stepping back.
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.state.lock().input.extend_from_slice(bytes);
+        self.input_pushed.notify_all();
+    }
+
+    // Blocks until push_input is called or timeout passes, ex. while a read syscall waits on the user.
+    pub fn wait_for_input(&self, timeout: Duration) {
+        let mut state = self.state.lock();
+
+        self.input_pushed.wait_for(&mut state, timeout);
+    }
+
+    // Input that has not been read yet.
+    pub fn pending_input(&self) -> Vec<u8> {
+        let state = self.state.lock();
+
+        state.input[state.cursor..].to_vec()
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.state.lock().output.clone()
+    }
+
+    pub fn output_length(&self) -> usize {
+        self.state.lock().output.len()
+    }
+
+    // Output written after the first start bytes, ex. what the last syscall printed.
+    pub fn output_since(&self, start: usize) -> Vec<u8> {
+        self.state.lock().output.get(start..).unwrap_or_default().to_vec()
+    }
+
+    fn record(&self, state: &mut ConsoleState, edit: ConsoleEdit) {
+        state.effects.push(Box::new(ConsoleEffect { state: self.state.clone(), edit }))
+    }
+
+    // Reads up to count bytes of input.
+    pub fn read(&self, count: usize) -> Vec<u8> {
+        let mut state = self.state.lock();
+
+        let end = state.input.len().min(state.cursor + count);
+        let bytes = state.input[state.cursor..end].to_vec();
+
+        if !bytes.is_empty() {
+            state.cursor = end;
+
+            self.record(&mut state, ConsoleEdit::Read(bytes.len()));
+        }
+
+        bytes
+    }
+
+    pub fn read_byte(&self) -> Option<u8> {
+        self.read(1).first().copied()
+    }
+
+    fn line_length(&self) -> Option<usize> {
+        let state = self.state.lock();
+
+        state.input[state.cursor..].iter().position(|byte| *byte == b'\n').map(|index| index + 1)
+    }
+
+    // Reads up to and including the next newline, None if no full line is available.
+    pub fn read_line(&self) -> Option<Vec<u8>> {
+        let length = self.line_length()?;
+
+        Some(self.read(length))
+    }
+
+    pub fn has_input(&self) -> bool {
+        let state = self.state.lock();
+
+        state.cursor < state.input.len()
+    }
+
+    pub fn write(&self, bytes: &[u8]) {
+        let mut state = self.state.lock();
+
+        state.output.extend_from_slice(bytes);
+
+        self.record(&mut state, ConsoleEdit::Write(bytes.to_vec()));
+    }
+
+    // Input, how much of it was read, and output, ex. for a save state.
+    pub fn contents(&self) -> (Vec<u8>, usize, Vec<u8>) {
+        let state = self.state.lock();
+
+        (state.input.clone(), state.cursor, state.output.clone())
+    }
+
+    // Replaces the contents, pending side effects are dropped.
+    pub fn restore(&self, input: Vec<u8>, cursor: usize, output: Vec<u8>) {
+        let mut state = self.state.lock();
+
+        state.cursor = cursor.min(input.len());
+        state.input = input;
+        state.output = output;
+        state.effects.clear();
+    }
+
+    // Side effects since the last call.
+    pub fn take_effects(&self) -> Effects {
+        std::mem::take(&mut self.state.lock().effects)
+    }
+}
+
+fn read_int(line: &[u8]) -> u32 {
+    // Like MARS, but text that is not a number reads as 0 instead of faulting.
+    String::from_utf8_lossy(line).trim().parse::<i32>().unwrap_or(0) as u32
+}
+
+impl Console {
+    // Runs the print and read syscalls (MARS numbering: 1, 4, 5, 8, 11, 12, 34, 35, 36) against the console.
+    // Printed bytes go through Executor::record_output, and reads and writes are recorded with
+    // Executor::record_effect so stepping back undoes them. Call Executor::syscall_handled after Handled or LimitReached,
+    // the executor then stays in LimitReached.
+    pub fn syscall<Mem: Memory, Track: Tracker<Mem>>(&self, executor: &Executor<Mem, Track>) -> ConsoleSyscall {
+        let (result, output) = executor.with_state(|state| self.run_syscall(state));
+
+        for effect in self.take_effects() {
+            executor.record_effect(effect)
+        }
+
+        if !output.is_empty() {
+            if let Some(limit) = executor.record_output(&output) {
+                return ConsoleSyscall::LimitReached(limit)
+            }
+        }
+
+        result
+    }
+
+    fn run_syscall<Mem: Memory>(&self, state: &mut State<Mem>) -> (ConsoleSyscall, Vec<u8>) {
+        let (v0, a0, a1) = (state.registers.get(V0), state.registers.get(A0), state.registers.get(A1));
+
+        let output = match v0 {
+            1 => (a0 as i32).to_string().into_bytes(),
+            4 => {
+                let mut bytes = vec![];
+
+                for offset in 0 .. MAX_STRING_LENGTH {
+                    match state.memory.get(a0.wrapping_add(offset)) {
+                        Ok(0) => break,
+                        Ok(byte) => bytes.push(byte),
+                        Err(error) => return (ConsoleSyscall::Fault(error), vec![])
+                    }
+                }
+
+                bytes
+            }
+            11 => vec![a0 as u8],
+            34 => format!("0x{a0:08x}").into_bytes(),
+            35 => format!("{a0:032b}").into_bytes(),
+            36 => a0.to_string().into_bytes(),
+            5 => {
+                let Some(line) = self.read_line() else { return (ConsoleSyscall::NeedsInput, vec![]) };
+
+                state.registers.set(V0, read_int(&line));
+
+                vec![]
+            }
+            8 => {
+                // Reads a line into the a1 byte buffer at a0, keeping the newline if it fits, NUL terminated.
+                let Some(length) = self.line_length() else { return (ConsoleSyscall::NeedsInput, vec![]) };
+
+                let line = self.pending_input();
+                let kept = (a1 as usize).saturating_sub(1).min(length);
+
+                let bytes = line[.. kept].iter().copied().chain([0]);
+
+                for (offset, byte) in bytes.enumerate().take(a1 as usize) {
+                    if let Err(error) = state.memory.set(a0.wrapping_add(offset as u32), byte) {
+                        return (ConsoleSyscall::Fault(error), vec![])
+                    }
+                }
+
+                self.read(length);
+
+                vec![]
+            }
+            12 => {
+                let Some(byte) = self.read_byte() else { return (ConsoleSyscall::NeedsInput, vec![]) };
+
+                state.registers.set(V0, byte as u32);
+
+                vec![]
+            }
+            _ => return (ConsoleSyscall::Unsupported(v0), vec![])
+        };
+
+        if !output.is_empty() {
+            self.write(&output)
+        }
+
+        (ConsoleSyscall::Handled, output)
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::assembler::binary::{Binary, RegionFlags};
+use crate::cpu::memory::section::SECTION_SIZE;
+use crate::cpu::memory::{PageKind, Paged};
+use crate::cpu::{Memory, State};
+use crate::unit::register::{RegisterId, RegisterName};
+
+// Bytes shown per change by Display, the rest is elided.
+const DISPLAY_BYTES: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: RegisterId,
+    pub old: u32,
+    pub new: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub address: u32,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+    pub label: Option<(String, u32)>, // data label the range starts in, and the offset from it
+}
+
+impl MemoryChange {
+    pub fn end(&self) -> u32 {
+        self.address.wrapping_add(self.new.len() as u32)
+    }
+}
+
+// What changed between two states. Pages mapped in only one of them (ex. a region mounted in between)
+// and device pages can't be compared, and are left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryChange>,
+}
+
+fn page_bytes(kind: &PageKind) -> Option<Vec<u8>> {
+    match kind {
+        PageKind::Data(data) => Some(data.clone()),
+        PageKind::Filled(value) => Some(vec![*value; SECTION_SIZE]),
+        PageKind::Device => None
+    }
+}
+
+impl StateDiff {
+    pub fn new<Mem: Memory + Paged>(before: &State<Mem>, after: &State<Mem>) -> StateDiff {
+        let ids = [RegisterId::Pc].into_iter()
+            .chain((1 .. 32u8).map(|index| RegisterId::Line(RegisterName::from(index))))
+            .chain([RegisterId::Hi, RegisterId::Lo]);
+
+        let registers = ids
+            .filter_map(|register| {
+                let (old, new) = (register.get(&before.registers), register.get(&after.registers));
+
+                (old != new).then_some(RegisterChange { register, old, new })
+            })
+            .collect();
+
+        let old_pages: HashMap<u32, PageKind> = before.memory.pages().into_iter()
+            .map(|page| (page.address, page.kind))
+            .collect();
+
+        let mut new_pages = after.memory.pages();
+        new_pages.sort_by_key(|page| page.address);
+
+        let mut memory: Vec<MemoryChange> = vec![];
+
+        for page in new_pages {
+            let Some(old) = old_pages.get(&page.address).and_then(page_bytes) else { continue };
+            let Some(new) = page_bytes(&page.kind) else { continue };
+
+            let mut index = 0;
+
+            while index < new.len().min(old.len()) {
+                if old[index] == new[index] {
+                    index += 1;
+
+                    continue
+                }
+
+                let start = index;
+
+                while index < new.len().min(old.len()) && old[index] != new[index] {
+                    index += 1
+                }
+
+                let address = page.address.wrapping_add(start as u32);
+
+                // Ranges that run across a page boundary are joined.
+                if let Some(last) = memory.last_mut().filter(|last| last.end() == address) {
+                    last.old.extend_from_slice(&old[start .. index]);
+                    last.new.extend_from_slice(&new[start .. index]);
+
+                    continue
+                }
+
+                memory.push(MemoryChange {
+                    address,
+                    old: old[start .. index].to_vec(),
+                    new: new[start .. index].to_vec(),
+                    label: None
+                })
+            }
+        }
+
+        StateDiff { registers, memory }
+    }
+
+    // Splits memory changes where a data label starts, and names each range after the label it is in.
+    // Addresses outside the data regions of binary (ex. the stack) keep no label.
+    pub fn with_labels(mut self, binary: &Binary) -> Self {
+        let regions: Vec<(u32, u32)> = binary.regions.iter()
+            .filter(|region| !region.flags.contains(RegionFlags::EXECUTABLE))
+            .map(|region| (region.address, region.wrapping_pc()))
+            .collect();
+
+        let mut labels: Vec<(u32, &str)> = binary.labels.iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .filter(|(address, _)| regions.iter().any(|(start, end)| address >= start && address < end))
+            .collect();
+
+        labels.sort();
+
+        let label_for = |address: u32| -> Option<(String, u32)> {
+            let (start, end) = regions.iter().find(|(start, end)| address >= *start && address < *end)?;
+            let index = labels.partition_point(|(label, _)| *label <= address).checked_sub(1)?;
+            let (label, name) = labels[index];
+
+            (label >= *start && label < *end).then(|| (name.to_string(), address - label))
+        };
+
+        let mut memory = vec![];
+
+        for change in self.memory {
+            let mut start = 0;
+
+            // Offsets within the change where a new label begins.
+            let mut splits: Vec<usize> = labels.iter()
+                .filter(|(label, _)| *label > change.address && *label < change.end())
+                .map(|(label, _)| (label - change.address) as usize)
+                .collect();
+
+            splits.dedup();
+            splits.push(change.new.len());
+
+            for end in splits {
+                let address = change.address.wrapping_add(start as u32);
+
+                memory.push(MemoryChange {
+                    address,
+                    old: change.old[start .. end].to_vec(),
+                    new: change.new[start .. end].to_vec(),
+                    label: label_for(address)
+                });
+
+                start = end
+            }
+        }
+
+        self.memory = memory;
+
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+
+    pub fn register(&self, register: RegisterId) -> Option<&RegisterChange> {
+        self.registers.iter().find(|change| change.register == register)
+    }
+
+    // Every changed byte as (address, old, new).
+    pub fn bytes(&self) -> impl Iterator<Item=(u32, u8, u8)> + '_ {
+        self.memory.iter().flat_map(|change| {
+            change.old.iter().zip(&change.new)
+                .enumerate()
+                .map(|(offset, (old, new))| (change.address.wrapping_add(offset as u32), *old, *new))
+        })
+    }
+
+    // Whether every changed byte falls inside one of the ranges, given as (address, length).
+    pub fn memory_within(&self, ranges: &[(u32, u32)]) -> bool {
+        self.bytes().all(|(address, _, _)| {
+            ranges.iter().any(|(start, length)| address >= *start && address - start < *length)
+        })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut result: Vec<String> = bytes.iter()
+        .take(DISPLAY_BYTES)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    if bytes.len() > DISPLAY_BYTES {
+        result.push(format!("... ({} bytes)", bytes.len()))
+    }
+
+    result.join(" ")
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.registers {
+            writeln!(f, "{}: 0x{:08x} -> 0x{:08x}", change.register, change.old, change.new)?
+        }
+
+        for change in &self.memory {
+            match &change.label {
+                Some((name, 0)) => write!(f, "{name} (0x{:08x})", change.address)?,
+                Some((name, offset)) => write!(f, "{name}+0x{offset:x} (0x{:08x})", change.address)?,
+                None => write!(f, "0x{:08x}", change.address)?
+            }
+
+            writeln!(f, ": {} -> {}", hex(&change.old), hex(&change.new))?
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::UnitDevice;
+    use super::*;
+
+    const SOURCE: &str = "
+        .data
+        first: .word 0
+        second: .word 0
+        buffer: .space 8
+        after: .word 7
+        .text
+        fill:
+            sw $a1, 0($a0)
+            sw $a1, 4($a0)
+            jr $ra
+    ";
+
+    fn device() -> (UnitDevice, Binary) {
+        let binary = assemble_from(SOURCE).unwrap();
+
+        (UnitDevice::new(binary.clone()), binary)
+    }
+
+    #[test]
+    fn ranges_join_across_pages() {
+        let (device, _) = device();
+        let before = device.snapshot();
+
+        // The heap runs across the page at 0x7ff00000.
+        device.set(RegisterName::T0, 5);
+        device.set_data(0x7fef_fffe, vec![1, 2, 3, 4]).unwrap();
+        device.set_data(0x7ff0_0010, vec![5, 0, 6]).unwrap();
+
+        let diff = device.diff(&before);
+
+        assert_eq!(diff.registers, vec![RegisterChange { register: RegisterId::Line(RegisterName::T0), old: 0, new: 5 }]);
+        assert_eq!(diff.memory, vec![
+            MemoryChange { address: 0x7fef_fffe, old: vec![0; 4], new: vec![1, 2, 3, 4], label: None },
+            MemoryChange { address: 0x7ff0_0010, old: vec![0], new: vec![5], label: None },
+            MemoryChange { address: 0x7ff0_0012, old: vec![0], new: vec![6], label: None },
+        ]);
+
+        device.restore(before.clone());
+        assert!(device.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn labels_split_ranges() {
+        let (device, binary) = device();
+        let before = device.snapshot();
+        let first = binary.labels["first"];
+
+        device.set_data(first + 2, vec![0xff; 4]).unwrap();
+
+        let diff = device.diff(&before);
+
+        assert_eq!(diff.memory, vec![
+            MemoryChange { address: first + 2, old: vec![0; 2], new: vec![0xff; 2], label: Some(("first".to_string(), 2)) },
+            MemoryChange { address: first + 4, old: vec![0; 2], new: vec![0xff; 2], label: Some(("second".to_string(), 0)) },
+        ]);
+
+        assert_eq!(diff.to_string(), format!(
+            "first+0x2 (0x{:08x}): 00 00 -> ff ff\nsecond (0x{:08x}): 00 00 -> ff ff\n", first + 2, first + 4
+        ));
+
+        // Without labels the write stays in one piece.
+        let raw = device.executor.with_state(|state| StateDiff::new(&before, state));
+        assert_eq!(raw.memory.len(), 1);
+        assert_eq!(raw.memory[0].label, None);
+    }
+
+    #[test]
+    fn memory_within_checks_what_a_call_wrote() {
+        let (device, binary) = device();
+        let buffer = binary.labels["buffer"];
+        let before = device.snapshot();
+
+        device.call("fill", [buffer, 0x01020304], None).unwrap();
+
+        let diff = device.diff(&before);
+
+        assert_eq!(diff.bytes().count(), 8);
+        assert!(diff.memory_within(&[(buffer, 8)]));
+        assert!(diff.memory_within(&[(buffer, 4), (buffer + 4, 4)]));
+        assert!(!diff.memory_within(&[(buffer, 4)]));
+        assert!(!diff.memory_within(&[]));
+        assert_eq!(diff.register(RegisterId::Line(RegisterName::A0)).map(|change| change.new), Some(buffer));
+        assert_eq!(device.get_data(binary.labels["after"], 4).unwrap(), vec![7, 0, 0, 0]);
+    }
+}
@@ -30,8 +30,17 @@ pub fn create_simple_state<T: ListenResponder>(
 
     memory.mount(heap);
 
-    let mut state = State::new(elf.header.program_entry, memory);
+    let entry = elf.header.program_entry;
+
+    // Returning from main lands right after the text, where Termination can halt the program.
+    let text_end = elf.program_headers.iter()
+        .map(|header| (header.virtual_address, header.virtual_address.wrapping_add(header.data.len() as u32)))
+        .find(|(start, end)| entry >= *start && entry < *end)
+        .map_or(0, |(_, end)| end);
+
+    let mut state = State::new(entry, memory);
     state.registers.line[29] = heap_end;
+    state.registers.line[31] = text_end;
 
     state
 }
@@ -0,0 +1,157 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use smallvec::SmallVec;
+use crate::cpu::error::Error;
+use crate::cpu::state::Registers;
+use crate::execution::breakpoints::{BreakpointId, Hit, WatchpointHit};
+use crate::execution::executor::ExecutorMode;
+use crate::execution::limits::Limit;
+use crate::execution::trackers::history::{register_edits, RegisterEdit};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Started,
+    Paused,
+    Breakpoint(BreakpointId),
+    Catchpoint(BreakpointId),
+    Watchpoint(WatchpointHit),
+    LimitReached(Limit),
+    WaitingForInput, // a syscall handler is blocked on input
+    Output(Vec<u8>),
+    Exited(u32), // exit code
+    Halted,
+    Faulted(Error),
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub kind: EventKind,
+    pub registers: Registers,
+    pub changes: SmallVec<[RegisterEdit; 2]>, // since the previous stop, pc excluded
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+enum Subscriber {
+    Channel(Sender<Event>),
+    Callback(Box<dyn FnMut(&Event) + Send>),
+}
+
+pub struct Events {
+    subscribers: Vec<(SubscriptionId, Subscriber)>,
+    next_id: usize,
+    previous: Option<Registers>, // at the last stop, or the first event
+    running: bool, // a Started event was sent without a stop after it
+}
+
+impl EventKind {
+    // None for modes that are not a stop, including syscalls that the frontend still has to handle.
+    pub fn for_stop(mode: ExecutorMode, hit: Option<Hit>) -> Option<EventKind> {
+        Some(match (mode, hit) {
+            (ExecutorMode::Running, _) | (ExecutorMode::Invalid(Error::CpuSyscall), _) => return None,
+            (ExecutorMode::Breakpoint, Some(Hit::Breakpoint(id))) => EventKind::Breakpoint(id),
+            (ExecutorMode::Breakpoint, Some(Hit::Catchpoint(id))) => EventKind::Catchpoint(id),
+            (ExecutorMode::Breakpoint, Some(Hit::Watchpoint(hit))) => EventKind::Watchpoint(hit),
+            (ExecutorMode::Breakpoint, None) | (ExecutorMode::Paused, _) => EventKind::Paused,
+            (ExecutorMode::LimitReached(limit), _) => EventKind::LimitReached(limit),
+            (ExecutorMode::Invalid(error), _) => EventKind::Faulted(error),
+            (ExecutorMode::Exited { code }, _) => EventKind::Exited(code),
+            (ExecutorMode::Halted, _) => EventKind::Halted,
+        })
+    }
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events { subscribers: vec![], next_id: 0, previous: None, running: false }
+    }
+
+    fn add(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+
+        self.subscribers.push((id, subscriber));
+
+        id
+    }
+
+    // Dropping the receiver ends the subscription.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+
+        self.add(Subscriber::Channel(sender));
+
+        receiver
+    }
+
+    pub fn on_event(&mut self, callback: Box<dyn FnMut(&Event) + Send>) -> SubscriptionId {
+        self.add(Subscriber::Callback(callback))
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let count = self.subscribers.len();
+
+        self.subscribers.retain(|(other, _)| *other != id);
+
+        self.subscribers.len() != count
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn emit(&mut self, kind: EventKind, registers: &Registers) {
+        let stop = !matches!(kind, EventKind::Started | EventKind::WaitingForInput | EventKind::Output(_));
+
+        match kind {
+            EventKind::Started => self.running = true,
+            _ if stop => self.running = false,
+            _ => { }
+        }
+
+        // Changes before the first stop are counted from the first event.
+        let previous = *self.previous.get_or_insert(*registers);
+
+        if !self.subscribers.is_empty() {
+            let changes = register_edits(&previous, registers);
+
+            let event = Event { kind, registers: *registers, changes };
+
+            self.subscribers.retain_mut(|(_, subscriber)| match subscriber {
+                Subscriber::Channel(sender) => sender.send(event.clone()).is_ok(),
+                Subscriber::Callback(callback) => {
+                    callback(&event);
+
+                    true
+                }
+            });
+        }
+
+        if stop {
+            self.previous = Some(*registers);
+        }
+    }
+
+    pub fn started(&mut self, registers: &Registers) {
+        if !self.running {
+            self.emit(EventKind::Started, registers)
+        }
+    }
+
+    // Sends the stop event for mode, if something is running.
+    pub fn stopped(&mut self, mode: ExecutorMode, hit: Option<Hit>, registers: &Registers) {
+        if !self.running {
+            return
+        }
+
+        if let Some(kind) = EventKind::for_stop(mode, hit) {
+            self.emit(kind, registers)
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
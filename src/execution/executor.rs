@@ -1,10 +1,31 @@
 use crate::cpu::error::Error;
 use crate::cpu::state::Registers;
 use crate::cpu::{Memory, State};
-use crate::execution::executor::ExecutorMode::{Breakpoint, Invalid, Paused, Running};
+use crate::cpu::memory::effect::SideEffect;
+use crate::cpu::memory::watched::WatchedMemory;
+use crate::cpu::memory::{Mountable, Paged};
+use crate::assembler::binary::Binary;
+use crate::cpu::error::Error::CpuSyscall;
+use crate::execution::executor::ExecutorMode::{Halted, Invalid, LimitReached, Paused, Running};
+use crate::execution::limits::{Limit, Limits, Usage};
+use crate::execution::save::MachineImage;
+use crate::execution::patch::{patch_state, PatchConflict, PatchOptions, PatchReport, PcMap};
+use crate::execution::speed::{Pacer, Speed};
+use crate::execution::stepping::{decode_at, StatementMap, StepTarget};
+use crate::execution::termination::Termination;
 use std::collections::HashSet;
+use crate::execution::breakpoints::{Breakpoint, BreakpointId, Breakpoints, Catchpoint, Hit, WatchContext, Watchpoint};
+use crate::execution::events::{Event, EventKind, Events, SubscriptionId};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
 use std::fmt::Debug;
+use crate::execution::trackers::call_stack::CallFrame;
 use crate::execution::trackers::empty::EmptyTracker;
+use crate::execution::trackers::history::WithHistory;
+use crate::execution::trackers::hooks::HookContext;
 use crate::execution::trackers::Tracker;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -13,29 +34,56 @@ pub enum ExecutorMode {
     Invalid(Error),
     Paused,
     Breakpoint,
+    LimitReached(Limit),
+    Exited { code: u32 }, // through an exit syscall
+    Halted, // a break instruction, or the end of .text, see Termination
 }
 
-// Addresses
-type Breakpoints = HashSet<u32>;
-
 pub struct ExecutorState<Mem: Memory, Track: Tracker<Mem>> {
     mode: ExecutorMode,
 
     state: State<Mem>,
     breakpoints: Breakpoints,
+    hit: Option<Hit>,
+    logs: Vec<String>,
     batch: usize,
+    speed: Speed,
+
+    limits: Limits,
+    usage: Usage,
+    termination: Termination,
+
+    step_target: Option<StepTarget>,
+    syscall_watch: Option<WatchContext>, // for the syscall being handled, see syscall_handled
+    events: Events,
 
     tracker: Track
 }
 
+// Runs on whichever thread holds the executor, between two instructions.
+type Request<Mem> = Box<dyn FnOnce (&mut State<Mem>) + Send>;
+
 pub struct Executor<Mem: Memory, Track: Tracker<Mem>> {
     mutex: parking_lot::Mutex<ExecutorState<Mem, Track>>,
+
+    // Checked by the run loop between instructions, so other threads never wait on a batch.
+    interrupt: AtomicBool,
+    requests: parking_lot::Mutex<Vec<Request<Mem>>>,
+    has_requests: AtomicBool,
+    snapshot: parking_lot::Mutex<Snapshot>,
+}
+
+// Releasing the executor answers queued requests and publishes a new snapshot.
+struct Locked<'a, Mem: Memory, Track: Tracker<Mem>> {
+    guard: parking_lot::MutexGuard<'a, ExecutorState<Mem, Track>>,
+    executor: &'a Executor<Mem, Track>,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct DebugFrame {
     pub mode: ExecutorMode,
     pub registers: Registers,
+    pub hit: Option<Hit>, // set when mode is Breakpoint
 }
 
 impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
@@ -43,8 +91,17 @@ impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
         ExecutorState {
             mode: Paused,
             state,
-            breakpoints: HashSet::new(),
+            breakpoints: Breakpoints::new(),
+            hit: None,
+            logs: vec![],
             batch: 140,
+            speed: Speed::Unlimited,
+            limits: Limits::default(),
+            usage: Usage::default(),
+            termination: Termination::default(),
+            step_target: None,
+            syscall_watch: None,
+            events: Events::new(),
             tracker
         }
     }
@@ -53,35 +110,195 @@ impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
         DebugFrame {
             mode: self.mode,
             registers: self.state.registers,
+            hit: self.hit,
         }
     }
 
     // Returns true if the CPU was interrupted.
     // If true, see self.frame() for details (ex. the mode)
     pub fn cycle(&mut self, no_breakpoints: bool) -> bool {
-        if !no_breakpoints && self.breakpoints.contains(&self.state.registers.pc) {
-            self.mode = Breakpoint;
+        self.hit = None;
+        self.syscall_watch = None;
+
+        if !no_breakpoints {
+            if let Some(hit) = self.breakpoints.check(&self.state, &mut self.logs) {
+                self.mode = ExecutorMode::Breakpoint;
+                self.hit = Some(hit);
+
+                return true
+            }
+        }
+
+        if self.termination.at_end(self.state.registers.pc) {
+            self.mode = Halted;
 
             return true
         }
 
+        if self.limits.instructions_exhausted(&self.usage) {
+            self.mode = LimitReached(Limit::Instructions);
+
+            return true
+        }
+
+        let start = self.state.registers.pc;
+        let stepping = self.step_target.as_ref()
+            .filter(|target| target.needs_instruction())
+            .and_then(|_| decode_at(&self.state));
+
+        if !no_breakpoints && self.step_target.as_ref().is_some_and(|target| target.before(stepping.as_ref())) {
+            self.step_target = None;
+            self.mode = Paused;
+
+            return true
+        }
+
+        let watch = self.breakpoints.has_watchpoints()
+            .then(|| self.breakpoints.prepare_watch(&self.state));
+
         self.tracker.pre_track(&mut self.state);
+
+        let hooks = self.tracker.wants_hooks()
+            .then(|| HookContext::fetch(&self.state, &mut self.tracker));
+
         let result = self.state.step();
 
+        self.track_effects();
+
+        if let Some(hooks) = hooks {
+            hooks.finish(result, &self.state, &mut self.tracker)
+        }
+
         if let Err(err) = result {
-            self.mode = Invalid(err);
+            if err == CpuSyscall {
+                self.usage.syscalls += 1;
+                self.syscall_watch = watch;
+            }
+
+            let catch = if no_breakpoints { None } else { self.breakpoints.check_error(err) };
+
+            self.mode = match (self.limits.check(&self.usage), catch) {
+                (Some(limit), _) => LimitReached(limit),
+                (None, Some(hit)) => {
+                    self.hit = Some(hit);
+
+                    ExecutorMode::Breakpoint
+                }
+                (None, None) => self.termination.check(err, &self.state.registers).unwrap_or(Invalid(err))
+            };
 
             true
         } else {
+            self.usage.instructions += 1;
+
             // Only track the instruction if it did not fail.
             // This means back-stepping will not go back to your instruction.
             self.tracker.post_track(&mut self.state);
 
-            false
+            // Checked last, any other reason to stop takes priority.
+            let pause = self.tracker.pause_requested();
+
+            if let Some(limit) = self.limits.check_pages(self.state.memory.resident_pages()) {
+                self.mode = LimitReached(limit);
+
+                return true
+            }
+
+            if let Some(hit) = watch.and_then(|context| self.breakpoints.check_watch(&context, &self.state)) {
+                self.mode = ExecutorMode::Breakpoint;
+                self.hit = Some(hit);
+
+                return true
+            }
+
+            if let Some(target) = &mut self.step_target {
+                if target.after(stepping.as_ref(), start, &self.state) {
+                    self.step_target = None;
+                    self.mode = Paused;
+
+                    return true
+                }
+            }
+
+            if pause {
+                self.mode = Paused;
+            }
+
+            pause
+        }
+    }
+
+    fn set_running(&mut self) {
+        self.mode = Running;
+        self.events.started(&self.state.registers);
+    }
+
+    fn stopped(&mut self) {
+        self.events.stopped(self.mode, self.hit, &self.state.registers);
+    }
+
+    // Returns the limit if the output pushed the executor past it.
+    pub fn record_output(&mut self, bytes: &[u8]) -> Option<Limit> {
+        self.usage.output_bytes += bytes.len() as u64;
+        self.events.emit(EventKind::Output(bytes.to_vec()), &self.state.registers);
+
+        let limit = self.limits.check(&self.usage);
+
+        if let Some(limit) = limit {
+            self.mode = LimitReached(limit);
+        }
+
+        limit
+    }
+
+    fn track_effects(&mut self) {
+        for effect in self.state.memory.take_effects() {
+            self.tracker.track_effect(effect)
         }
     }
 }
 
+// The executor as of the last batch, readable while a run holds the state.
+#[derive(Copy, Clone, Debug)]
+pub struct Snapshot {
+    pub frame: DebugFrame,
+    pub usage: Usage,
+}
+
+// Instructions between two checks of Limits::deadline.
+const DEADLINE_INTERVAL: u64 = 1024;
+
+impl<Mem: Memory, Track: Tracker<Mem>> Deref for Locked<'_, Mem, Track> {
+    type Target = ExecutorState<Mem, Track>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<Mem: Memory, Track: Tracker<Mem>> DerefMut for Locked<'_, Mem, Track> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<Mem: Memory, Track: Tracker<Mem>> Locked<'_, Mem, Track> {
+    // Every run, step and reverse run begins here. A pause() that came in while nothing was running
+    // (ex. the lock was held by with_state) is dropped, instead of stopping this run at its first instruction.
+    fn start(&mut self) {
+        self.executor.interrupt.store(false, Ordering::Relaxed);
+        self.guard.set_running()
+    }
+}
+
+impl<Mem: Memory, Track: Tracker<Mem>> Drop for Locked<'_, Mem, Track> {
+    fn drop(&mut self) {
+        self.executor.serve_requests(&mut self.guard.state);
+
+        *self.executor.snapshot.lock() = Snapshot { frame: self.guard.frame(), usage: self.guard.usage };
+    }
+}
+
 pub struct BatchResult {
     pub instructions_executed: u64,
     pub interrupted: bool
@@ -89,80 +306,302 @@ pub struct BatchResult {
 
 impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
     pub fn new(state: State<Mem>, tracker: Track) -> Executor<Mem, Track> {
+        let state = ExecutorState::new(state, tracker);
+        let snapshot = Snapshot { frame: state.frame(), usage: state.usage };
+
         Executor {
-            mutex: parking_lot::Mutex::new(ExecutorState::new(state, tracker)),
+            mutex: parking_lot::Mutex::new(state),
+            interrupt: AtomicBool::new(false),
+            requests: parking_lot::Mutex::new(vec![]),
+            has_requests: AtomicBool::new(false),
+            snapshot: parking_lot::Mutex::new(snapshot),
         }
     }
 
     pub fn from_state(state: State<Mem>) -> Executor<Mem, EmptyTracker> {
-        Executor {
-            mutex: parking_lot::Mutex::new(ExecutorState::new(state, EmptyTracker { }))
+        Executor::new(state, EmptyTracker { })
+    }
+
+    fn lock(&self) -> Locked<'_, Mem, Track> {
+        Locked { guard: self.mutex.lock(), executor: self }
+    }
+
+    fn serve_requests(&self, state: &mut State<Mem>) {
+        if !self.has_requests.swap(false, Ordering::Acquire) {
+            return
+        }
+
+        for request in std::mem::take(&mut *self.requests.lock()) {
+            request(state)
         }
     }
 
     pub fn frame(&self) -> DebugFrame {
-        self.mutex.lock().frame()
+        self.lock().frame()
     }
 
+    // Does not wait for a running batch, see run_batched.
+    pub fn snapshot(&self) -> Snapshot {
+        *self.snapshot.lock()
+    }
+
+    // Runs f between two instructions of a run, or right away if nothing is running.
+    // Unlike with_state, this only waits for the current instruction, not the whole batch.
+    // Never returns if the calling thread holds the executor, ex. from inside with_state or an on_event callback.
+    pub fn request<T: Send + 'static, F: FnOnce (&mut State<Mem>) -> T + Send + 'static>(&self, f: F) -> T {
+        let (sender, receiver) = channel();
+
+        self.requests.lock().push(Box::new(move |state| {
+            let _ = sender.send(f(state));
+        }));
+
+        self.has_requests.store(true, Ordering::Release);
+
+        loop {
+            // Requests are also answered whenever the lock is released, ex. between batches.
+            drop(self.mutex.try_lock().map(|guard| Locked { guard, executor: self }));
+
+            if let Ok(value) = receiver.recv_timeout(Duration::from_millis(1)) {
+                return value
+            }
+        }
+    }
+
+    // The stop event is sent by the run loop once it notices.
+    // The run loop picks this up at its next instruction, without waiting for the lock.
+    // Only a run in progress is paused, the next run clears a request nothing picked up.
     pub fn pause(&self) {
-        self.mutex.lock().mode = Paused
+        self.interrupt.store(true, Ordering::Relaxed);
+
+        // Not running, or between batches, so there might be no run loop to notice the request.
+        if let Some(guard) = self.mutex.try_lock() {
+            let mut lock = Locked { guard, executor: self };
+
+            if self.interrupt.swap(false, Ordering::Relaxed) {
+                lock.mode = Paused
+            }
+        }
     }
     
     pub fn override_mode(&self, mode: ExecutorMode) {
-        self.mutex.lock().mode = mode
+        let mut lock = self.lock();
+
+        if mode == Running {
+            lock.start()
+        } else {
+            lock.mode = mode;
+            lock.stopped()
+        }
+    }
+
+    // Events are sent from whichever thread runs the executor.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.lock().events.subscribe()
+    }
+
+    // Callbacks run with the executor locked, and must not call back into it. See subscribe.
+    pub fn on_event<F: FnMut(&Event) + Send + 'static>(&self, callback: F) -> SubscriptionId {
+        self.lock().events.on_event(Box::new(callback))
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.lock().events.unsubscribe(id)
+    }
+
+    // For events only the frontend knows about, like WaitingForInput or Exited.
+    pub fn notify(&self, kind: EventKind) {
+        let mut lock = self.lock();
+        let lock = &mut *lock;
+
+        lock.events.emit(kind, &lock.state.registers)
     }
 
     pub fn with_state<T, F: FnOnce (&mut State<Mem>) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.state)
     }
 
     pub fn with_memory<T, F: FnOnce (&mut Mem) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.state.memory)
     }
 
     pub fn with_tracker<T, F: FnOnce (&mut Track) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.tracker)
     }
 
     pub fn syscall_handled(&self) {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
+        let syscall = lock.mode == Invalid(CpuSyscall);
 
         if let Invalid(_) = lock.mode {
             lock.mode = Running
         }
         
         lock.state.registers.pc += 4;
+        lock.usage.instructions += 1;
+
+        // The syscall failed its cycle, so it is tracked here, along with the handler's changes.
+        if syscall {
+            let lock = &mut *lock;
+
+            lock.track_effects();
+            lock.tracker.post_track(&mut lock.state);
+
+            // The handler may have written watched memory or registers (ex. read string).
+            let hit = lock.syscall_watch.take()
+                .and_then(|context| lock.breakpoints.check_watch(&context, &lock.state));
+
+            if let Some(hit) = hit.filter(|_| lock.mode == Running) {
+                lock.hit = Some(hit);
+                lock.mode = ExecutorMode::Breakpoint
+            }
+
+            if lock.tracker.pause_requested() && lock.mode == Running {
+                lock.mode = Paused
+            }
+
+            // The syscall can be the last instruction of a step.
+            let start = lock.state.registers.pc.wrapping_sub(4);
+
+            if lock.step_target.as_mut().is_some_and(|target| target.after(None, start, &lock.state)) {
+                lock.step_target = None;
+
+                if lock.mode == Running {
+                    lock.mode = Paused
+                }
+            }
+        }
+    }
+
+    // Syscall handlers report undo information for their side effects here (ex. consumed input).
+    pub fn record_effect(&self, effect: Box<dyn SideEffect>) {
+        self.lock().tracker.track_effect(effect)
+    }
+
+    // Syscall handlers report printed bytes here so the output limit can be enforced.
+    // Subscribers get them as an Output event. Past the limit, the mode is LimitReached and syscall_handled keeps it.
+    pub fn record_output(&self, bytes: &[u8]) -> Option<Limit> {
+        self.lock().record_output(bytes)
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        self.lock().limits = limits
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.lock().limits
+    }
+
+    // Paces run and the step operations built on it, takes effect mid-run.
+    pub fn set_speed(&self, speed: Speed) {
+        self.lock().speed = speed
     }
 
-    pub fn set_breakpoints(&self, breakpoints: Breakpoints) {
-        let mut lock = self.mutex.lock();
+    pub fn speed(&self) -> Speed {
+        self.lock().speed
+    }
+
+    pub fn set_termination(&self, termination: Termination) {
+        self.lock().termination = termination
+    }
+
+    pub fn termination(&self) -> Termination {
+        self.lock().termination
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.lock().usage
+    }
+
+    pub fn reset_usage(&self) {
+        self.lock().usage = Usage::default()
+    }
+
+    // Replaces the address breakpoints with unconditional ones. Catchpoints are kept.
+    pub fn set_breakpoints(&self, breakpoints: HashSet<u32>) {
+        let mut lock = self.lock();
+
+        lock.breakpoints.set_addresses(breakpoints)
+    }
+
+    pub fn add_breakpoint(&self, breakpoint: Breakpoint) -> BreakpointId {
+        self.lock().breakpoints.add(breakpoint)
+    }
+
+    pub fn add_catchpoint(&self, catchpoint: Catchpoint) -> BreakpointId {
+        self.lock().breakpoints.add_catchpoint(catchpoint)
+    }
+
+    pub fn add_watchpoint(&self, watchpoint: Watchpoint) -> BreakpointId {
+        self.lock().breakpoints.add_watchpoint(watchpoint)
+    }
+
+    pub fn remove_breakpoint(&self, id: BreakpointId) -> bool {
+        self.lock().breakpoints.remove(id)
+    }
+
+    pub fn with_breakpoints<T, F: FnOnce (&mut Breakpoints) -> T>(&self, f: F) -> T {
+        let mut lock = self.lock();
+
+        f(&mut lock.breakpoints)
+    }
 
-        lock.breakpoints = breakpoints
+    // Messages produced by logpoints since the last call.
+    pub fn take_logs(&self) -> Vec<String> {
+        std::mem::take(&mut self.lock().logs)
     }
 
     // Returns true if CPU was interrupted.
     pub fn cycle(&self, no_breakpoints: bool) -> bool {
-        self.mutex.lock().cycle(no_breakpoints)
+        let mut lock = self.lock();
+        let interrupted = lock.cycle(no_breakpoints);
+
+        if interrupted {
+            lock.stopped()
+        }
+
+        interrupted
     }
     
     pub fn is_breakpoint(&self) -> bool {
-        self.mutex.lock().mode == Breakpoint
+        self.lock().mode == ExecutorMode::Breakpoint
     }
     
     // Returns true if the CPU was interrupted.
+    // Holds the state for the whole batch. Meanwhile snapshot and request still answer, and pause is noticed
+    // at the next instruction.
     pub fn run_batched(&self, batch: usize, mut skip_first_breakpoint: bool, allow_interrupt: bool) -> BatchResult {
-        let mut value = self.mutex.lock();
+        let mut value = self.lock();
 
         let mut instructions_executed = 0;
         
         for _ in 0..batch {
+            if allow_interrupt && self.interrupt.load(Ordering::Relaxed) && self.interrupt.swap(false, Ordering::Relaxed) {
+                value.mode = Paused
+            }
+
+            if self.has_requests.load(Ordering::Relaxed) {
+                self.serve_requests(&mut value.state)
+            }
+
+            if instructions_executed % DEADLINE_INTERVAL == 0 && value.limits.deadline_passed() {
+                value.mode = LimitReached(Limit::Time);
+                value.stopped();
+
+                return BatchResult {
+                    instructions_executed,
+                    interrupted: true
+                }
+            }
+
             if allow_interrupt && value.mode != Running {
+                value.stopped();
+
                 return BatchResult {
                     instructions_executed,
                     interrupted: true
@@ -170,6 +609,8 @@ impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
             }
 
             if value.cycle(skip_first_breakpoint) {
+                value.stopped();
+
                 return BatchResult {
                     instructions_executed,
                     interrupted: true
@@ -187,13 +628,512 @@ impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
         }
     }
 
+    fn run_to_target(&self, target: StepTarget) -> DebugFrame {
+        {
+            let mut lock = self.lock();
+
+            lock.step_target = Some(target);
+            lock.start();
+        }
+
+        let frame = self.run(true);
+
+        // Keep stepping through syscalls, since the handler will resume the run.
+        if frame.mode != Invalid(CpuSyscall) {
+            self.lock().step_target = None;
+        }
+
+        frame
+    }
+
+    // Treats calls (jal, jalr, bgezal, bltzal) as a single instruction.
+    // Breakpoints inside the callee still stop execution.
+    pub fn step_over(&self) -> DebugFrame {
+        let target = self.with_state(|state| StepTarget::over(state));
+
+        match target {
+            Some(target) => self.run_to_target(target),
+            None => self.step()
+        }
+    }
+
+    // Executes a single instruction, ignoring breakpoints.
+    pub fn step(&self) -> DebugFrame {
+        let mut lock = self.lock();
+
+        lock.start();
+
+        if !lock.cycle(true) {
+            lock.mode = Paused;
+        }
+
+        lock.stopped();
+        lock.frame()
+    }
+
+    // Runs until the current function returns through jr $ra, stopping at the return address.
+    pub fn step_out(&self) -> DebugFrame {
+        self.run_to_target(StepTarget::out())
+    }
+
+    // Runs until the current function is about to execute its jr $ra.
+    pub fn run_to_return(&self) -> DebugFrame {
+        self.run_to_target(StepTarget::to_return())
+    }
+
+    // Runs until the next source statement starts, so pseudo-instructions and macro calls execute as a whole.
+    // Calls are entered. Syscalls inside the statement stop as usual and resume the step once handled.
+    pub fn step_line(&self, statements: &Arc<StatementMap>) -> DebugFrame {
+        self.run_to_target(StepTarget::line(statements.clone(), false, false))
+    }
+
+    // Like step_line, treating calls as part of the statement.
+    pub fn step_over_line(&self, statements: &Arc<StatementMap>) -> DebugFrame {
+        self.run_to_target(StepTarget::line(statements.clone(), true, false))
+    }
+
+    // Like step_line, but stops at each statement of a macro body.
+    // Statement::location then gives the definition site inside the macro.
+    pub fn step_into_macro(&self, statements: &Arc<StatementMap>) -> DebugFrame {
+        self.run_to_target(StepTarget::line(statements.clone(), false, true))
+    }
+
     pub fn run(&self, mut skip_first_breakpoint: bool) -> DebugFrame {
-        let batch = self.mutex.lock().batch;
-        
-        while !self.run_batched(batch, skip_first_breakpoint, true).interrupted {
-            skip_first_breakpoint = false
+        let (batch, speed) = {
+            let lock = self.lock();
+
+            (lock.batch, lock.speed)
+        };
+
+        let mut pacer = Pacer::new(speed);
+
+        loop {
+            let result = self.run_batched(pacer.speed().batch(batch), skip_first_breakpoint, true);
+
+            if result.interrupted {
+                break
+            }
+
+            skip_first_breakpoint = false;
+
+            // Picks up set_speed calls made while running, also in the middle of a wait.
+            let speed = self.lock().speed;
+
+            if speed != pacer.speed() {
+                pacer.reset(speed)
+            }
+
+            pacer.wait(result.instructions_executed, || {
+                let lock = self.lock();
+
+                lock.mode == Running && lock.speed == speed
+            });
         }
         
         self.frame()
     }
 }
+
+impl<Mem: Memory + Paged, Track: Tracker<Mem>> Executor<Mem, Track> {
+    // Registers, memory, breakpoints and the mode. The console belongs to the frontend and history to the tracker,
+    // they are added to the image by whoever owns them (see HistoryTracker::save).
+    pub fn save_image(&self) -> MachineImage {
+        let lock = self.lock();
+
+        MachineImage {
+            registers: lock.state.registers,
+            mode: lock.mode,
+            usage: lock.usage,
+            termination: lock.termination,
+            pages: lock.state.memory.pages(),
+            next_breakpoint: lock.breakpoints.next_id(),
+            points: lock.breakpoints.points(),
+            console: None,
+            history: None,
+        }
+    }
+
+    // Devices stay mounted as they are in this executor, trackers are left to the caller.
+    pub fn load_image(&self, image: &MachineImage) {
+        let mut lock = self.lock();
+
+        lock.state.registers = image.registers;
+        lock.state.memory.restore_pages(image.pages.clone());
+
+        lock.mode = image.mode;
+        lock.usage = image.usage;
+        lock.termination = image.termination;
+        lock.breakpoints.restore(image.next_breakpoint, image.points.clone());
+
+        lock.hit = None;
+        lock.logs.clear();
+        lock.step_target = None;
+    }
+}
+
+impl<Mem: Memory + Mountable, Track: Tracker<Mem>> Executor<Mem, Track> {
+    // Swaps in code reassembled from edited sources, see patch_state. Address breakpoints follow their statements,
+    // and trackers are told with Tracker::code_patched (ex. history is cleared, it can't step back across the patch).
+    // frames are the calls in progress (see CallStackTracker::frames), empty if they aren't tracked.
+    pub fn hot_patch(
+        &self, old: &Binary, new: &Binary, map: &PcMap, frames: &[CallFrame], options: &PatchOptions
+    ) -> PatchReport {
+        let mut lock = self.lock();
+
+        let mut report = patch_state(&mut lock.state, old, new, map, frames, options);
+
+        if !report.applied {
+            return report
+        }
+
+        lock.tracker.code_patched(map);
+
+        let lost = lock.breakpoints.remap(|address| {
+            if map.is_old_code(address) { map.get(address) } else { Some(address) }
+        });
+
+        report.conflicts.extend(lost.into_iter().map(|(id, address)| PatchConflict::Breakpoint { id, address }));
+
+        if lock.termination.end_of_text.is_some() && lock.termination.end_of_text == old.text_end() {
+            lock.termination.end_of_text = new.text_end()
+        }
+
+        // A step in progress was planned against the old code.
+        lock.step_target = None;
+
+        report
+    }
+}
+
+// A reverse cycle, the undone instruction and anything that stopped execution there.
+struct ReverseCycle {
+    pc: u32,
+    next_pc: u32,
+    hit: Option<Hit>,
+}
+
+impl<Mem: Memory + Clone, Track: WithHistory<Mem>> ExecutorState<WatchedMemory<Mem>, Track> {
+    fn reverse_cycle(&mut self) -> Option<ReverseCycle> {
+        let entry = self.tracker.history().undo(&mut self.state)?;
+        let (pc, next_pc) = (entry.pc, entry.next_pc);
+
+        let mut hit = None;
+
+        if self.breakpoints.has_watchpoints() {
+            // Replay the instruction to see if it triggers a watchpoint.
+            let context = self.breakpoints.prepare_watch(&self.state);
+
+            self.tracker.history().redo(&mut self.state);
+            hit = self.breakpoints.check_watch(&context, &self.state);
+            self.tracker.history().undo(&mut self.state);
+        }
+
+        let hit = hit.or_else(|| self.breakpoints.matches(&self.state));
+
+        Some(ReverseCycle { pc, next_pc, hit })
+    }
+
+    fn stop_reverse(&mut self, hit: Option<Hit>) {
+        self.hit = hit;
+        self.mode = if hit.is_some() { ExecutorMode::Breakpoint } else { Paused };
+
+        self.stopped();
+    }
+}
+
+impl<Mem: Memory + Clone, Track: WithHistory<Mem>> Executor<WatchedMemory<Mem>, Track> {
+    // Undoes a single instruction. Returns false if there is no history left.
+    pub fn reverse_step(&self) -> bool {
+        let mut lock = self.lock();
+
+        lock.hit = None;
+        lock.start();
+
+        let result = lock.reverse_cycle().is_some();
+        lock.stop_reverse(None);
+
+        result
+    }
+
+    // Undoes instructions in batches until a breakpoint or watchpoint is hit, history runs out, pause() is called
+    // or done returns true. The lock is released between batches, so other threads can pause or read state.
+    fn run_reverse(
+        &self, mut done: impl FnMut(&mut ExecutorState<WatchedMemory<Mem>, Track>, &ReverseCycle) -> bool
+    ) -> DebugFrame {
+        self.lock().start();
+
+        loop {
+            let mut lock = self.lock();
+
+            for _ in 0..lock.batch {
+                if self.interrupt.load(Ordering::Relaxed) && self.interrupt.swap(false, Ordering::Relaxed) {
+                    lock.mode = Paused
+                }
+
+                // Set by pause() between batches.
+                if lock.mode != Running {
+                    lock.stopped();
+
+                    return lock.frame()
+                }
+
+                let Some(cycle) = lock.reverse_cycle() else {
+                    lock.stop_reverse(None);
+
+                    return lock.frame()
+                };
+
+                if cycle.hit.is_some() {
+                    lock.stop_reverse(cycle.hit);
+
+                    return lock.frame()
+                }
+
+                if done(&mut lock, &cycle) {
+                    lock.stop_reverse(None);
+
+                    return lock.frame()
+                }
+            }
+        }
+    }
+
+    // Runs backwards until a breakpoint or watchpoint is hit, or history runs out.
+    pub fn reverse_continue(&self) -> DebugFrame {
+        self.run_reverse(|_, _| false)
+    }
+
+    // Steps back to the previous instruction of the current function, skipping over calls.
+    pub fn reverse_step_over(&self) -> DebugFrame {
+        let mut depth = 0usize;
+
+        self.run_reverse(|state, cycle| {
+            let instruction = decode_at(&state.state);
+
+            if instruction.as_ref().is_some_and(|i| i.is_return()) {
+                depth += 1;
+            } else if instruction.is_some_and(|i| i.is_call()) && cycle.next_pc != cycle.pc.wrapping_add(4) {
+                depth = depth.saturating_sub(1);
+            }
+
+            depth == 0
+        })
+    }
+
+    // Moves to a recorded instruction count, in either direction.
+    pub fn seek(&self, count: u64) -> bool {
+        let mut lock = self.lock();
+        lock.start();
+
+        let lock = &mut *lock;
+        lock.hit = None;
+
+        let result = lock.tracker.history().seek(count, &mut lock.state);
+        lock.stop_reverse(None);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::Region;
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::memory::Width;
+    use crate::execution::breakpoints::{WatchKind, WatchTarget};
+    use crate::execution::console::{Console, ConsoleSyscall};
+    use crate::unit::register::{RegisterId, RegisterName};
+    use super::*;
+
+    type TestExecutor = Executor<SectionMemory<DefaultResponder>, EmptyTracker>;
+
+    const HEAP: u32 = 0x10040000;
+
+    fn executor(source: &str, limits: Limits) -> TestExecutor {
+        let binary = assemble_from(source).unwrap();
+        let mut memory = SectionMemory::new();
+
+        for region in &binary.regions {
+            memory.mount(Region { start: region.address, data: region.data.clone() })
+        }
+
+        memory.mount_writable((HEAP >> 16) as usize, 0);
+
+        let executor = TestExecutor::new(State::new(binary.entry, memory), EmptyTracker { });
+        executor.set_limits(limits);
+
+        executor
+    }
+
+    // Runs until something other than a console syscall stops the executor.
+    fn run(executor: &TestExecutor, console: &Console) -> ExecutorMode {
+        loop {
+            executor.override_mode(Running);
+
+            let frame = executor.run(false);
+
+            if frame.mode != Invalid(CpuSyscall) {
+                return frame.mode
+            }
+
+            match console.syscall(executor) {
+                ConsoleSyscall::Handled | ConsoleSyscall::LimitReached(_) => executor.syscall_handled(),
+                _ => return frame.mode
+            }
+
+            // Ex. the output limit, or a watched buffer the syscall wrote to.
+            let mode = executor.frame().mode;
+
+            if mode != Running {
+                return mode
+            }
+        }
+    }
+
+    const SPIN: &str = "
+        loop:
+        addi $t0, $t0, 1
+        j loop
+    ";
+
+    #[test]
+    fn instruction_limit() {
+        let executor = executor(SPIN, Limits::new().with_instructions(10));
+
+        assert_eq!(run(&executor, &Console::new()), LimitReached(Limit::Instructions));
+        assert_eq!(executor.usage().instructions, 10);
+    }
+
+    #[test]
+    fn page_limit() {
+        let executor = executor("
+            li $t0, 0x10040000
+            sw $t0, 0($t0)
+            li $t1, 1
+        ", Limits::new());
+
+        let pages = executor.with_memory(|memory| memory.resident_pages());
+        executor.set_limits(Limits::new().with_pages(pages));
+
+        assert_eq!(run(&executor, &Console::new()), LimitReached(Limit::Pages));
+        assert_eq!(executor.with_state(|state| state.registers.line[9]), 0);
+    }
+
+    #[test]
+    fn output_limit() {
+        let executor = executor("
+            loop:
+            li $v0, 11
+            li $a0, 65
+            syscall
+            j loop
+        ", Limits::new().with_output_bytes(3));
+
+        let console = Console::new();
+
+        assert_eq!(run(&executor, &console), LimitReached(Limit::OutputBytes));
+        assert_eq!(console.output(), b"AAAA");
+    }
+
+    #[test]
+    fn syscall_limit() {
+        let executor = executor("
+            loop:
+            li $v0, 1
+            syscall
+            j loop
+        ", Limits::new().with_syscalls(2));
+
+        let console = Console::new();
+
+        assert_eq!(run(&executor, &console), LimitReached(Limit::Syscalls));
+        assert_eq!(console.output(), b"00");
+    }
+
+    #[test]
+    fn time_limit() {
+        let executor = executor(SPIN, Limits::new().with_deadline(Instant::now()));
+
+        assert_eq!(run(&executor, &Console::new()), LimitReached(Limit::Time));
+    }
+
+    // Loads and stores with an offset expand to five instructions, the lw is at 0x10 and the sw at 0x20.
+    const WATCHED: &str = "
+        li $t0, 0x10040000
+        lw $t1, 0($t0)
+        sw $t0, 4($t0)
+        li $t1, 5
+        li $v0, 8
+        move $a0, $t0
+        li $a1, 16
+        syscall
+        li $v0, 10
+        syscall
+    ";
+
+    // Runs to each watchpoint hit, returning the pc of the instruction and what it saw.
+    fn watch_hits(watchpoint: Watchpoint) -> Vec<(u32, WatchTarget, u32, u32)> {
+        let executor = executor(WATCHED, Limits::new());
+        let console = Console::new();
+        console.push_input(b"hi\n");
+
+        executor.add_watchpoint(watchpoint);
+
+        let mut hits = vec![];
+
+        while run(&executor, &console) == ExecutorMode::Breakpoint {
+            let Some(Hit::Watchpoint(hit)) = executor.frame().hit else { panic!("not a watchpoint") };
+
+            hits.push((hit.pc - 0x400000, hit.target, hit.old, hit.new))
+        }
+
+        hits
+    }
+
+    fn memory(address: u32, width: Width, store: bool) -> WatchTarget {
+        WatchTarget::Memory { address: HEAP + address, width, store }
+    }
+
+    #[test]
+    fn read_watchpoint() {
+        let hits = watch_hits(Watchpoint::Memory { address: HEAP, length: 8, kind: WatchKind::Read });
+
+        assert_eq!(hits, vec![(0x10, memory(0, Width::Word, false), 0, 0)]);
+    }
+
+    #[test]
+    fn write_watchpoint() {
+        let hits = watch_hits(Watchpoint::Memory { address: HEAP + 4, length: 4, kind: WatchKind::Write });
+
+        assert_eq!(hits, vec![(0x20, memory(4, Width::Word, true), 0, HEAP)]);
+    }
+
+    #[test]
+    fn access_watchpoint() {
+        let hits = watch_hits(Watchpoint::Memory { address: HEAP, length: 8, kind: WatchKind::Access });
+
+        assert_eq!(hits, vec![
+            (0x10, memory(0, Width::Word, false), 0, 0),
+            (0x20, memory(4, Width::Word, true), 0, HEAP),
+            (0x34, memory(0, Width::Byte, true), 0, b'h' as u32),
+        ]);
+    }
+
+    #[test]
+    fn register_watchpoint() {
+        let register = RegisterId::Line(RegisterName::T1);
+        let hits = watch_hits(Watchpoint::Register(register));
+
+        assert_eq!(hits, vec![(0x24, WatchTarget::Register(register), 0, 5)]);
+    }
+
+    #[test]
+    fn syscall_writes_trigger_watchpoints() {
+        let hits = watch_hits(Watchpoint::Memory { address: HEAP + 1, length: 2, kind: WatchKind::Write });
+
+        // The read string syscall writes "hi\n" and a NUL, the hit is for the first watched byte it changed.
+        assert_eq!(hits, vec![(0x34, memory(1, Width::Byte, true), 0, b'i' as u32)]);
+    }
+}
@@ -0,0 +1,761 @@
+use std::fmt::{Display, Formatter};
+use crate::cpu::error::Error;
+use crate::cpu::memory::Width;
+use crate::cpu::{Memory, State};
+use crate::unit::register::RegisterId;
+
+// Longest array view and string a watch will read.
+const MAX_ELEMENTS: u32 = 4096;
+const MAX_STRING: u32 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpressionError {
+    Parse { position: usize, message: String },
+    UnknownName(String),
+    Memory(Error),
+    DivideByZero,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpressionError::Parse { position, message } => write!(f, "{message} (at column {})", position + 1),
+            ExpressionError::UnknownName(name) => write!(f, "Unknown register or label \"{name}\""),
+            ExpressionError::Memory(error) => write!(f, "{error}"),
+            ExpressionError::DivideByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+pub type Result<T> = std::result::Result<T, ExpressionError>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not, // bitwise
+    LogicalNot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::LogicalAnd => "&&",
+            BinaryOp::LogicalOr => "||",
+        }
+    }
+
+    // Operators of the same level, from loosest to tightest binding.
+    const LEVELS: [&'static [BinaryOp]; 9] = [
+        &[BinaryOp::LogicalOr],
+        &[BinaryOp::LogicalAnd],
+        &[BinaryOp::Or],
+        &[BinaryOp::Xor],
+        &[BinaryOp::And],
+        &[BinaryOp::Equal, BinaryOp::NotEqual],
+        &[BinaryOp::Less, BinaryOp::LessEqual, BinaryOp::Greater, BinaryOp::GreaterEqual],
+        &[BinaryOp::ShiftLeft, BinaryOp::ShiftRight],
+        &[BinaryOp::Add, BinaryOp::Subtract],
+    ];
+
+    const PRODUCT: &'static [BinaryOp] = &[BinaryOp::Multiply, BinaryOp::Divide, BinaryOp::Remainder];
+
+    // Values are 32-bit words. Comparisons, division and remainder treat them as signed, like int in C.
+    fn apply(&self, a: u32, b: u32) -> Result<u32> {
+        let (signed_a, signed_b) = (a as i32, b as i32);
+
+        Ok(match self {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Subtract => a.wrapping_sub(b),
+            BinaryOp::Multiply => a.wrapping_mul(b),
+            BinaryOp::Divide if b == 0 => return Err(ExpressionError::DivideByZero),
+            BinaryOp::Divide => signed_a.wrapping_div(signed_b) as u32,
+            BinaryOp::Remainder if b == 0 => return Err(ExpressionError::DivideByZero),
+            BinaryOp::Remainder => signed_a.wrapping_rem(signed_b) as u32,
+            BinaryOp::And => a & b,
+            BinaryOp::Or => a | b,
+            BinaryOp::Xor => a ^ b,
+            BinaryOp::ShiftLeft => a.wrapping_shl(b),
+            BinaryOp::ShiftRight => a.wrapping_shr(b),
+            BinaryOp::Equal => (a == b) as u32,
+            BinaryOp::NotEqual => (a != b) as u32,
+            BinaryOp::Less => (signed_a < signed_b) as u32,
+            BinaryOp::LessEqual => (signed_a <= signed_b) as u32,
+            BinaryOp::Greater => (signed_a > signed_b) as u32,
+            BinaryOp::GreaterEqual => (signed_a >= signed_b) as u32,
+            BinaryOp::LogicalAnd => (a != 0 && b != 0) as u32,
+            BinaryOp::LogicalOr => (a != 0 || b != 0) as u32,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Constant(u32), // labels are resolved to constants when parsing
+    Register(RegisterId),
+    Load { width: Width, address: Box<Node> },
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+fn width_name(width: Width) -> &'static str {
+    match width {
+        Width::Byte => "byte",
+        Width::Half => "half",
+        Width::Word => "word",
+    }
+}
+
+// Parenthesized fully, so the text parses back to the same tree without the labels.
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Node::Constant(value) => write!(f, "0x{value:x}"),
+            Node::Register(register) => write!(f, "{register}"),
+            Node::Load { width, address } => write!(f, "{}[{address}]", width_name(*width)),
+            Node::Unary(op, value) => {
+                let symbol = match op {
+                    UnaryOp::Negate => "-",
+                    UnaryOp::Not => "~",
+                    UnaryOp::LogicalNot => "!",
+                };
+
+                write!(f, "{symbol}({value})")
+            }
+            Node::Binary(op, a, b) => write!(f, "({a} {} {b})", op.symbol()),
+        }
+    }
+}
+
+impl Node {
+    pub fn evaluate<Mem: Memory>(&self, state: &State<Mem>) -> Result<u32> {
+        match self {
+            Node::Constant(value) => Ok(*value),
+            Node::Register(register) => Ok(register.get(&state.registers)),
+            Node::Load { width, address } => {
+                let address = address.evaluate(state)?;
+
+                width.read(&state.memory, address).map_err(ExpressionError::Memory)
+            }
+            Node::Unary(op, value) => {
+                let value = value.evaluate(state)?;
+
+                Ok(match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as u32,
+                })
+            }
+            Node::Binary(BinaryOp::LogicalAnd, a, b) => {
+                Ok((a.evaluate(state)? != 0 && b.evaluate(state)? != 0) as u32)
+            }
+            Node::Binary(BinaryOp::LogicalOr, a, b) => {
+                Ok((a.evaluate(state)? != 0 || b.evaluate(state)? != 0) as u32)
+            }
+            Node::Binary(op, a, b) => op.apply(a.evaluate(state)?, b.evaluate(state)?),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Name(String), // label, or a register with its $
+    Symbol(&'static str),
+}
+
+// ',' and '@' only separate the parts of a Watch.
+const SYMBOLS: [&str; 26] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "(", ")", "[", "]", ",", "@",
+];
+
+fn lex(text: &str) -> Result<Vec<(usize, Token)>> {
+    let mut result = vec![];
+    let bytes = text.as_bytes();
+    let mut index = 0;
+
+    let error = |position: usize, message: &str| ExpressionError::Parse { position, message: message.into() };
+
+    while index < bytes.len() {
+        let c = bytes[index] as char;
+
+        if c.is_whitespace() {
+            index += 1;
+
+            continue
+        }
+
+        let start = index;
+
+        if c.is_ascii_digit() {
+            while index < bytes.len() && (bytes[index] as char).is_ascii_alphanumeric() {
+                index += 1
+            }
+
+            let literal = &text[start .. index];
+
+            let value = if let Some(hex) = literal.strip_prefix("0x") {
+                u32::from_str_radix(hex, 16)
+            } else if let Some(binary) = literal.strip_prefix("0b") {
+                u32::from_str_radix(binary, 2)
+            } else {
+                literal.parse::<u32>()
+            };
+
+            result.push((start, Token::Number(value.map_err(|_| error(start, "Invalid number"))?)));
+        } else if c == '\'' {
+            // Character literal, ex. 'a' or '\n'.
+            let rest = &text[index + 1..];
+            let (value, length) = match rest.as_bytes() {
+                [b'\\', escape, b'\'', ..] => (match escape {
+                    b'n' => b'\n',
+                    b't' => b'\t',
+                    b'r' => b'\r',
+                    b'0' => 0,
+                    other => *other,
+                }, 3),
+                [value, b'\'', ..] => (*value, 2),
+                _ => return Err(error(start, "Invalid character literal"))
+            };
+
+            result.push((start, Token::Number(value as u32)));
+            index += 1 + length;
+        } else if c == '$' || c == '_' || c == '.' || c.is_ascii_alphabetic() {
+            index += 1;
+
+            while index < bytes.len() && {
+                let c = bytes[index] as char;
+
+                c == '_' || c == '.' || c.is_ascii_alphanumeric()
+            } {
+                index += 1
+            }
+
+            result.push((start, Token::Name(text[start .. index].to_string())));
+        } else {
+            let symbol = SYMBOLS.iter()
+                .find(|symbol| text[index..].starts_with(**symbol))
+                .ok_or_else(|| error(start, &format!("Unexpected character '{c}'")))?;
+
+            result.push((start, Token::Symbol(symbol)));
+            index += symbol.len();
+        }
+    }
+
+    Ok(result)
+}
+
+struct Parser<'a, F: FnMut(&str) -> Option<u32>> {
+    tokens: &'a [(usize, Token)],
+    index: usize,
+    end: usize, // position reported for errors at the end of the text
+    labels: F,
+}
+
+impl<F: FnMut(&str) -> Option<u32>> Parser<'_, F> {
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T> {
+        Err(ExpressionError::Parse { position: self.position(), message: message.into() })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(other)) if *other == symbol);
+
+        if found {
+            self.index += 1
+        }
+
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.error(&format!("Expected '{symbol}'"))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node> {
+        let operators = BinaryOp::LEVELS.get(level).copied().unwrap_or(BinaryOp::PRODUCT);
+
+        let next = |parser: &mut Self| if level < BinaryOp::LEVELS.len() {
+            parser.binary(level + 1)
+        } else {
+            parser.unary()
+        };
+
+        let mut node = next(self)?;
+
+        while let Some(op) = operators.iter().find(|op| matches!(self.peek(), Some(Token::Symbol(symbol)) if *symbol == op.symbol())) {
+            self.index += 1;
+
+            node = Node::Binary(*op, Box::new(node), Box::new(next(self)?))
+        }
+
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        let op = if self.eat("-") {
+            UnaryOp::Negate
+        } else if self.eat("~") {
+            UnaryOp::Not
+        } else if self.eat("!") {
+            UnaryOp::LogicalNot
+        } else {
+            return self.primary()
+        };
+
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node> {
+        let Some(token) = self.peek().cloned() else { return self.error("Expected a value") };
+
+        match token {
+            Token::Number(value) => {
+                self.index += 1;
+
+                Ok(Node::Constant(value))
+            }
+            Token::Symbol("(") => {
+                self.index += 1;
+
+                let node = self.binary(0)?;
+                self.expect(")")?;
+
+                Ok(node)
+            }
+            Token::Name(name) => {
+                let width = match name.as_str() {
+                    "byte" => Some(Width::Byte),
+                    "half" => Some(Width::Half),
+                    "word" => Some(Width::Word),
+                    _ => None
+                };
+
+                if let Some(width) = width {
+                    if matches!(self.tokens.get(self.index + 1), Some((_, Token::Symbol("[")))) {
+                        self.index += 2;
+
+                        let address = self.binary(0)?;
+                        self.expect("]")?;
+
+                        return Ok(Node::Load { width, address: Box::new(address) })
+                    }
+                }
+
+                let node = if name.starts_with('$') {
+                    RegisterId::from_name(&name).map(Node::Register)
+                } else {
+                    (self.labels)(&name).map(Node::Constant)
+                };
+
+                self.index += 1;
+
+                node.ok_or(ExpressionError::UnknownName(name))
+            }
+            Token::Symbol(symbol) => self.error(&format!("Unexpected '{symbol}'"))
+        }
+    }
+}
+
+// A value computed from registers and memory, ex. "word[$sp + 4] & 0xff".
+// Registers start with $, memory is read with byte[...], half[...] or word[...].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    pub root: Node,
+}
+
+impl Expression {
+    // labels resolves names like Binary::labels, they become constants.
+    pub fn parse<F: FnMut(&str) -> Option<u32>>(text: &str, labels: F) -> Result<Expression> {
+        let tokens = lex(text)?;
+
+        let mut parser = Parser { tokens: &tokens, index: 0, end: text.len(), labels };
+        let root = parser.binary(0)?;
+
+        if parser.index < tokens.len() {
+            return parser.error("Unexpected text after the expression")
+        }
+
+        Ok(Expression { root })
+    }
+
+    pub fn evaluate<Mem: Memory>(&self, state: &State<Mem>) -> Result<u32> {
+        self.root.evaluate(state)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.root)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Signed,
+    Unsigned,
+    Char,
+    String, // the value is the address of a NUL terminated string
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        Some(match name {
+            "x" | "hex" => Format::Hex,
+            "d" | "i" | "signed" => Format::Signed,
+            "u" | "unsigned" => Format::Unsigned,
+            "c" | "char" => Format::Char,
+            "s" | "string" => Format::String,
+            _ => return None
+        })
+    }
+}
+
+// quote is the character around the text, ex. '"' for a string.
+fn escape(byte: u8, quote: u8) -> String {
+    match byte {
+        b'\n' => "\\n".into(),
+        b'\t' => "\\t".into(),
+        b'\r' => "\\r".into(),
+        b'\\' => "\\\\".into(),
+        _ if byte == quote => format!("\\{}", byte as char),
+        0x20 ..= 0x7e => (byte as char).to_string(),
+        _ => format!("\\x{byte:02x}"),
+    }
+}
+
+fn read_string<Mem: Memory>(memory: &Mem, address: u32) -> Result<String> {
+    let mut result = "\"".to_string();
+
+    for offset in 0 .. MAX_STRING {
+        let byte = memory.get(address.wrapping_add(offset)).map_err(ExpressionError::Memory)?;
+
+        if byte == 0 {
+            result.push('"');
+
+            return Ok(result)
+        }
+
+        result.push_str(&escape(byte, b'"'))
+    }
+
+    result.push_str("...");
+
+    Ok(result)
+}
+
+// An expression for a watch window or the CLI, with an optional array view and format:
+// "word[arr]@10", "byte[msg]@5, c", "$a0, s" or "$t0 - $t1, d".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub expression: Expression,
+    pub count: Option<Expression>, // elements to show, the expression must be a memory read
+    pub format: Option<Format>,
+}
+
+impl Watch {
+    pub fn parse<F: FnMut(&str) -> Option<u32>>(text: &str, mut labels: F) -> Result<Watch> {
+        // Split on tokens, so a ',' or '@' character literal stays in the expression.
+        let tokens = lex(text)?;
+
+        let last = |symbol: &str, end: usize| tokens.iter()
+            .rev()
+            .find(|(position, token)| *position < end && matches!(token, Token::Symbol(other) if *other == symbol))
+            .map(|(position, _)| *position);
+
+        let (end, format) = match last(",", text.len()) {
+            Some(comma) => {
+                let name = text[comma + 1..].trim();
+
+                let format = Format::from_name(name).ok_or_else(|| ExpressionError::Parse {
+                    position: comma + 1,
+                    message: format!("Unknown format \"{name}\", expected x, d, u, c or s")
+                })?;
+
+                (comma, Some(format))
+            }
+            None => (text.len(), None)
+        };
+
+        let (end, count) = match last("@", end) {
+            Some(at) => (at, Some(Expression::parse(&text[at + 1 .. end], &mut labels).map_err(|error| match error {
+                ExpressionError::Parse { position, message } =>
+                    ExpressionError::Parse { position: position + at + 1, message },
+                error => error
+            })?)),
+            None => (end, None)
+        };
+
+        let body = &text[.. end];
+
+        let expression = Expression::parse(body, &mut labels)?;
+
+        if count.is_some() && !matches!(expression.root, Node::Load { .. }) {
+            return Err(ExpressionError::Parse {
+                position: 0, message: "Array views need a memory read, ex. word[arr]@10".into()
+            })
+        }
+
+        Ok(Watch { expression, count, format })
+    }
+
+    fn format_value<Mem: Memory>(&self, state: &State<Mem>, value: u32, width: Width) -> Result<String> {
+        let bits = width.bytes() * 8;
+
+        Ok(match self.format.unwrap_or(Format::Hex) {
+            Format::Hex => format!("0x{value:0digits$x}", digits = width.bytes() as usize * 2),
+            Format::Signed => format!("{}", ((value << (32 - bits)) as i32) >> (32 - bits)),
+            Format::Unsigned => format!("{value}"),
+            Format::Char => format!("'{}'", escape(value as u8, b'\'')),
+            Format::String => read_string(&state.memory, value)?,
+        })
+    }
+
+    pub fn evaluate<Mem: Memory>(&self, state: &State<Mem>) -> Result<String> {
+        let Some(count) = &self.count else {
+            let width = match &self.expression.root {
+                Node::Load { width, .. } => *width,
+                _ => Width::Word
+            };
+
+            return self.format_value(state, self.expression.evaluate(state)?, width)
+        };
+
+        let Node::Load { width, address } = &self.expression.root else { unreachable!() };
+
+        let count = count.evaluate(state)?.min(MAX_ELEMENTS);
+        let start = address.evaluate(state)?;
+
+        // Byte arrays shown as strings read exactly count bytes.
+        if *width == Width::Byte && self.format == Some(Format::String) {
+            let text: Result<String> = (0 .. count)
+                .map(|offset| state.memory.get(start.wrapping_add(offset)).map(|byte| escape(byte, b'"')).map_err(ExpressionError::Memory))
+                .collect();
+
+            return Ok(format!("\"{}\"", text?))
+        }
+
+        let values: Result<Vec<String>> = (0 .. count)
+            .map(|index| {
+                let address = start.wrapping_add(index * width.bytes());
+                let value = width.read(&state.memory, address).map_err(ExpressionError::Memory)?;
+
+                self.format_value(state, value, *width)
+            })
+            .collect();
+
+        Ok(format!("[{}]", values?.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::unit::register::RegisterName;
+    use super::*;
+
+    const DATA: u32 = 0x10010000;
+
+    fn labels(name: &str) -> Option<u32> {
+        match name {
+            "msg" => Some(DATA),
+            "arr" => Some(DATA + 0x10),
+            _ => None
+        }
+    }
+
+    fn parse(text: &str) -> Result<Expression> {
+        Expression::parse(text, labels)
+    }
+
+    fn state() -> State<SectionMemory<DefaultResponder>> {
+        let mut memory = SectionMemory::new();
+        memory.mount_writable((DATA >> 16) as usize, 0);
+
+        for (offset, byte) in b"a,\"@'\n\x01".iter().enumerate() {
+            memory.set(DATA + offset as u32, *byte).unwrap();
+        }
+
+        for (index, value) in [1u32, 0xFFFFFFFE, 300].iter().enumerate() {
+            memory.set_u32(DATA + 0x10 + index as u32 * 4, *value).unwrap();
+        }
+
+        let mut state = State::new(0x400000, memory);
+        state.registers.line[RegisterName::A0 as usize] = DATA;
+        state.registers.line[RegisterName::T0 as usize] = 7;
+
+        state
+    }
+
+    fn evaluate(text: &str) -> Result<u32> {
+        parse(text)?.evaluate(&state())
+    }
+
+    fn show(text: &str) -> Result<String> {
+        Watch::parse(text, labels)?.evaluate(&state())
+    }
+
+    fn error_position(result: Result<impl std::fmt::Debug>) -> usize {
+        match result {
+            Err(ExpressionError::Parse { position, .. }) => position,
+            other => panic!("expected a parse error, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(evaluate("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(evaluate("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(evaluate("1 | 2 == 2").unwrap(), 1);
+        assert_eq!(evaluate("6 & 3 ^ 1").unwrap(), 3);
+        assert_eq!(evaluate("0 || 2 && 3").unwrap(), 1);
+        assert_eq!(evaluate("10 - 3 - 2").unwrap(), 5);
+        assert_eq!(evaluate("-~!0").unwrap(), 2);
+    }
+
+    #[test]
+    fn signed_arithmetic() {
+        assert_eq!(evaluate("-7 / 2").unwrap(), -3i32 as u32);
+        assert_eq!(evaluate("-7 % 2").unwrap(), -1i32 as u32);
+        assert_eq!(evaluate("-1 < 0").unwrap(), 1);
+        assert_eq!(evaluate("1 / 0"), Err(ExpressionError::DivideByZero));
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(evaluate("0x10 + 0b101 + 3").unwrap(), 24);
+        assert_eq!(evaluate("'a' + '\\n'").unwrap(), 97 + 10);
+        assert_eq!(evaluate("','").unwrap(), b',' as u32);
+        assert_eq!(evaluate("'\\''").unwrap(), b'\'' as u32);
+        assert_eq!(error_position(parse("1 + 0xZZ")), 4);
+    }
+
+    #[test]
+    fn registers_labels_and_memory() {
+        assert_eq!(evaluate("$t0 * 2").unwrap(), 14);
+        assert_eq!(evaluate("$8").unwrap(), 7);
+        assert_eq!(evaluate("$pc").unwrap(), 0x400000);
+        assert_eq!(evaluate("byte[msg + 1]").unwrap(), b',' as u32);
+        assert_eq!(evaluate("word[arr + 8]").unwrap(), 300);
+        assert_eq!(evaluate("half[arr + 4]").unwrap(), 0xFFFE);
+        assert_eq!(evaluate("byte[$a0] == 'a'").unwrap(), 1);
+
+        assert_eq!(parse("$nope"), Err(ExpressionError::UnknownName("$nope".into())));
+        assert_eq!(parse("missing"), Err(ExpressionError::UnknownName("missing".into())));
+        assert!(matches!(evaluate("word[0]"), Err(ExpressionError::Memory(_))));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error_position(parse("1 +")), 3);
+        assert_eq!(error_position(parse("(1 + 2")), 6);
+        assert_eq!(error_position(parse("1 2")), 2);
+        assert_eq!(error_position(parse("1 # 2")), 2);
+        assert_eq!(error_position(parse("1, 2")), 1);
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for text in ["1 + 2 * $t0", "word[arr + $a0 * 4] >> 2", "-(1 - ~$hi) == !msg", "byte[byte[msg]] || 3"] {
+            let expression = parse(text).unwrap();
+            let again = Expression::parse(&expression.to_string(), |_| None).unwrap();
+
+            assert_eq!(again, expression, "{text}");
+        }
+    }
+
+    #[test]
+    fn watch_parts() {
+        let watch = Watch::parse("word[arr]@3, d", labels).unwrap();
+
+        assert_eq!(watch.expression, parse("word[arr]").unwrap());
+        assert_eq!(watch.count, Some(parse("3").unwrap()));
+        assert_eq!(watch.format, Some(Format::Signed));
+
+        assert_eq!(show("word[arr]@3, d").unwrap(), "[1, -2, 300]");
+        assert_eq!(show("half[arr + 4]@1, u").unwrap(), "[65534]");
+        assert_eq!(show("$t0").unwrap(), "0x00000007");
+        assert_eq!(show("byte[msg], c").unwrap(), "'a'");
+        assert_eq!(show("$a0, s").unwrap(), "\"a,\\\"@'\\n\\x01\"");
+    }
+
+    #[test]
+    fn watch_separators_in_character_literals() {
+        let watch = Watch::parse("byte[msg + 1] == ','", labels).unwrap();
+
+        assert_eq!(watch.format, None);
+        assert_eq!(watch.evaluate(&state()).unwrap(), "0x00000001");
+
+        let watch = Watch::parse("byte[msg + 3] == '@', u", labels).unwrap();
+
+        assert_eq!(watch.count, None);
+        assert_eq!(watch.evaluate(&state()).unwrap(), "1");
+    }
+
+    #[test]
+    fn watch_escapes_quotes() {
+        assert_eq!(show("byte[msg]@4, s").unwrap(), "\"a,\\\"@\"");
+        assert_eq!(show("byte[msg + 4], c").unwrap(), "'\\''");
+        assert_eq!(show("byte[msg + 2], c").unwrap(), "'\"'");
+    }
+
+    #[test]
+    fn watch_errors() {
+        assert_eq!(error_position(Watch::parse("$t0, q", labels)), 4);
+        assert_eq!(error_position(Watch::parse("$t0@2", labels)), 0);
+        assert_eq!(error_position(Watch::parse("word[arr]@(1 +", labels)), 14);
+    }
+}
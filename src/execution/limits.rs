@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+use num_derive::{ToPrimitive, FromPrimitive};
+use crate::execution::limits::Limit::{Instructions, OutputBytes, Pages, Syscalls};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ToPrimitive, FromPrimitive)]
+pub enum Limit {
+    Instructions,
+    Pages,
+    OutputBytes,
+    Syscalls,
+    Time,
+}
+
+// None means unlimited.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    pub instructions: Option<u64>,
+    pub pages: Option<usize>, // resident 64 KiB pages, including the ones mounted at startup
+    pub output_bytes: Option<u64>,
+    pub syscalls: Option<u64>,
+    pub deadline: Option<Instant>, // checked every so often by the run loop, not by single steps
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub instructions: u64,
+    pub output_bytes: u64,
+    pub syscalls: u64,
+}
+
+fn exceeds<T: PartialOrd>(value: T, limit: Option<T>) -> bool {
+    limit.is_some_and(|limit| value > limit)
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Self::default()
+    }
+
+    pub fn with_instructions(mut self, count: u64) -> Self {
+        self.instructions = Some(count);
+
+        self
+    }
+
+    pub fn with_pages(mut self, count: usize) -> Self {
+        self.pages = Some(count);
+
+        self
+    }
+
+    pub fn with_output_bytes(mut self, count: u64) -> Self {
+        self.output_bytes = Some(count);
+
+        self
+    }
+
+    pub fn with_syscalls(mut self, count: u64) -> Self {
+        self.syscalls = Some(count);
+
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Checked before an instruction is executed.
+    pub fn instructions_exhausted(&self, usage: &Usage) -> bool {
+        self.instructions.is_some_and(|limit| usage.instructions >= limit)
+    }
+
+    pub fn check_pages(&self, pages: usize) -> Option<Limit> {
+        exceeds(pages, self.pages).then_some(Pages)
+    }
+
+    pub fn check(&self, usage: &Usage) -> Option<Limit> {
+        if exceeds(usage.instructions, self.instructions) {
+            Some(Instructions)
+        } else if exceeds(usage.syscalls, self.syscalls) {
+            Some(Syscalls)
+        } else if exceeds(usage.output_bytes, self.output_bytes) {
+            Some(OutputBytes)
+        } else {
+            None
+        }
+    }
+}
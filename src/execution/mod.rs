@@ -1,5 +1,16 @@
+pub mod breakpoints;
+pub mod console;
+pub mod diff;
 pub mod executor;
 pub mod elf;
+pub mod events;
+pub mod expression;
+pub mod limits;
+pub mod patch;
+pub mod save;
+pub mod speed;
+pub mod stepping;
+pub mod termination;
 pub mod trackers;
 
 pub use executor::Executor;
@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use crate::assembler::binary::{Binary, BinaryBreakpoint, RegionFlags};
+use crate::assembler::lexer::Location;
+use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::section::INITIAL_BYTE;
+use crate::cpu::{Memory, State};
+use crate::execution::breakpoints::BreakpointId;
+use crate::execution::trackers::call_stack::CallFrame;
+use crate::unit::instruction::InstructionDecoder;
+use crate::unit::register::RegisterName;
+use crate::unit::register::RegisterName::{RA, SP};
+
+// Larger edits are diffed by their common start and end only.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+// Old line -> new line for the lines kept by an edit, by longest common subsequence.
+fn match_lines(old: &[&str], new: &[&str]) -> Vec<Option<usize>> {
+    let mut result = vec![None; old.len()];
+
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    for (line, entry) in result.iter_mut().enumerate().take(prefix) {
+        *entry = Some(line)
+    }
+
+    for line in 0 .. suffix {
+        result[old.len() - 1 - line] = Some(new.len() - 1 - line)
+    }
+
+    let old_middle = &old[prefix .. old.len() - suffix];
+    let new_middle = &new[prefix .. new.len() - suffix];
+
+    let (rows, columns) = (old_middle.len(), new_middle.len());
+
+    if rows * columns > MAX_DIFF_CELLS {
+        return result
+    }
+
+    // lengths[i][j] is the length of the common subsequence of old_middle[i..] and new_middle[j..]
+    let width = columns + 1;
+    let mut lengths = vec![0u32; (rows + 1) * width];
+
+    for i in (0 .. rows).rev() {
+        for j in (0 .. columns).rev() {
+            lengths[i * width + j] = if old_middle[i] == new_middle[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            }
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+
+    while i < rows && j < columns {
+        if old_middle[i] == new_middle[j] {
+            result[prefix + i] = Some(prefix + j);
+
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1
+        } else {
+            j += 1
+        }
+    }
+
+    result
+}
+
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut result = vec![0];
+
+    result.extend(text.match_indices('\n').map(|(index, _)| index + 1));
+
+    result
+}
+
+// Moves offsets from a source before an edit to the same text after it.
+// Untouched lines keep their text, so only the start of the line moves.
+pub struct SourceEdit {
+    pub source: usize, // id of the source in the new binary
+    old_starts: Vec<usize>,
+    new_starts: Vec<usize>,
+    lines: Vec<Option<usize>>,
+}
+
+impl SourceEdit {
+    pub fn new(source: usize, old: &str, new: &str) -> SourceEdit {
+        let old_lines: Vec<&str> = old.split('\n').collect();
+        let new_lines: Vec<&str> = new.split('\n').collect();
+
+        SourceEdit {
+            source,
+            old_starts: line_starts(old),
+            new_starts: line_starts(new),
+            lines: match_lines(&old_lines, &new_lines),
+        }
+    }
+
+    // None if the line with offset was changed or removed.
+    pub fn offset(&self, offset: usize) -> Option<usize> {
+        let line = self.old_starts.partition_point(|start| *start <= offset).checked_sub(1)?;
+        let new_line = self.lines.get(line).copied().flatten()?;
+
+        Some(self.new_starts[new_line] + offset - self.old_starts[line])
+    }
+
+    pub fn line(&self, line: usize) -> Option<usize> {
+        self.lines.get(line).copied().flatten()
+    }
+}
+
+type StatementKey = (Location, Option<Location>);
+
+// Old pc -> new pc for two assemblies of edited sources, matched through the statements that emitted them.
+pub struct PcMap {
+    pcs: HashMap<u32, u32>,
+    calls: HashSet<u32>, // old pcs of call instructions
+    text: Vec<(u32, u32)>, // old executable regions, and the end of the text
+}
+
+impl PcMap {
+    // edits is keyed by the source id in the old binary, sources without an edit are taken as unchanged.
+    pub fn new(old: &Binary, new: &Binary, edits: &HashMap<usize, SourceEdit>) -> PcMap {
+        let move_location = |location: Location| match edits.get(&location.source) {
+            Some(edit) => edit.offset(location.index)
+                .map(|index| Location { source: edit.source, index }),
+            None => Some(location)
+        };
+
+        let move_key = |breakpoint: &BinaryBreakpoint| -> Option<StatementKey> {
+            let expansion = match breakpoint.expansion {
+                Some(expansion) => Some(move_location(expansion)?),
+                None => None
+            };
+
+            Some((move_location(breakpoint.location)?, expansion))
+        };
+
+        // A statement can be emitted more than once (ex. an included file), occurrences are paired in order.
+        let mut statements: HashMap<StatementKey, Vec<&BinaryBreakpoint>> = HashMap::new();
+
+        for breakpoint in new.breakpoints.iter().rev() {
+            statements.entry((breakpoint.location, breakpoint.expansion)).or_default().push(breakpoint)
+        }
+
+        let mut pcs = HashMap::new();
+
+        for breakpoint in &old.breakpoints {
+            let Some(key) = move_key(breakpoint) else { continue };
+            let Some(target) = statements.get_mut(&key).and_then(|list| list.pop()) else { continue };
+
+            if breakpoint.pcs.len() == target.pcs.len() {
+                pcs.extend(breakpoint.pcs.iter().copied().zip(target.pcs.iter().copied()))
+            } else if let (Some(start), Some(target)) = (breakpoint.pcs.first(), target.pcs.first()) {
+                // The statement assembled to a different length (ex. li with a larger value), only its start is safe.
+                pcs.insert(*start, *target);
+            }
+        }
+
+        if let (Some(old_end), Some(new_end)) = (old.text_end(), new.text_end()) {
+            pcs.insert(old_end, new_end);
+        }
+
+        let mut calls = HashSet::new();
+        let mut text = vec![];
+
+        for region in old.regions.iter().filter(|region| region.flags.contains(RegionFlags::EXECUTABLE)) {
+            text.push((region.address, region.wrapping_pc()));
+
+            for (index, word) in region.data.chunks_exact(4).enumerate() {
+                let pc = region.address.wrapping_add(index as u32 * 4);
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+                if InstructionDecoder::decode(pc, word).is_some_and(|instruction| instruction.is_call()) {
+                    calls.insert(pc);
+                }
+            }
+        }
+
+        if let Some(end) = old.text_end() {
+            text.push((end, end.wrapping_add(1)));
+        }
+
+        PcMap { pcs, calls, text }
+    }
+
+    pub fn get(&self, pc: u32) -> Option<u32> {
+        self.pcs.get(&pc).copied()
+    }
+
+    // Whether address points into the old code, and should be remapped.
+    pub fn is_old_code(&self, address: u32) -> bool {
+        self.text.iter().any(|(start, end)| address >= *start && address < *end)
+    }
+
+    // A code address found in a register or on the stack.
+    // Return addresses follow their call, so new code between the call and the next line runs on return.
+    pub fn address(&self, address: u32) -> Option<u32> {
+        let call = address.wrapping_sub(4);
+
+        if self.calls.contains(&call) {
+            return self.get(call).map(|pc| pc.wrapping_add(4))
+        }
+
+        self.get(address)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CodeSlot {
+    Pc,
+    Register(RegisterName),
+    Stack(u32), // address of the word on the stack
+}
+
+impl Display for CodeSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeSlot::Pc => write!(f, "pc"),
+            CodeSlot::Register(name) => write!(f, "{name}"),
+            CodeSlot::Stack(address) => write!(f, "stack word 0x{address:08x}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchConflict {
+    Code { slot: CodeSlot, address: u32 }, // no matching statement in the new binary, blocks the patch unless forced
+    Register { name: RegisterName, address: u32 }, // might be a code address or just a number, left as is
+    Data { address: u32 }, // .data region that moved or was resized, left as is
+    Breakpoint { id: BreakpointId, address: u32 }, // left at its old address
+}
+
+impl PatchConflict {
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, PatchConflict::Code { .. })
+    }
+}
+
+impl Display for PatchConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchConflict::Code { slot, address } =>
+                write!(f, "{slot} holds 0x{address:08x}, which has no matching statement in the new code"),
+            PatchConflict::Register { name, address } =>
+                write!(f, "{name} holds 0x{address:08x}, which points into the old code and was left as is"),
+            PatchConflict::Data { address } =>
+                write!(f, "Data region at 0x{address:08x} moved or changed size and was not replaced"),
+            PatchConflict::Breakpoint { id, address } =>
+                write!(f, "Breakpoint {id} at 0x{address:08x} has no matching statement in the new code"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PatchOptions {
+    pub data: bool, // also replace .data regions that kept their address and size, losing writes made while running
+    pub force: bool, // patch despite blocking conflicts, leaving those addresses alone
+}
+
+impl PatchOptions {
+    pub fn new() -> PatchOptions {
+        Self::default()
+    }
+
+    pub fn with_data(mut self, data: bool) -> Self {
+        self.data = data;
+
+        self
+    }
+
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PatchReport {
+    pub applied: bool,
+    pub remapped: Vec<(CodeSlot, u32, u32)>, // slot, old address, new address
+    pub conflicts: Vec<PatchConflict>,
+}
+
+impl PatchReport {
+    pub fn is_blocked(&self) -> bool {
+        self.conflicts.iter().any(|conflict| conflict.is_blocking())
+    }
+}
+
+// Stack words that hold a frame's return address, between the callee's $sp and the $sp at the call.
+// Frames are outermost first, as in CallStackTracker::frames.
+fn return_slots<Mem: Memory>(state: &State<Mem>, frames: &[CallFrame]) -> Vec<(u32, u32)> {
+    let mut result = vec![];
+
+    for (index, frame) in frames.iter().enumerate() {
+        let callee_sp = frames.get(index + 1).map_or(state.registers.get(SP), |inner| inner.sp);
+
+        let mut address = callee_sp & !3;
+
+        while address < frame.sp {
+            if state.memory.get_u32(address).is_ok_and(|value| value == frame.return_address) {
+                result.push((address, frame.return_address))
+            }
+
+            address += 4;
+        }
+    }
+
+    result
+}
+
+// Replaces the code of old with the code of new, keeping registers, stack and heap.
+// The pc, $ra and the return addresses saved for frames (see CallStackTracker::frames) are moved to the same
+// statement in the new code. Other registers that point into the old code are reported and left as is.
+// Nothing changes if an address can't be moved, unless options.force is set.
+pub fn patch_state<Mem: Memory + Mountable>(
+    state: &mut State<Mem>, old: &Binary, new: &Binary, map: &PcMap, frames: &[CallFrame], options: &PatchOptions
+) -> PatchReport {
+    let mut report = PatchReport::default();
+
+    let check = |report: &mut PatchReport, slot: CodeSlot, address: u32| {
+        if !map.is_old_code(address) {
+            return
+        }
+
+        let moved = match slot {
+            CodeSlot::Pc => map.get(address),
+            _ => map.address(address)
+        };
+
+        match moved {
+            Some(moved) => report.remapped.push((slot, address, moved)),
+            None => report.conflicts.push(PatchConflict::Code { slot, address })
+        }
+    };
+
+    check(&mut report, CodeSlot::Pc, state.registers.pc);
+    check(&mut report, CodeSlot::Register(RA), state.registers.get(RA));
+
+    for index in 1 .. 32u8 {
+        let (name, address) = (RegisterName::from(index), state.registers.line[index as usize]);
+
+        if name != RA && map.is_old_code(address) {
+            report.conflicts.push(PatchConflict::Register { name, address })
+        }
+    }
+
+    for (address, value) in return_slots(state, frames) {
+        check(&mut report, CodeSlot::Stack(address), value);
+    }
+
+    let old_data: Vec<_> = old.regions.iter()
+        .filter(|region| !region.flags.contains(RegionFlags::EXECUTABLE))
+        .collect();
+
+    let mut data = vec![];
+
+    if options.data {
+        for region in new.regions.iter().filter(|region| !region.flags.contains(RegionFlags::EXECUTABLE)) {
+            let unchanged = old_data.iter()
+                .any(|old| old.address == region.address && old.data.len() == region.data.len());
+
+            if unchanged {
+                data.push(region)
+            } else {
+                report.conflicts.push(PatchConflict::Data { address: region.address })
+            }
+        }
+    }
+
+    if report.is_blocked() && !options.force {
+        return report
+    }
+
+    // Old code past the end of the new code is filled like fresh memory, so running off the end faults
+    // instead of running stale code (zeroes would decode as nops and slide into whatever follows).
+    for region in old.regions.iter().filter(|region| region.flags.contains(RegionFlags::EXECUTABLE)) {
+        state.memory.mount(Region { start: region.address, data: vec![INITIAL_BYTE; region.data.len()] })
+    }
+
+    for region in new.regions.iter().filter(|region| region.flags.contains(RegionFlags::EXECUTABLE)) {
+        state.memory.mount(Region { start: region.address, data: region.data.clone() })
+    }
+
+    for region in data {
+        state.memory.mount(Region { start: region.address, data: region.data.clone() })
+    }
+
+    for (slot, _, moved) in &report.remapped {
+        match slot {
+            CodeSlot::Pc => state.registers.pc = *moved,
+            CodeSlot::Register(name) => state.registers.line[*name as usize] = *moved,
+            CodeSlot::Stack(address) => { let _ = state.memory.set_u32(*address, *moved); }
+        }
+    }
+
+    report.applied = true;
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use super::*;
+
+    const STACK: u32 = 0x7FFFFFF0;
+
+    const OLD: &str = "main:
+    li $t0, 1
+    jal f
+    li $v0, 10
+    syscall
+f:
+    addi $t1, $t1, 1
+    jr $ra
+";
+
+    fn map(old: &str, new: &str) -> (Binary, Binary, PcMap) {
+        let (old_binary, new_binary) = (assemble_from(old).unwrap(), assemble_from(new).unwrap());
+        let edits = HashMap::from([(0, SourceEdit::new(0, old, new))]);
+        let map = PcMap::new(&old_binary, &new_binary, &edits);
+
+        (old_binary, new_binary, map)
+    }
+
+    fn state(binary: &Binary) -> State<SectionMemory<DefaultResponder>> {
+        let mut memory = SectionMemory::new();
+
+        for region in &binary.regions {
+            memory.mount(Region { start: region.address, data: region.data.clone() })
+        }
+
+        memory.mount_writable((STACK >> 16) as usize, 0);
+
+        State::new(binary.entry, memory)
+    }
+
+    #[test]
+    fn lines_match_by_common_subsequence() {
+        assert_eq!(match_lines(&["a", "b", "c", "d"], &["a", "x", "b", "d"]), vec![Some(0), Some(2), None, Some(3)]);
+        assert_eq!(match_lines(&["a", "b"], &["b", "a"]), vec![None, Some(0)]);
+        assert_eq!(match_lines(&["a", "a"], &[]), vec![None, None]);
+    }
+
+    #[test]
+    fn offsets_move_with_their_line() {
+        let edit = SourceEdit::new(0, "one\ntwo\nthree", "one\nnew\ntwo\nthree!");
+
+        assert_eq!(edit.offset(1), Some(1));
+        assert_eq!(edit.offset(5), Some(9)); // the w of two
+        assert_eq!(edit.offset(8), None); // three was changed
+        assert_eq!(edit.line(1), Some(2));
+    }
+
+    #[test]
+    fn inserted_line_moves_later_code() {
+        let new = OLD.replace("    jal f\n", "    li $t2, 2\n    jal f\n");
+        let (old, new, map) = map(OLD, &new);
+
+        let (old_main, new_main) = (old.labels["main"], new.labels["main"]);
+        let (old_f, new_f) = (old.labels["f"], new.labels["f"]);
+
+        assert_eq!(map.get(old_main), Some(new_main));
+        assert_eq!(map.get(old_main + 4), Some(new_main + 8)); // jal f
+        assert_eq!(map.get(old_f), Some(new_f));
+        assert!(map.is_old_code(old_f) && !map.is_old_code(0x10010000));
+    }
+
+    #[test]
+    fn changed_li_length_maps_its_start() {
+        // The li line itself is untouched, but its value grows past 16 bits.
+        let old_source = format!(".eqv VALUE 1\n{}", OLD.replace("li $t0, 1", "li $t0, VALUE"));
+        let new_source = old_source.replace(".eqv VALUE 1", ".eqv VALUE 0x12345678");
+        let (old, new, map) = map(&old_source, &new_source);
+
+        let (old_main, new_main) = (old.labels["main"], new.labels["main"]);
+
+        // li grew to lui and ori, only its start has a match, and the next line moves down by one.
+        assert_eq!(map.get(old_main), Some(new_main));
+        assert_eq!(map.get(old_main + 4), Some(new_main + 8));
+        assert_eq!(map.get(old.labels["f"]), Some(new.labels["f"]));
+    }
+
+    #[test]
+    fn return_address_follows_its_call() {
+        // A line added right after the call runs once f returns.
+        let new = OLD.replace("    jal f\n", "    jal f\n    li $t2, 2\n");
+        let (old, _, map) = map(OLD, &new);
+
+        let call = old.labels["main"] + 4;
+
+        assert_eq!(map.address(call + 4), Some(call + 4));
+        assert_eq!(map.get(call + 4), Some(call + 8));
+    }
+
+    #[test]
+    fn patch_moves_pc_ra_and_stack_return_slots() {
+        let edited = OLD.replace("main:\n", "main:\n    li $t2, 2\n");
+        let (old, new, map) = map(OLD, &edited);
+
+        let call = old.labels["main"] + 4;
+        let moved = |address: u32| address + 4;
+
+        // Inside f, called from main, with the return address also saved on the stack.
+        let mut state = state(&old);
+        state.registers.pc = old.labels["f"];
+        state.registers.set(RA, call + 4);
+        state.registers.set(SP, STACK - 8);
+        state.registers.set(RegisterName::T3, old.labels["f"]);
+        state.memory.set_u32(STACK - 4, call + 4).unwrap();
+        state.memory.set_u32(STACK - 8, 1234).unwrap();
+
+        let frames = [CallFrame { call_site: call, function: old.labels["f"], return_address: call + 4, sp: STACK }];
+        let report = patch_state(&mut state, &old, &new, &map, &frames, &PatchOptions::new());
+
+        assert!(report.applied);
+        assert_eq!(report.remapped, vec![
+            (CodeSlot::Pc, old.labels["f"], new.labels["f"]),
+            (CodeSlot::Register(RA), call + 4, moved(call + 4)),
+            (CodeSlot::Stack(STACK - 4), call + 4, moved(call + 4)),
+        ]);
+        assert_eq!(report.conflicts, vec![PatchConflict::Register { name: RegisterName::T3, address: old.labels["f"] }]);
+
+        assert_eq!(state.registers.pc, new.labels["f"]);
+        assert_eq!(state.registers.get(RA), moved(call + 4));
+        assert_eq!(state.memory.get_u32(STACK - 4), Ok(moved(call + 4)));
+        assert_eq!(state.memory.get_u32(STACK - 8), Ok(1234));
+        assert_eq!(state.memory.get_u32(new.labels["main"]), Ok(new.word_at(new.labels["main"]).unwrap()));
+    }
+
+    #[test]
+    fn removed_code_faults() {
+        let edited = OLD.replace("    addi $t1, $t1, 1\n", "");
+        let (old, new, map) = map(OLD, &edited);
+
+        let mut state = state(&old);
+        let report = patch_state(&mut state, &old, &new, &map, &[], &PatchOptions::new());
+
+        assert!(report.applied);
+
+        let end = new.text_end().unwrap();
+
+        assert_eq!(state.memory.get_u32(end), Ok(0xCCCCCCCC));
+        assert!(InstructionDecoder::decode(end, 0xCCCCCCCC).is_none());
+    }
+}
@@ -0,0 +1,870 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use crate::cpu::error::{Error, ErrorKind};
+use crate::cpu::memory::watched::BackupValue;
+use crate::cpu::memory::{Page, PageKind};
+use crate::cpu::state::Registers;
+use crate::execution::expression::Expression;
+use crate::execution::breakpoints::{Breakpoint, BreakpointId, Catchpoint, Condition, ConditionValue, Point, Watchpoint};
+use crate::execution::executor::ExecutorMode;
+use crate::execution::limits::Usage;
+use crate::execution::termination::Termination;
+use crate::execution::trackers::history::{MemoryEdit, RegisterEdit, SavedEntry, SavedHistory};
+use crate::unit::register::{RegisterId, RegisterName};
+
+const MAGIC: &[u8; 8] = b"TITANSAV";
+
+// Bumped when an existing chunk changes layout. New chunks don't need it, readers skip tags they don't know.
+pub const SAVE_VERSION: u32 = 1;
+
+const REGISTERS: &[u8; 4] = b"REGS";
+const EXECUTION: &[u8; 4] = b"EXEC";
+const PAGES: &[u8; 4] = b"PAGE";
+const POINTS: &[u8; 4] = b"BRKP";
+const CONSOLE: &[u8; 4] = b"CONS";
+const HISTORY: &[u8; 4] = b"HIST";
+const END: &[u8; 4] = b"END ";
+
+#[derive(Debug)]
+pub enum SaveError {
+    NotASave,
+    UnsupportedVersion(u32),
+    Corrupt(&'static str),
+    Io(io::Error),
+}
+
+impl From<io::Error> for SaveError {
+    fn from(value: io::Error) -> Self {
+        match value.kind() {
+            io::ErrorKind::UnexpectedEof => SaveError::Corrupt("the save ends early"),
+            _ => SaveError::Io(value)
+        }
+    }
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NotASave => write!(f, "Not a Titan save state"),
+            SaveError::UnsupportedVersion(version) =>
+                write!(f, "Save state version {version} is not supported (expected {SAVE_VERSION} or older)"),
+            SaveError::Corrupt(reason) => write!(f, "Corrupt save state: {reason}"),
+            SaveError::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+pub type Result<T> = std::result::Result<T, SaveError>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConsoleImage {
+    pub input: Vec<u8>,
+    pub cursor: usize, // input[..cursor] was read by the program
+    pub output: Vec<u8>,
+}
+
+// Everything needed to pick a run back up, see Executor::save_image.
+#[derive(Clone)]
+pub struct MachineImage {
+    pub registers: Registers,
+    pub mode: ExecutorMode,
+    pub usage: Usage,
+    pub termination: Termination,
+    pub pages: Vec<Page>,
+    pub next_breakpoint: BreakpointId,
+    pub points: Vec<(BreakpointId, Point)>,
+    pub console: Option<ConsoleImage>, // syscall input and output, when the frontend has a console
+    pub history: Option<SavedHistory>,
+}
+
+// PackBits: a control byte n < 128 is followed by n + 1 literal bytes, n > 128 repeats the next byte 257 - n times.
+fn pack(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![];
+    let mut index = 0;
+
+    while index < data.len() {
+        let run = data[index..].iter()
+            .take(128)
+            .take_while(|byte| **byte == data[index])
+            .count();
+
+        if run >= 3 {
+            result.push((257 - run) as u8);
+            result.push(data[index]);
+
+            index += run;
+
+            continue
+        }
+
+        let start = index;
+
+        while index < data.len() && index - start < 128 {
+            let repeats = data[index..].iter().take(3).filter(|byte| **byte == data[index]).count();
+
+            if index > start && repeats == 3 {
+                break
+            }
+
+            index += 1;
+        }
+
+        result.push((index - start - 1) as u8);
+        result.extend_from_slice(&data[start .. index]);
+    }
+
+    result
+}
+
+fn unpack(data: &[u8]) -> Result<Vec<u8>> {
+    let mut result = vec![];
+    let mut input = data;
+
+    while let Some((&control, rest)) = input.split_first() {
+        input = rest;
+
+        match control {
+            0 ..= 127 => {
+                let count = control as usize + 1;
+                let literal = input.get(.. count).ok_or(SaveError::Corrupt("page data ends early"))?;
+
+                result.extend_from_slice(literal);
+                input = &input[count..];
+            }
+            128 => { }
+            _ => {
+                let (&value, rest) = input.split_first().ok_or(SaveError::Corrupt("page data ends early"))?;
+
+                result.extend(std::iter::repeat_n(value, 257 - control as usize));
+                input = rest;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.write_u32::<LittleEndian>(bytes.len() as u32).unwrap();
+    out.extend_from_slice(bytes);
+}
+
+fn read_bytes(input: &mut &[u8]) -> Result<Vec<u8>> {
+    let length = input.read_u32::<LittleEndian>()? as usize;
+
+    if length > input.len() {
+        return Err(SaveError::Corrupt("the save ends early"))
+    }
+
+    let (bytes, rest) = input.split_at(length);
+    *input = rest;
+
+    Ok(bytes.to_vec())
+}
+
+fn read_string(input: &mut &[u8]) -> Result<String> {
+    String::from_utf8(read_bytes(input)?).map_err(|_| SaveError::Corrupt("invalid text"))
+}
+
+fn write_option_u32(out: &mut Vec<u8>, value: Option<u32>) {
+    out.push(value.is_some() as u8);
+    out.write_u32::<LittleEndian>(value.unwrap_or(0)).unwrap();
+}
+
+fn read_option_u32(input: &mut &[u8]) -> Result<Option<u32>> {
+    let present = input.read_u8()? != 0;
+    let value = input.read_u32::<LittleEndian>()?;
+
+    Ok(present.then_some(value))
+}
+
+// Enums derived with ToPrimitive, stored as a byte.
+fn write_enum<T: ToPrimitive>(out: &mut Vec<u8>, value: T) {
+    out.push(value.to_u8().unwrap())
+}
+
+fn read_enum<T: FromPrimitive>(input: &mut &[u8], what: &'static str) -> Result<T> {
+    T::from_u8(input.read_u8()?).ok_or(SaveError::Corrupt(what))
+}
+
+fn write_register(out: &mut Vec<u8>, register: RegisterId) {
+    out.push(match register {
+        RegisterId::Line(name) => name as u8,
+        RegisterId::Pc => 32,
+        RegisterId::Hi => 33,
+        RegisterId::Lo => 34,
+    })
+}
+
+fn read_register(input: &mut &[u8]) -> Result<RegisterId> {
+    Ok(match input.read_u8()? {
+        index @ 0 ..= 31 => RegisterId::Line(RegisterName::from(index)),
+        32 => RegisterId::Pc,
+        33 => RegisterId::Hi,
+        34 => RegisterId::Lo,
+        _ => return Err(SaveError::Corrupt("unknown register"))
+    })
+}
+
+fn write_error(out: &mut Vec<u8>, error: Error) {
+    write_enum(out, error.kind());
+
+    out.write_u32::<LittleEndian>(match error {
+        Error::MemoryAlign(value) | Error::MemoryUnmapped(value) | Error::CpuInvalid(value) => value,
+        _ => 0
+    }).unwrap();
+}
+
+fn read_error(input: &mut &[u8]) -> Result<Error> {
+    let kind: ErrorKind = read_enum(input, "unknown error")?;
+    let value = input.read_u32::<LittleEndian>()?;
+
+    Ok(match kind {
+        ErrorKind::MemoryAlign => Error::MemoryAlign(value),
+        ErrorKind::MemoryUnmapped => Error::MemoryUnmapped(value),
+        ErrorKind::CpuInvalid => Error::CpuInvalid(value),
+        ErrorKind::CpuTrap => Error::CpuTrap,
+        ErrorKind::CpuSyscall => Error::CpuSyscall,
+        ErrorKind::CpuBreak => Error::CpuBreak,
+    })
+}
+
+fn write_mode(out: &mut Vec<u8>, mode: ExecutorMode) {
+    match mode {
+        ExecutorMode::Running => out.push(0),
+        ExecutorMode::Invalid(error) => {
+            out.push(1);
+            write_error(out, error)
+        }
+        ExecutorMode::Paused => out.push(2),
+        ExecutorMode::Breakpoint => out.push(3),
+        ExecutorMode::LimitReached(limit) => {
+            out.push(4);
+            write_enum(out, limit)
+        }
+        ExecutorMode::Exited { code } => {
+            out.push(5);
+            out.write_u32::<LittleEndian>(code).unwrap()
+        }
+        ExecutorMode::Halted => out.push(6),
+    }
+}
+
+fn read_mode(input: &mut &[u8]) -> Result<ExecutorMode> {
+    Ok(match input.read_u8()? {
+        0 => ExecutorMode::Running,
+        1 => ExecutorMode::Invalid(read_error(input)?),
+        2 => ExecutorMode::Paused,
+        3 => ExecutorMode::Breakpoint,
+        4 => ExecutorMode::LimitReached(read_enum(input, "unknown limit")?),
+        5 => ExecutorMode::Exited { code: input.read_u32::<LittleEndian>()? },
+        6 => ExecutorMode::Halted,
+        _ => return Err(SaveError::Corrupt("unknown executor mode"))
+    })
+}
+
+fn write_point(out: &mut Vec<u8>, point: &Point) {
+    match point {
+        Point::Breakpoint(breakpoint) => {
+            out.push(0);
+            out.write_u32::<LittleEndian>(breakpoint.address).unwrap();
+
+            match &breakpoint.condition {
+                Some(condition) => {
+                    out.push(1);
+
+                    match &condition.value {
+                        ConditionValue::Register(register) => {
+                            out.push(0);
+                            write_register(out, *register)
+                        }
+                        ConditionValue::Memory { address, width } => {
+                            out.push(1);
+                            out.write_u32::<LittleEndian>(*address).unwrap();
+                            write_enum(out, *width)
+                        }
+                        // Labels are constants in the canonical text, so it parses back without the binary.
+                        ConditionValue::Expression(expression) => {
+                            out.push(2);
+                            write_bytes(out, expression.to_string().as_bytes())
+                        }
+                    }
+
+                    write_enum(out, condition.comparison);
+                    out.write_u32::<LittleEndian>(condition.operand).unwrap();
+                    out.push(condition.signed as u8);
+                }
+                None => out.push(0)
+            }
+
+            out.push(breakpoint.hit_count.is_some() as u8);
+            out.write_u64::<LittleEndian>(breakpoint.hit_count.unwrap_or(0)).unwrap();
+
+            out.push(breakpoint.log.is_some() as u8);
+            write_bytes(out, breakpoint.log.as_deref().unwrap_or_default().as_bytes());
+
+            out.write_u64::<LittleEndian>(breakpoint.hits).unwrap();
+        }
+        Point::Catchpoint(catchpoint) => {
+            out.push(1);
+
+            match catchpoint {
+                Catchpoint::Syscall(number) => {
+                    out.push(0);
+                    write_option_u32(out, *number)
+                }
+                Catchpoint::Trap => out.push(1),
+                Catchpoint::Error(kind) => {
+                    out.push(2);
+                    write_enum(out, *kind)
+                }
+                Catchpoint::Mnemonic(name) => {
+                    out.push(3);
+                    write_bytes(out, name.as_bytes())
+                }
+                Catchpoint::Class(class) => {
+                    out.push(4);
+                    write_enum(out, *class)
+                }
+            }
+        }
+        Point::Watchpoint(watchpoint) => {
+            out.push(2);
+
+            match watchpoint {
+                Watchpoint::Memory { address, length, kind } => {
+                    out.push(0);
+                    out.write_u32::<LittleEndian>(*address).unwrap();
+                    out.write_u32::<LittleEndian>(*length).unwrap();
+                    write_enum(out, *kind)
+                }
+                Watchpoint::Register(register) => {
+                    out.push(1);
+                    write_register(out, *register)
+                }
+            }
+        }
+    }
+}
+
+fn read_point(input: &mut &[u8]) -> Result<Point> {
+    Ok(match input.read_u8()? {
+        0 => {
+            let mut breakpoint = Breakpoint::new(input.read_u32::<LittleEndian>()?);
+
+            if input.read_u8()? != 0 {
+                let value = match input.read_u8()? {
+                    0 => ConditionValue::Register(read_register(input)?),
+                    1 => ConditionValue::Memory {
+                        address: input.read_u32::<LittleEndian>()?,
+                        width: read_enum(input, "unknown width")?
+                    },
+                    2 => ConditionValue::Expression(Expression::parse(&read_string(input)?, |_| None)
+                        .map_err(|_| SaveError::Corrupt("invalid condition expression"))?),
+                    _ => return Err(SaveError::Corrupt("unknown condition"))
+                };
+
+                breakpoint.condition = Some(Condition {
+                    value,
+                    comparison: read_enum(input, "unknown comparison")?,
+                    operand: input.read_u32::<LittleEndian>()?,
+                    signed: input.read_u8()? != 0,
+                })
+            }
+
+            let has_hit_count = input.read_u8()? != 0;
+            let hit_count = input.read_u64::<LittleEndian>()?;
+            breakpoint.hit_count = has_hit_count.then_some(hit_count);
+
+            let has_log = input.read_u8()? != 0;
+            let log = read_string(input)?;
+            breakpoint.log = has_log.then_some(log);
+
+            breakpoint.hits = input.read_u64::<LittleEndian>()?;
+
+            Point::Breakpoint(breakpoint)
+        }
+        1 => Point::Catchpoint(match input.read_u8()? {
+            0 => Catchpoint::Syscall(read_option_u32(input)?),
+            1 => Catchpoint::Trap,
+            2 => Catchpoint::Error(read_enum(input, "unknown error")?),
+            3 => Catchpoint::Mnemonic(read_string(input)?),
+            4 => Catchpoint::Class(read_enum(input, "unknown instruction class")?),
+            _ => return Err(SaveError::Corrupt("unknown catchpoint"))
+        }),
+        2 => Point::Watchpoint(match input.read_u8()? {
+            0 => Watchpoint::Memory {
+                address: input.read_u32::<LittleEndian>()?,
+                length: input.read_u32::<LittleEndian>()?,
+                kind: read_enum(input, "unknown watch kind")?
+            },
+            1 => Watchpoint::Register(read_register(input)?),
+            _ => return Err(SaveError::Corrupt("unknown watchpoint"))
+        }),
+        _ => return Err(SaveError::Corrupt("unknown breakpoint kind"))
+    })
+}
+
+fn write_backup(out: &mut Vec<u8>, value: &BackupValue) {
+    let (tag, value) = match value {
+        BackupValue::Null => (0, 0),
+        BackupValue::Byte(value) => (1, *value as u32),
+        BackupValue::Short(value) => (2, *value as u32),
+        BackupValue::Word(value) => (3, *value),
+    };
+
+    out.push(tag);
+    out.write_u32::<LittleEndian>(value).unwrap();
+}
+
+fn read_backup(input: &mut &[u8]) -> Result<BackupValue> {
+    let tag = input.read_u8()?;
+    let value = input.read_u32::<LittleEndian>()?;
+
+    Ok(match tag {
+        0 => BackupValue::Null,
+        1 => BackupValue::Byte(value as u8),
+        2 => BackupValue::Short(value as u16),
+        3 => BackupValue::Word(value),
+        _ => return Err(SaveError::Corrupt("unknown memory edit"))
+    })
+}
+
+fn write_history(out: &mut Vec<u8>, history: &SavedHistory) {
+    out.write_u64::<LittleEndian>(history.start).unwrap();
+    out.write_u64::<LittleEndian>(history.cursor as u64).unwrap();
+    out.write_u32::<LittleEndian>(history.entries.len() as u32).unwrap();
+
+    for entry in &history.entries {
+        out.write_u32::<LittleEndian>(entry.pc).unwrap();
+        out.write_u32::<LittleEndian>(entry.next_pc).unwrap();
+
+        out.push(entry.registers.len() as u8);
+
+        for edit in &entry.registers {
+            write_register(out, edit.register);
+            out.write_u32::<LittleEndian>(edit.old).unwrap();
+            out.write_u32::<LittleEndian>(edit.new).unwrap();
+        }
+
+        out.write_u32::<LittleEndian>(entry.edits.len() as u32).unwrap();
+
+        for edit in &entry.edits {
+            out.write_u32::<LittleEndian>(edit.address).unwrap();
+            write_backup(out, &edit.old);
+            write_backup(out, &edit.new);
+        }
+    }
+}
+
+fn read_history(input: &mut &[u8]) -> Result<SavedHistory> {
+    let start = input.read_u64::<LittleEndian>()?;
+    let cursor = input.read_u64::<LittleEndian>()? as usize;
+    let count = input.read_u32::<LittleEndian>()?;
+
+    let mut entries = vec![];
+
+    for _ in 0 .. count {
+        let pc = input.read_u32::<LittleEndian>()?;
+        let next_pc = input.read_u32::<LittleEndian>()?;
+
+        let mut registers = vec![];
+
+        for _ in 0 .. input.read_u8()? {
+            registers.push(RegisterEdit {
+                register: read_register(input)?,
+                old: input.read_u32::<LittleEndian>()?,
+                new: input.read_u32::<LittleEndian>()?,
+            })
+        }
+
+        let mut edits = vec![];
+
+        for _ in 0 .. input.read_u32::<LittleEndian>()? {
+            edits.push(MemoryEdit {
+                address: input.read_u32::<LittleEndian>()?,
+                old: read_backup(input)?,
+                new: read_backup(input)?,
+            })
+        }
+
+        entries.push(SavedEntry { pc, next_pc, registers, edits })
+    }
+
+    Ok(SavedHistory { start, cursor, entries })
+}
+
+fn write_chunk(out: &mut Vec<u8>, tag: &[u8; 4], payload: Vec<u8>) {
+    out.extend_from_slice(tag);
+    write_bytes(out, &payload)
+}
+
+impl MachineImage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.write_u32::<LittleEndian>(SAVE_VERSION).unwrap();
+
+        let mut registers = vec![];
+        registers.write_u32::<LittleEndian>(self.registers.pc).unwrap();
+
+        for value in self.registers.line.iter().chain([&self.registers.hi, &self.registers.lo]) {
+            registers.write_u32::<LittleEndian>(*value).unwrap();
+        }
+
+        write_chunk(&mut out, REGISTERS, registers);
+
+        let mut execution = vec![];
+        write_mode(&mut execution, self.mode);
+        execution.write_u64::<LittleEndian>(self.usage.instructions).unwrap();
+        execution.write_u64::<LittleEndian>(self.usage.output_bytes).unwrap();
+        execution.write_u64::<LittleEndian>(self.usage.syscalls).unwrap();
+        execution.push(self.termination.exit_syscall as u8 | (self.termination.exit2_syscall as u8) << 1);
+        write_option_u32(&mut execution, self.termination.end_of_text);
+
+        write_chunk(&mut out, EXECUTION, execution);
+
+        let mut pages = vec![];
+        pages.write_u32::<LittleEndian>(self.pages.len() as u32).unwrap();
+
+        for page in &self.pages {
+            pages.write_u32::<LittleEndian>(page.address).unwrap();
+
+            match &page.kind {
+                PageKind::Data(data) => {
+                    pages.push(0);
+                    write_bytes(&mut pages, &pack(data))
+                }
+                PageKind::Filled(value) => {
+                    pages.push(1);
+                    pages.push(*value)
+                }
+                PageKind::Device => pages.push(2)
+            }
+        }
+
+        write_chunk(&mut out, PAGES, pages);
+
+        let mut points = vec![];
+        points.write_u64::<LittleEndian>(self.next_breakpoint as u64).unwrap();
+        points.write_u32::<LittleEndian>(self.points.len() as u32).unwrap();
+
+        for (id, point) in &self.points {
+            points.write_u64::<LittleEndian>(*id as u64).unwrap();
+            write_point(&mut points, point)
+        }
+
+        write_chunk(&mut out, POINTS, points);
+
+        if let Some(console) = &self.console {
+            let mut payload = vec![];
+            write_bytes(&mut payload, &console.input);
+            payload.write_u64::<LittleEndian>(console.cursor as u64).unwrap();
+            write_bytes(&mut payload, &console.output);
+
+            write_chunk(&mut out, CONSOLE, payload);
+        }
+
+        if let Some(history) = &self.history {
+            let mut payload = vec![];
+            write_history(&mut payload, history);
+
+            write_chunk(&mut out, HISTORY, payload);
+        }
+
+        write_chunk(&mut out, END, vec![]);
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MachineImage> {
+        let mut input = bytes;
+
+        if input.len() < MAGIC.len() || &input[.. MAGIC.len()] != MAGIC {
+            return Err(SaveError::NotASave)
+        }
+
+        input = &input[MAGIC.len()..];
+
+        let version = input.read_u32::<LittleEndian>()?;
+
+        if version == 0 || version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version))
+        }
+
+        let mut registers = None;
+        let mut image = MachineImage {
+            registers: Registers::new(0),
+            mode: ExecutorMode::Paused,
+            usage: Usage::default(),
+            termination: Termination::default(),
+            pages: vec![],
+            next_breakpoint: 0,
+            points: vec![],
+            console: None,
+            history: None,
+        };
+
+        loop {
+            let mut tag = [0u8; 4];
+            input.read_exact(&mut tag)?;
+
+            let payload = read_bytes(&mut input)?;
+            let mut chunk = payload.as_slice();
+
+            match &tag {
+                REGISTERS => {
+                    let mut values = [0u32; 35];
+                    chunk.read_u32_into::<LittleEndian>(&mut values)?;
+
+                    let mut result = Registers::new(values[0]);
+                    result.line.copy_from_slice(&values[1 .. 33]);
+                    result.hi = values[33];
+                    result.lo = values[34];
+
+                    registers = Some(result)
+                }
+                EXECUTION => {
+                    image.mode = read_mode(&mut chunk)?;
+                    image.usage = Usage {
+                        instructions: chunk.read_u64::<LittleEndian>()?,
+                        output_bytes: chunk.read_u64::<LittleEndian>()?,
+                        syscalls: chunk.read_u64::<LittleEndian>()?,
+                    };
+                    let exits = chunk.read_u8()?;
+
+                    image.termination = Termination {
+                        exit_syscall: exits & 1 != 0,
+                        exit2_syscall: exits & 2 != 0,
+                        end_of_text: read_option_u32(&mut chunk)?,
+                    };
+                }
+                PAGES => {
+                    for _ in 0 .. chunk.read_u32::<LittleEndian>()? {
+                        let address = chunk.read_u32::<LittleEndian>()?;
+
+                        let kind = match chunk.read_u8()? {
+                            0 => PageKind::Data(unpack(&read_bytes(&mut chunk)?)?),
+                            1 => PageKind::Filled(chunk.read_u8()?),
+                            2 => PageKind::Device,
+                            _ => return Err(SaveError::Corrupt("unknown page kind"))
+                        };
+
+                        image.pages.push(Page { address, kind })
+                    }
+                }
+                POINTS => {
+                    image.next_breakpoint = chunk.read_u64::<LittleEndian>()? as BreakpointId;
+
+                    for _ in 0 .. chunk.read_u32::<LittleEndian>()? {
+                        let id = chunk.read_u64::<LittleEndian>()? as BreakpointId;
+
+                        image.points.push((id, read_point(&mut chunk)?))
+                    }
+                }
+                CONSOLE => {
+                    image.console = Some(ConsoleImage {
+                        input: read_bytes(&mut chunk)?,
+                        cursor: chunk.read_u64::<LittleEndian>()? as usize,
+                        output: read_bytes(&mut chunk)?,
+                    })
+                }
+                HISTORY => image.history = Some(read_history(&mut chunk)?),
+                END => break,
+                _ => { }
+            }
+        }
+
+        image.registers = registers.ok_or(SaveError::Corrupt("no registers"))?;
+
+        Ok(image)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<MachineImage> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).map_err(SaveError::Io)?;
+
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::memory::Width;
+    use crate::execution::breakpoints::{Comparison, WatchKind};
+    use super::*;
+
+    fn image() -> MachineImage {
+        let mut registers = Registers::new(0x400010);
+        registers.line[2] = 10;
+        registers.line[29] = 0x7FFFEFFC;
+        registers.hi = 0xDEADBEEF;
+        registers.lo = 7;
+
+        let mut data = vec![0u8; 0x10000];
+        data[.. 5].copy_from_slice(b"hello");
+        data[0x8000 .. 0x8200].fill(0xAB);
+
+        let expression = Expression::parse("$a0 + 4 == 12", |_| None).unwrap();
+
+        let points = vec![
+            (0, Point::Breakpoint(Breakpoint::new(0x400000)
+                .with_condition(Condition::register(RegisterId::Line(RegisterName::T0), Comparison::Less, 3).signed())
+                .with_hit_count(2))),
+            (1, Point::Breakpoint(Breakpoint::new(0x400008).with_log("t0 = {$t0}".into()))),
+            (2, Point::Breakpoint(Breakpoint::new(0x40000C).with_condition(Condition::memory(0x10010000, Width::Half, Comparison::Equal, 9)))),
+            (3, Point::Breakpoint(Breakpoint::new(0x400010).with_condition(Condition::expression(expression)))),
+            (5, Point::Catchpoint(Catchpoint::Syscall(Some(4)))),
+            (6, Point::Catchpoint(Catchpoint::Error(ErrorKind::MemoryUnmapped))),
+            (7, Point::Catchpoint(Catchpoint::Mnemonic("jal".into()))),
+            (8, Point::Watchpoint(Watchpoint::Memory { address: 0x10010000, length: 4, kind: WatchKind::Access })),
+            (9, Point::Watchpoint(Watchpoint::Register(RegisterId::Hi))),
+        ];
+
+        let history = SavedHistory {
+            start: 3,
+            cursor: 1,
+            entries: vec![
+                SavedEntry {
+                    pc: 0x400008,
+                    next_pc: 0x40000C,
+                    registers: vec![RegisterEdit { register: RegisterId::Line(RegisterName::T0), old: 1, new: 2 }],
+                    edits: vec![
+                        MemoryEdit { address: 0x10010000, old: BackupValue::Word(1), new: BackupValue::Word(2) },
+                        MemoryEdit { address: 0x10010004, old: BackupValue::Byte(3), new: BackupValue::Byte(4) },
+                    ],
+                },
+                SavedEntry {
+                    pc: 0x40000C,
+                    next_pc: 0x400010,
+                    registers: vec![],
+                    edits: vec![MemoryEdit { address: 0x10010006, old: BackupValue::Short(5), new: BackupValue::Null }],
+                },
+            ],
+        };
+
+        MachineImage {
+            registers,
+            mode: ExecutorMode::Exited { code: 3 },
+            usage: Usage { instructions: 1234, output_bytes: 56, syscalls: 7 },
+            termination: Termination::default().with_exit_syscall(17, false).with_end_of_text(0x400020),
+            pages: vec![
+                Page { address: 0x10010000, kind: PageKind::Data(data) },
+                Page { address: 0x10020000, kind: PageKind::Filled(0xCC) },
+                Page { address: 0xFFFF0000, kind: PageKind::Device },
+            ],
+            next_breakpoint: 10,
+            points,
+            console: Some(ConsoleImage { input: b"12\nab\n".to_vec(), cursor: 3, output: b"12".to_vec() }),
+            history: Some(history),
+        }
+    }
+
+    fn backup(value: &BackupValue) -> Option<u32> {
+        match value {
+            BackupValue::Byte(value) => Some(*value as u32),
+            BackupValue::Short(value) => Some(*value as u32 | 0x10000),
+            BackupValue::Word(value) => Some(*value),
+            BackupValue::Null => None
+        }
+    }
+
+    #[test]
+    fn pack_round_trips() {
+        let mut mixed = vec![1, 2, 3, 3, 3, 3, 4, 5];
+        mixed.extend(std::iter::repeat_n(9, 300));
+        mixed.extend((0 .. 300).map(|i| i as u8));
+
+        for data in [vec![], vec![7], vec![0; 0x10000], mixed] {
+            assert_eq!(unpack(&pack(&data)).unwrap(), data);
+        }
+
+        // Each run of 128 bytes takes two.
+        assert_eq!(pack(&[0; 0x10000]).len(), 0x10000 / 128 * 2);
+    }
+
+    #[test]
+    fn unpack_rejects_truncated_literals() {
+        assert!(matches!(unpack(&[4, 1, 2]), Err(SaveError::Corrupt(_))));
+    }
+
+    #[test]
+    fn image_round_trips() {
+        let image = image();
+        let bytes = image.to_bytes();
+        let read = MachineImage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(read.registers.pc, image.registers.pc);
+        assert_eq!(read.registers.line, image.registers.line);
+        assert_eq!((read.registers.hi, read.registers.lo), (image.registers.hi, image.registers.lo));
+        assert_eq!(read.mode, image.mode);
+        assert_eq!(read.usage, image.usage);
+        assert_eq!(read.termination, image.termination);
+        assert_eq!(read.pages, image.pages);
+        assert_eq!(read.next_breakpoint, image.next_breakpoint);
+        assert_eq!(format!("{:?}", read.points), format!("{:?}", image.points));
+        assert_eq!(read.console, image.console);
+
+        let (history, expected) = (read.history.unwrap(), image.history.unwrap());
+
+        assert_eq!((history.start, history.cursor), (expected.start, expected.cursor));
+        assert_eq!(history.entries.len(), expected.entries.len());
+
+        for (entry, expected) in history.entries.iter().zip(&expected.entries) {
+            assert_eq!((entry.pc, entry.next_pc), (expected.pc, expected.next_pc));
+            assert_eq!(format!("{:?}", entry.registers), format!("{:?}", expected.registers));
+
+            let edits = |edits: &[MemoryEdit]| edits.iter()
+                .map(|edit| (edit.address, backup(&edit.old), backup(&edit.new)))
+                .collect::<Vec<_>>();
+
+            assert_eq!(edits(&entry.edits), edits(&expected.edits));
+        }
+
+        // Writing what was read gives the same bytes.
+        assert_eq!(MachineImage::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn optional_chunks_stay_empty() {
+        let image = MachineImage { console: None, history: None, ..image() };
+        let read = MachineImage::from_bytes(&image.to_bytes()).unwrap();
+
+        assert!(read.console.is_none());
+        assert!(read.history.is_none());
+    }
+
+    #[test]
+    fn unknown_chunks_are_skipped() {
+        let mut bytes = image().to_bytes();
+        let end = bytes.len() - 8; // END tag and its empty length
+
+        let mut extra = vec![];
+        write_chunk(&mut extra, b"NEW!", vec![1, 2, 3]);
+        bytes.splice(end .. end, extra);
+
+        assert_eq!(MachineImage::from_bytes(&bytes).unwrap().pages, image().pages);
+    }
+
+    #[test]
+    fn rejects_bad_saves() {
+        assert!(matches!(MachineImage::from_bytes(b"NOTASAVE"), Err(SaveError::NotASave)));
+
+        let mut bytes = image().to_bytes();
+        bytes[MAGIC.len() .. MAGIC.len() + 4].copy_from_slice(&(SAVE_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(MachineImage::from_bytes(&bytes), Err(SaveError::UnsupportedVersion(_))));
+
+        let bytes = image().to_bytes();
+
+        assert!(matches!(MachineImage::from_bytes(&bytes[.. bytes.len() / 2]), Err(SaveError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,87 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Longest sleep before checking for a pause again.
+const SLICE: Duration = Duration::from_millis(10);
+
+// Falling further behind than this (ex. while a syscall waits for input) starts the clock over,
+// instead of running a burst of instructions to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Speed {
+    #[default]
+    Unlimited,
+    PerSecond(u32), // instructions per second, 0 runs as 1
+}
+
+impl Speed {
+    pub fn rate(&self) -> Option<u32> {
+        match self {
+            Speed::Unlimited => None,
+            Speed::PerSecond(rate) => Some((*rate).max(1)),
+        }
+    }
+
+    // Instructions to run between waits, about a hundred batches per second when paced.
+    pub fn batch(&self, unlimited: usize) -> usize {
+        match self.rate() {
+            None => unlimited,
+            Some(rate) => (rate as usize / 100).clamp(1, unlimited.max(1)),
+        }
+    }
+}
+
+// Spaces out batches to hold a Speed.
+// Time is measured by instructions executed, so the rate does not drift with the cost of each batch.
+pub struct Pacer {
+    speed: Speed,
+    start: Instant,
+    executed: u64,
+}
+
+impl Pacer {
+    pub fn new(speed: Speed) -> Pacer {
+        Pacer { speed, start: Instant::now(), executed: 0 }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    // For a speed change while running, the clock starts over at the new rate.
+    pub fn reset(&mut self, speed: Speed) {
+        *self = Pacer::new(speed)
+    }
+
+    // Counts the instructions of the last batch and sleeps until they are due.
+    // Returns false without waiting out the rest once running returns false (ex. the executor was paused or the speed changed).
+    pub fn wait<F: FnMut() -> bool>(&mut self, executed: u64, mut running: F) -> bool {
+        let Some(rate) = self.speed.rate() else { return true };
+
+        self.executed += executed;
+
+        let deadline = self.start + Duration::from_secs_f64(self.executed as f64 / rate as f64);
+        let now = Instant::now();
+
+        if now > deadline + MAX_LAG {
+            self.reset(self.speed);
+
+            return true
+        }
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if remaining.is_zero() {
+                break
+            }
+
+            if !running() {
+                return false
+            }
+
+            thread::sleep(remaining.min(SLICE));
+        }
+
+        true
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::assembler::binary::Binary;
+use crate::assembler::lexer::Location;
+use crate::cpu::{Memory, State};
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::unit::register::RegisterName::SP;
+
+// The source statement that emitted an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub location: Location, // the definition site for instructions from a macro body
+    pub expansion: Option<Location>, // the macro call
+    pub start: u32, // first pc of the statement
+    pub expansion_start: u32, // first pc of the whole macro expansion, start outside of macros
+}
+
+// pc -> statement, so a pseudo-instruction or a macro call can be stepped as a whole.
+#[derive(Clone, Debug)]
+pub struct StatementMap {
+    statements: HashMap<u32, Statement>,
+}
+
+// Runs until the start of another statement, or until execution leaves the assembled code.
+#[derive(Clone, Debug)]
+pub struct LineStep {
+    map: Arc<StatementMap>,
+    over_calls: bool,
+    into_macros: bool,
+    callee: Option<(u32, u32)>, // return address and sp of the call being stepped over
+}
+
+#[derive(Clone, Debug)]
+pub enum StepTarget {
+    // Return to address with the stack at least as high as sp (handles recursion).
+    Over { address: u32, sp: u32 },
+    // Leave the current function through jr $ra, stopping before the jr if stop_before_return.
+    Out { depth: usize, stop_before_return: bool },
+    Line(LineStep),
+}
+
+pub fn decode_at<Mem: Memory>(state: &State<Mem>) -> Option<Instruction> {
+    let pc = state.registers.pc;
+
+    state.memory.get_u32(pc).ok()
+        .and_then(|value| InstructionDecoder::decode(pc, value))
+}
+
+impl Statement {
+    // Where a frontend should point. Macro calls are one statement unless stepping into macros.
+    pub fn location(&self, into_macros: bool) -> Location {
+        if into_macros { self.location } else { self.expansion.unwrap_or(self.location) }
+    }
+
+    pub fn start(&self, into_macros: bool) -> u32 {
+        if into_macros { self.start } else { self.expansion_start }
+    }
+}
+
+impl StatementMap {
+    pub fn new(binary: &Binary) -> StatementMap {
+        let mut expansions: HashMap<Location, u32> = HashMap::new();
+
+        for breakpoint in &binary.breakpoints {
+            if let (Some(expansion), Some(start)) = (breakpoint.expansion, breakpoint.pcs.first()) {
+                let value = expansions.entry(expansion).or_insert(*start);
+
+                *value = (*value).min(*start);
+            }
+        }
+
+        let mut statements = HashMap::new();
+
+        for breakpoint in &binary.breakpoints {
+            let Some(start) = breakpoint.pcs.first().copied() else { continue };
+
+            let statement = Statement {
+                location: breakpoint.location,
+                expansion: breakpoint.expansion,
+                start,
+                expansion_start: breakpoint.expansion
+                    .and_then(|expansion| expansions.get(&expansion).copied())
+                    .unwrap_or(start),
+            };
+
+            for pc in &breakpoint.pcs {
+                statements.entry(*pc).or_insert(statement);
+            }
+        }
+
+        StatementMap { statements }
+    }
+
+    pub fn statement(&self, pc: u32) -> Option<Statement> {
+        self.statements.get(&pc).copied()
+    }
+}
+
+impl LineStep {
+    fn after<Mem: Memory>(&mut self, instruction: Option<&Instruction>, start: u32, state: &State<Mem>) -> bool {
+        let pc = state.registers.pc;
+
+        if let Some((address, sp)) = self.callee {
+            if pc != address || state.registers.get(SP) < sp {
+                return false
+            }
+
+            self.callee = None;
+        } else if self.over_calls
+            && instruction.is_some_and(|instruction| instruction.is_call())
+            && pc != start.wrapping_add(4) {
+            self.callee = Some((start.wrapping_add(4), state.registers.get(SP)));
+
+            return false
+        }
+
+        // Jumping back to the start of the same statement (ex. a loop on one line) also stops.
+        self.map.statement(pc)
+            .is_none_or(|statement| statement.start(self.into_macros) == pc)
+    }
+}
+
+impl StepTarget {
+    // None if the instruction at pc is not a call, in which case a single step is enough.
+    pub fn over<Mem: Memory>(state: &State<Mem>) -> Option<StepTarget> {
+        let instruction = decode_at(state)?;
+
+        instruction.is_call().then(|| StepTarget::Over {
+            address: state.registers.pc.wrapping_add(4),
+            sp: state.registers.get(SP)
+        })
+    }
+
+    pub fn out() -> StepTarget {
+        StepTarget::Out { depth: 0, stop_before_return: false }
+    }
+
+    pub fn to_return() -> StepTarget {
+        StepTarget::Out { depth: 0, stop_before_return: true }
+    }
+
+    pub fn line(map: Arc<StatementMap>, over_calls: bool, into_macros: bool) -> StepTarget {
+        StepTarget::Line(LineStep { map, over_calls, into_macros, callee: None })
+    }
+
+    pub fn needs_instruction(&self) -> bool {
+        match self {
+            StepTarget::Out { .. } => true,
+            StepTarget::Line(step) => step.over_calls,
+            StepTarget::Over { .. } => false,
+        }
+    }
+
+    // Checked before the instruction runs. True if the target is reached.
+    pub fn before(&self, instruction: Option<&Instruction>) -> bool {
+        match self {
+            StepTarget::Out { depth: 0, stop_before_return: true } =>
+                instruction.is_some_and(|instruction| instruction.is_return()),
+            _ => false
+        }
+    }
+
+    // Checked after the instruction at start executed. True if the target is reached.
+    pub fn after<Mem: Memory>(&mut self, instruction: Option<&Instruction>, start: u32, state: &State<Mem>) -> bool {
+        match self {
+            StepTarget::Over { address, sp } => {
+                state.registers.pc == *address && state.registers.get(SP) >= *sp
+            }
+            StepTarget::Out { depth, .. } => {
+                let Some(instruction) = instruction else { return false };
+
+                if instruction.is_return() {
+                    if *depth == 0 {
+                        return true
+                    }
+
+                    *depth -= 1;
+                } else if instruction.is_call() && state.registers.pc != start.wrapping_add(4) {
+                    *depth += 1;
+                }
+
+                false
+            }
+            StepTarget::Line(step) => step.after(instruction, start, state),
+        }
+    }
+}
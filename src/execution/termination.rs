@@ -0,0 +1,76 @@
+use crate::assembler::binary::Binary;
+use crate::cpu::error::Error;
+use crate::cpu::state::Registers;
+use crate::execution::executor::ExecutorMode;
+use crate::unit::register::RegisterName::{A0, V0};
+
+pub const EXIT_SYSCALL: u32 = 10;
+pub const EXIT2_SYSCALL: u32 = 17; // exit with the code in $a0
+
+// How a program ends without faulting. A break instruction always halts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Termination {
+    // Stop as Exited instead of leaving the syscall to the frontend.
+    pub exit_syscall: bool, // 10
+    pub exit2_syscall: bool, // 17
+    pub end_of_text: Option<u32>, // reaching this pc halts, instead of faulting on the fetch
+}
+
+impl Default for Termination {
+    fn default() -> Self {
+        Termination { exit_syscall: true, exit2_syscall: true, end_of_text: None }
+    }
+}
+
+impl Termination {
+    pub fn new() -> Termination {
+        Self::default()
+    }
+
+    // Exit syscalls, and the end of the text with the entry point.
+    pub fn for_binary(binary: &Binary) -> Termination {
+        Termination { exit_syscall: true, exit2_syscall: true, end_of_text: binary.text_end() }
+    }
+
+    pub fn with_exit_syscalls(mut self, enabled: bool) -> Self {
+        self.exit_syscall = enabled;
+        self.exit2_syscall = enabled;
+
+        self
+    }
+
+    // Only for one of EXIT_SYSCALL or EXIT2_SYSCALL, other values are ignored.
+    pub fn with_exit_syscall(mut self, v0: u32, enabled: bool) -> Self {
+        match v0 {
+            EXIT_SYSCALL => self.exit_syscall = enabled,
+            EXIT2_SYSCALL => self.exit2_syscall = enabled,
+            _ => {}
+        }
+
+        self
+    }
+
+    // Usually the address right after the last instruction of .text, see Binary::text_end.
+    pub fn with_end_of_text(mut self, address: u32) -> Self {
+        self.end_of_text = Some(address);
+
+        self
+    }
+
+    pub fn at_end(&self, pc: u32) -> bool {
+        self.end_of_text == Some(pc)
+    }
+
+    // The mode for an instruction that failed with error, None if it is a fault.
+    pub fn check(&self, error: Error, registers: &Registers) -> Option<ExecutorMode> {
+        match error {
+            Error::CpuBreak => Some(ExecutorMode::Halted),
+            Error::CpuSyscall => match registers.get(V0) {
+                EXIT_SYSCALL if self.exit_syscall => Some(ExecutorMode::Exited { code: 0 }),
+                EXIT2_SYSCALL if self.exit2_syscall => Some(ExecutorMode::Exited { code: registers.get(A0) }),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
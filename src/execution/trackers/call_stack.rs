@@ -0,0 +1,253 @@
+use std::fmt::{Display, Formatter};
+use crate::assembler::binary::Binary;
+use crate::assembler::lexer::Location;
+use crate::assembler::line_details::LineDetails;
+use crate::cpu::state::Registers;
+use crate::cpu::{Memory, State};
+use crate::execution::patch::PcMap;
+use crate::execution::stepping::decode_at;
+use crate::execution::trackers::Tracker;
+use crate::unit::instruction::Instruction;
+use crate::unit::register::RegisterName::{RA, SP};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub call_site: u32, // pc of the calling instruction
+    pub function: u32, // address that was called
+    pub return_address: u32,
+    pub sp: u32, // $sp when the call was made
+}
+
+pub struct CallStackTracker {
+    entry: u32,
+    frames: Vec<CallFrame>,
+    pending: Option<(u32, Instruction)>,
+}
+
+impl CallStackTracker {
+    pub fn new(entry: u32) -> CallStackTracker {
+        CallStackTracker { entry, frames: vec![], pending: None }
+    }
+
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.pending = None;
+    }
+
+    // Moves the frames to the new code after a hot patch. Addresses without a match are kept.
+    pub fn remap(&mut self, map: &PcMap) {
+        let moved = |address: u32, found: Option<u32>| {
+            if map.is_old_code(address) { found.unwrap_or(address) } else { address }
+        };
+
+        self.entry = moved(self.entry, map.get(self.entry));
+
+        for frame in &mut self.frames {
+            frame.call_site = moved(frame.call_site, map.get(frame.call_site));
+            frame.function = moved(frame.function, map.get(frame.function));
+            frame.return_address = moved(frame.return_address, map.address(frame.return_address));
+        }
+
+        self.pending = None;
+    }
+
+    // Innermost frame first.
+    pub fn backtrace(&self, registers: &Registers, binary: &Binary) -> Backtrace {
+        let mut frames = vec![];
+
+        let mut pc = registers.pc;
+        let mut sp = registers.get(SP);
+
+        for frame in self.frames.iter().rev() {
+            frames.push(BacktraceFrame::new(pc, frame.function, sp, binary));
+
+            pc = frame.call_site;
+            sp = frame.sp;
+        }
+
+        frames.push(BacktraceFrame::new(pc, self.entry, sp, binary));
+
+        Backtrace { frames }
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for CallStackTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        self.pending = decode_at(state)
+            .filter(|instruction| instruction.is_call() || instruction.is_return())
+            .map(|instruction| (state.registers.pc, instruction));
+    }
+
+    fn code_patched(&mut self, map: &PcMap) {
+        self.remap(map)
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let Some((start, instruction)) = self.pending.take() else { return };
+
+        let pc = state.registers.pc;
+
+        if instruction.is_return() {
+            // Unwind to the frame we returned to, frames without a matching return are left alone.
+            if let Some(index) = self.frames.iter().rposition(|frame| frame.return_address == pc) {
+                self.frames.truncate(index);
+            }
+        } else if pc != start.wrapping_add(4) {
+            self.frames.push(CallFrame {
+                call_site: start,
+                function: pc,
+                return_address: state.registers.get(RA),
+                sp: state.registers.get(SP),
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BacktraceFrame {
+    pub pc: u32,
+    pub function: u32,
+    pub sp: u32,
+    pub label: Option<(String, u32)>, // closest label and offset
+    pub location: Option<Location>,
+}
+
+impl BacktraceFrame {
+    fn new(pc: u32, function: u32, sp: u32, binary: &Binary) -> BacktraceFrame {
+        let label = binary.label_before(pc)
+            .map(|(name, offset)| (name.to_string(), offset));
+
+        BacktraceFrame { pc, function, sp, label, location: binary.location_for(pc) }
+    }
+
+    pub fn symbol(&self) -> String {
+        match &self.label {
+            Some((name, 0)) => name.clone(),
+            Some((name, offset)) => format!("{name}+0x{offset:x}"),
+            None => format!("0x{:08x}", self.function),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Backtrace {
+    pub frames: Vec<BacktraceFrame>,
+}
+
+impl Backtrace {
+    // Like Display, but adds source lines. source returns the text for a source id.
+    pub fn describe<'a, F: Fn(usize) -> Option<&'a str>>(&self, source: F) -> String {
+        let mut result = String::new();
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            result.push_str(&format!("  #{index} 0x{:08x} in {}", frame.pc, frame.symbol()));
+
+            let line = frame.location
+                .and_then(|location| source(location.source).map(|text| (location, text)))
+                .map(|(location, text)| LineDetails::from_offset(text, location.index));
+
+            if let Some(line) = line {
+                result.push_str(&format!(" (line {}: {})", line.line_number + 1, line.line_text.trim()));
+            }
+
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.describe(|_| None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::{Mountable, Region};
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::execution::executor::ExecutorMode;
+    use crate::execution::Executor;
+    use super::*;
+
+    const SOURCE: &str = "main:
+    jal outer
+    li $v0, 10
+    syscall
+outer:
+    move $s0, $ra
+    jal inner
+    move $ra, $s0
+    jr $ra
+inner:
+    addu $t0, $t0, $t0
+    jr $ra
+";
+
+    // Runs until label + offset, returning the frames and backtrace there.
+    fn run_to(label: &str, offset: u32) -> (Binary, Vec<CallFrame>, Backtrace) {
+        let binary = assemble_from(SOURCE).unwrap();
+        let mut memory = SectionMemory::<DefaultResponder>::new();
+
+        for region in &binary.regions {
+            memory.mount(Region { start: region.address, data: region.data.clone() })
+        }
+
+        let executor = Executor::new(State::new(binary.entry, memory), CallStackTracker::new(binary.entry));
+        executor.set_breakpoints(HashSet::from([binary.labels[label] + offset]));
+        executor.override_mode(ExecutorMode::Running);
+
+        assert_eq!(executor.run(false).mode, ExecutorMode::Breakpoint);
+
+        let registers = executor.frame().registers;
+        let (frames, backtrace) = executor.with_tracker(|tracker| {
+            (tracker.frames().to_vec(), tracker.backtrace(&registers, &binary))
+        });
+
+        (binary, frames, backtrace)
+    }
+
+    #[test]
+    fn frames_follow_calls_and_returns() {
+        let (binary, frames, _) = run_to("inner", 0);
+        let (main, outer, inner) = (binary.labels["main"], binary.labels["outer"], binary.labels["inner"]);
+
+        assert_eq!(frames, vec![
+            CallFrame { call_site: main, function: outer, return_address: main + 4, sp: 0 },
+            CallFrame { call_site: outer + 4, function: inner, return_address: outer + 8, sp: 0 },
+        ]);
+
+        // Back in main after both returns.
+        let (_, frames, _) = run_to("main", 4);
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn backtrace_names_each_frame() {
+        let (binary, _, backtrace) = run_to("inner", 0);
+
+        let symbols: Vec<_> = backtrace.frames.iter().map(BacktraceFrame::symbol).collect();
+        assert_eq!(symbols, vec!["inner", "outer+0x4", "main"]);
+
+        let pcs: Vec<_> = backtrace.frames.iter().map(|frame| frame.pc).collect();
+        assert_eq!(pcs, vec![binary.labels["inner"], binary.labels["outer"] + 4, binary.labels["main"]]);
+
+        let text = backtrace.describe(|id| (id == 0).then_some(SOURCE));
+
+        assert_eq!(text, format!(
+            "  #0 0x{:08x} in inner (line 11: addu $t0, $t0, $t0)\n  #1 0x{:08x} in outer+0x4 (line 7: jal inner)\n  #2 0x{:08x} in main (line 2: jal outer)\n",
+            pcs[0], pcs[1], pcs[2]
+        ));
+    }
+}
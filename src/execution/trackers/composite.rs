@@ -0,0 +1,338 @@
+use std::any::Any;
+use crate::cpu::{Memory, State};
+use crate::cpu::error::Error;
+use crate::cpu::memory::Width;
+use crate::cpu::memory::effect::SideEffect;
+use crate::execution::patch::PcMap;
+use crate::execution::trackers::Tracker;
+use crate::unit::register::RegisterId;
+
+// Tuples run every tracker in order. Each tracker is asked to pause, so none keeps a stale request.
+macro_rules! tuple_tracker {
+    ($($name:ident $index:tt),+) => {
+        impl<Mem: Memory, $($name: Tracker<Mem>),+> Tracker<Mem> for ($($name,)+) {
+            fn pre_track(&mut self, state: &mut State<Mem>) {
+                $(self.$index.pre_track(state);)+
+            }
+
+            fn post_track(&mut self, state: &mut State<Mem>) {
+                $(self.$index.post_track(state);)+
+            }
+
+            fn track_effect(&mut self, effect: Box<dyn SideEffect>) {
+                $(
+                    if self.$index.wants_effects() {
+                        return self.$index.track_effect(effect)
+                    }
+                )+
+            }
+
+            fn wants_effects(&self) -> bool {
+                $(self.$index.wants_effects())||+
+            }
+
+            fn pause_requested(&mut self) -> bool {
+                let mut pause = false;
+
+                $(pause |= self.$index.pause_requested();)+
+
+                pause
+            }
+
+            fn code_patched(&mut self, map: &PcMap) {
+                $(self.$index.code_patched(map);)+
+            }
+
+            fn wants_hooks(&self) -> bool {
+                $(self.$index.wants_hooks())||+
+            }
+
+            fn on_fetch(&mut self, pc: u32, word: u32) {
+                $(if self.$index.wants_hooks() { self.$index.on_fetch(pc, word) })+
+            }
+
+            fn on_load(&mut self, address: u32, width: Width, value: u32) {
+                $(if self.$index.wants_hooks() { self.$index.on_load(address, width, value) })+
+            }
+
+            fn on_store(&mut self, address: u32, width: Width, old: u32, new: u32) {
+                $(if self.$index.wants_hooks() { self.$index.on_store(address, width, old, new) })+
+            }
+
+            fn on_register_write(&mut self, register: RegisterId, old: u32, new: u32) {
+                $(if self.$index.wants_hooks() { self.$index.on_register_write(register, old, new) })+
+            }
+
+            fn on_branch(&mut self, pc: u32, taken: bool, target: u32) {
+                $(if self.$index.wants_hooks() { self.$index.on_branch(pc, taken, target) })+
+            }
+
+            fn on_syscall(&mut self, state: &State<Mem>) {
+                $(if self.$index.wants_hooks() { self.$index.on_syscall(state) })+
+            }
+
+            fn on_fault(&mut self, pc: u32, error: Error) {
+                $(if self.$index.wants_hooks() { self.$index.on_fault(pc, error) })+
+            }
+        }
+    }
+}
+
+tuple_tracker!(A 0, B 1);
+tuple_tracker!(A 0, B 1, C 2);
+tuple_tracker!(A 0, B 1, C 2, D 3);
+
+// Boxed trackers in order, ex. Vec<Box<dyn Tracker<Mem> + Send>>. TrackerSet forwards here.
+impl<Mem: Memory, T: Tracker<Mem> + ?Sized> Tracker<Mem> for Vec<Box<T>> {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        for tracker in self.iter_mut() {
+            tracker.pre_track(state)
+        }
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        for tracker in self.iter_mut() {
+            tracker.post_track(state)
+        }
+    }
+
+    fn track_effect(&mut self, effect: Box<dyn SideEffect>) {
+        if let Some(tracker) = self.iter_mut().find(|tracker| tracker.wants_effects()) {
+            tracker.track_effect(effect)
+        }
+    }
+
+    fn wants_effects(&self) -> bool {
+        self.iter().any(|tracker| tracker.wants_effects())
+    }
+
+    fn pause_requested(&mut self) -> bool {
+        self.iter_mut().fold(false, |pause, tracker| tracker.pause_requested() | pause)
+    }
+
+    fn code_patched(&mut self, map: &PcMap) {
+        for tracker in self.iter_mut() {
+            tracker.code_patched(map)
+        }
+    }
+
+    fn wants_hooks(&self) -> bool {
+        self.iter().any(|tracker| tracker.wants_hooks())
+    }
+
+    fn on_fetch(&mut self, pc: u32, word: u32) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_fetch(pc, word)
+            }
+        }
+    }
+
+    fn on_load(&mut self, address: u32, width: Width, value: u32) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_load(address, width, value)
+            }
+        }
+    }
+
+    fn on_store(&mut self, address: u32, width: Width, old: u32, new: u32) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_store(address, width, old, new)
+            }
+        }
+    }
+
+    fn on_register_write(&mut self, register: RegisterId, old: u32, new: u32) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_register_write(register, old, new)
+            }
+        }
+    }
+
+    fn on_branch(&mut self, pc: u32, taken: bool, target: u32) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_branch(pc, taken, target)
+            }
+        }
+    }
+
+    fn on_syscall(&mut self, state: &State<Mem>) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_syscall(state)
+            }
+        }
+    }
+
+    fn on_fault(&mut self, pc: u32, error: Error) {
+        for tracker in self.iter_mut() {
+            if tracker.wants_hooks() {
+                tracker.on_fault(pc, error)
+            }
+        }
+    }
+}
+
+// A tracker that can be found again in a TrackerSet by its type.
+pub trait AnyTracker<Mem: Memory>: Tracker<Mem> + Send {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<Mem: Memory, T: Tracker<Mem> + Send + 'static> AnyTracker<Mem> for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrackerId(usize);
+
+// Trackers that can be added and removed while the executor is alive, through Executor::with_tracker.
+pub struct TrackerSet<Mem: Memory> {
+    ids: Vec<TrackerId>, // ids[i] belongs to trackers[i]
+    trackers: Vec<Box<dyn AnyTracker<Mem>>>,
+    next_id: usize,
+}
+
+impl<Mem: Memory> TrackerSet<Mem> {
+    pub fn new() -> TrackerSet<Mem> {
+        TrackerSet { ids: vec![], trackers: vec![], next_id: 0 }
+    }
+
+    pub fn add<T: Tracker<Mem> + Send + 'static>(&mut self, tracker: T) -> TrackerId {
+        let id = TrackerId(self.next_id);
+        self.next_id += 1;
+
+        self.ids.push(id);
+        self.trackers.push(Box::new(tracker));
+
+        id
+    }
+
+    // Tracking starts with the next instruction, a tracker added in the middle of one misses it.
+    pub fn with<T: Tracker<Mem> + Send + 'static>(mut self, tracker: T) -> Self {
+        self.add(tracker);
+
+        self
+    }
+
+    fn index(&self, id: TrackerId) -> Option<usize> {
+        self.ids.iter().position(|other| *other == id)
+    }
+
+    pub fn remove(&mut self, id: TrackerId) -> bool {
+        let Some(index) = self.index(id) else { return false };
+
+        self.ids.remove(index);
+        self.trackers.remove(index);
+
+        true
+    }
+
+    // Removes the tracker and hands it back, ex. to read a report. None if T is the wrong type.
+    pub fn take<T: 'static>(&mut self, id: TrackerId) -> Option<T> {
+        let index = self.index(id).filter(|index| self.trackers[*index].as_any().is::<T>())?;
+
+        self.ids.remove(index);
+        self.trackers.remove(index).into_any().downcast().ok().map(|tracker| *tracker)
+    }
+
+    pub fn get<T: 'static>(&self, id: TrackerId) -> Option<&T> {
+        self.index(id).and_then(|index| self.trackers[index].as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, id: TrackerId) -> Option<&mut T> {
+        self.index(id).and_then(|index| self.trackers[index].as_any_mut().downcast_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.trackers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trackers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.trackers.clear()
+    }
+}
+
+impl<Mem: Memory> Default for TrackerSet<Mem> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for TrackerSet<Mem> {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        self.trackers.pre_track(state)
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        self.trackers.post_track(state)
+    }
+
+    fn track_effect(&mut self, effect: Box<dyn SideEffect>) {
+        self.trackers.track_effect(effect)
+    }
+
+    fn wants_effects(&self) -> bool {
+        self.trackers.wants_effects()
+    }
+
+    fn pause_requested(&mut self) -> bool {
+        self.trackers.pause_requested()
+    }
+
+    fn code_patched(&mut self, map: &PcMap) {
+        self.trackers.code_patched(map)
+    }
+
+    fn wants_hooks(&self) -> bool {
+        self.trackers.wants_hooks()
+    }
+
+    fn on_fetch(&mut self, pc: u32, word: u32) {
+        self.trackers.on_fetch(pc, word)
+    }
+
+    fn on_load(&mut self, address: u32, width: Width, value: u32) {
+        self.trackers.on_load(address, width, value)
+    }
+
+    fn on_store(&mut self, address: u32, width: Width, old: u32, new: u32) {
+        self.trackers.on_store(address, width, old, new)
+    }
+
+    fn on_register_write(&mut self, register: RegisterId, old: u32, new: u32) {
+        self.trackers.on_register_write(register, old, new)
+    }
+
+    fn on_branch(&mut self, pc: u32, taken: bool, target: u32) {
+        self.trackers.on_branch(pc, taken, target)
+    }
+
+    fn on_syscall(&mut self, state: &State<Mem>) {
+        self.trackers.on_syscall(state)
+    }
+
+    fn on_fault(&mut self, pc: u32, error: Error) {
+        self.trackers.on_fault(pc, error)
+    }
+}
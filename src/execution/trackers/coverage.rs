@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::assembler::binary::{source_breakpoints, Binary};
+use crate::cpu::{Memory, State};
+use crate::execution::stepping::decode_at;
+use crate::execution::trackers::Tracker;
+use crate::unit::analysis::InstructionClass;
+use crate::unit::instruction::InstructionDecoder;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// Marks every retired pc and which way each conditional branch went.
+pub struct CoverageTracker {
+    hits: HashMap<u32, u64>,
+    branches: HashMap<u32, BranchCounts>,
+    pending: Option<(u32, bool)>, // pc and whether it is a conditional branch
+}
+
+#[derive(Clone, Debug)]
+pub struct BranchCoverage {
+    pub pc: u32,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineCoverage {
+    pub line: usize, // starting at 0
+    pub pcs: Vec<u32>,
+    pub hits: u64, // of the most executed instruction on the line
+    pub executed: usize, // instructions that ran at least once
+    pub branches: Vec<BranchCoverage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileCoverage {
+    pub source: usize,
+    pub lines: Vec<LineCoverage>, // by line number
+}
+
+#[derive(Clone, Debug)]
+pub struct CoverageReport {
+    pub files: Vec<FileCoverage>,
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 { 100.0 } else { count as f64 * 100.0 / total as f64 }
+}
+
+impl CoverageTracker {
+    pub fn new() -> CoverageTracker {
+        CoverageTracker { hits: HashMap::new(), branches: HashMap::new(), pending: None }
+    }
+
+    pub fn hits(&self, pc: u32) -> u64 {
+        self.hits.get(&pc).copied().unwrap_or(0)
+    }
+
+    pub fn executed(&self, pc: u32) -> bool {
+        self.hits(pc) > 0
+    }
+
+    pub fn branch(&self, pc: u32) -> Option<BranchCounts> {
+        self.branches.get(&pc).copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.hits.clear();
+        self.branches.clear();
+        self.pending = None;
+    }
+
+    // Covers every source that source returns text for.
+    // Branches are found by decoding the binary, so ones that never ran are reported too.
+    pub fn report<'a, F: Fn(usize) -> Option<&'a str>>(&self, binary: &Binary, source: F) -> CoverageReport {
+        let mut ids: Vec<usize> = binary.breakpoints.iter()
+            .map(|breakpoint| breakpoint.location.source)
+            .collect();
+
+        ids.sort_unstable();
+        ids.dedup();
+
+        let files = ids.into_iter()
+            .filter_map(|id| source(id).map(|text| self.file(binary, id, text)))
+            .collect();
+
+        CoverageReport { files }
+    }
+
+    fn file(&self, binary: &Binary, id: usize, text: &str) -> FileCoverage {
+        let mut lines: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+
+        for breakpoint in source_breakpoints(&binary.breakpoints, text, id) {
+            lines.entry(breakpoint.line).or_default().extend(breakpoint.pcs);
+        }
+
+        let lines = lines.into_iter()
+            .map(|(line, mut pcs)| {
+                pcs.sort_unstable();
+                pcs.dedup();
+
+                let branches = pcs.iter()
+                    .filter(|pc| {
+                        binary.word_at(**pc)
+                            .and_then(|word| InstructionDecoder::decode(**pc, word))
+                            .is_some_and(|instruction| instruction.class() == InstructionClass::Branch)
+                    })
+                    .map(|pc| {
+                        let counts = self.branch(*pc).unwrap_or_default();
+
+                        BranchCoverage { pc: *pc, taken: counts.taken, not_taken: counts.not_taken }
+                    })
+                    .collect();
+
+                LineCoverage {
+                    line,
+                    hits: pcs.iter().map(|pc| self.hits(*pc)).max().unwrap_or(0),
+                    executed: pcs.iter().filter(|pc| self.executed(**pc)).count(),
+                    pcs,
+                    branches,
+                }
+            })
+            .collect();
+
+        FileCoverage { source: id, lines }
+    }
+}
+
+impl Default for CoverageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineCoverage {
+    pub fn covered(&self) -> bool {
+        self.hits > 0
+    }
+
+    // Some instructions of the line ran, but not all of them.
+    pub fn partial(&self) -> bool {
+        self.executed > 0 && self.executed < self.pcs.len()
+    }
+}
+
+impl FileCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.iter().filter(|line| line.covered()).count()
+    }
+
+    // Each conditional branch has two directions.
+    pub fn branches_found(&self) -> usize {
+        self.lines.iter().map(|line| line.branches.len() * 2).sum()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.lines.iter()
+            .flat_map(|line| &line.branches)
+            .map(|branch| (branch.taken > 0) as usize + (branch.not_taken > 0) as usize)
+            .sum()
+    }
+
+    // The source with execution counts in front of each line, in the style of gcov.
+    // "#####" marks lines with code that never ran, "*" lines that only partly ran.
+    pub fn annotate(&self, text: &str) -> String {
+        let lines: HashMap<usize, &LineCoverage> = self.lines.iter()
+            .map(|line| (line.line, line))
+            .collect();
+
+        let mut result = String::new();
+
+        for (index, source) in text.lines().enumerate() {
+            let count = match lines.get(&index) {
+                None => "-".to_string(),
+                Some(line) if !line.covered() => "#####".to_string(),
+                Some(line) if line.partial() => format!("{}*", line.hits),
+                Some(line) => line.hits.to_string(),
+            };
+
+            result.push_str(&format!("{count:>9}:{:>5}: {source}", index + 1));
+
+            for branch in lines.get(&index).map_or(&[][..], |line| &line.branches) {
+                result.push_str(&format!(
+                    "  [branch 0x{:08x}: taken {}, not taken {}]", branch.pc, branch.taken, branch.not_taken
+                ));
+            }
+
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+impl CoverageReport {
+    pub fn file(&self, source: usize) -> Option<&FileCoverage> {
+        self.files.iter().find(|file| file.source == source)
+    }
+
+    // LCOV tracefile (.info), path gives the SF path for a source id.
+    pub fn lcov<F: Fn(usize) -> String>(&self, path: F) -> String {
+        let mut result = String::new();
+
+        for file in &self.files {
+            result.push_str(&format!("TN:\nSF:{}\n", path(file.source)));
+
+            for line in &file.lines {
+                for (block, branch) in line.branches.iter().enumerate() {
+                    for (index, count) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                        // "-" if the branch never ran, lcov's way of saying it was never evaluated.
+                        let count = if branch.taken + branch.not_taken == 0 { "-".to_string() } else { count.to_string() };
+
+                        result.push_str(&format!("BRDA:{},{block},{index},{count}\n", line.line + 1));
+                    }
+                }
+            }
+
+            result.push_str(&format!("BRF:{}\nBRH:{}\n", file.branches_found(), file.branches_hit()));
+
+            for line in &file.lines {
+                result.push_str(&format!("DA:{},{}\n", line.line + 1, line.hits));
+            }
+
+            result.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", file.lines.len(), file.lines_hit()));
+        }
+
+        result
+    }
+
+    // One line per file with line and branch percentages.
+    pub fn summary<F: Fn(usize) -> String>(&self, path: F) -> String {
+        self.files.iter()
+            .map(|file| {
+                let (lines, lines_hit) = (file.lines.len(), file.lines_hit());
+                let (branches, branches_hit) = (file.branches_found(), file.branches_hit());
+
+                format!(
+                    "{}: lines {lines_hit}/{lines} ({:.1}%), branches {branches_hit}/{branches} ({:.1}%)\n",
+                    path(file.source), percent(lines_hit, lines), percent(branches_hit, branches)
+                )
+            })
+            .collect()
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for CoverageTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        let branch = decode_at(state)
+            .is_some_and(|instruction| instruction.class() == InstructionClass::Branch);
+
+        self.pending = Some((state.registers.pc, branch));
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let Some((start, branch)) = self.pending.take() else { return };
+
+        *self.hits.entry(start).or_insert(0) += 1;
+
+        if branch {
+            let counts = self.branches.entry(start).or_default();
+
+            if state.registers.pc == start.wrapping_add(4) {
+                counts.not_taken += 1
+            } else {
+                counts.taken += 1
+            }
+        }
+    }
+}
@@ -1,71 +1,700 @@
 use std::collections::VecDeque;
+use std::mem::size_of;
 use smallvec::SmallVec;
 use crate::cpu::{Memory, State};
-use crate::cpu::memory::watched::{LOG_SIZE, WatchEntry, WatchedMemory};
+use crate::cpu::memory::effect::{Effects, SideEffect};
+use crate::cpu::memory::watched::{BackupValue, LOG_SIZE, WatchedMemory};
 use crate::cpu::state::Registers;
+use crate::execution::patch::PcMap;
 use crate::execution::trackers::Tracker;
+use crate::unit::register::{RegisterId, RegisterName};
 
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RegisterEdit {
+    pub register: RegisterId,
+    pub old: u32,
+    pub new: u32,
+}
+
+#[derive(Clone)]
+pub struct MemoryEdit {
+    pub address: u32,
+    pub old: BackupValue,
+    pub new: BackupValue,
+}
+
+fn write_backup<Mem: Memory>(memory: &mut Mem, address: u32, value: &BackupValue) {
+    // Errors are ignored, the address was writable when the edit was recorded.
+    let _ = match value {
+        BackupValue::Byte(value) => memory.set(address, *value),
+        BackupValue::Short(value) => memory.set_u16(address, *value),
+        BackupValue::Word(value) => memory.set_u32(address, *value),
+        BackupValue::Null => Ok(())
+    };
+}
+
+fn read_like<Mem: Memory>(memory: &Mem, address: u32, like: &BackupValue) -> BackupValue {
+    match like {
+        BackupValue::Byte(_) => memory.get(address).map_or(BackupValue::Null, BackupValue::Byte),
+        BackupValue::Short(_) => memory.get_u16(address).map_or(BackupValue::Null, BackupValue::Short),
+        BackupValue::Word(_) => memory.get_u32(address).map_or(BackupValue::Null, BackupValue::Word),
+        BackupValue::Null => BackupValue::Null
+    }
+}
+
+// Changes made by a single instruction, enough to move in both directions.
 pub struct HistoryEntry {
-    pub registers: Registers,
-    pub edits: SmallVec<[WatchEntry; LOG_SIZE]>
+    pub pc: u32,
+    pub next_pc: u32,
+    pub registers: SmallVec<[RegisterEdit; 2]>,
+    pub edits: SmallVec<[MemoryEdit; LOG_SIZE]>,
+    pub effects: Effects, // device and syscall side effects
+}
+
+fn register_ids() -> impl Iterator<Item=RegisterId> {
+    (1..32u8)
+        .map(|index| RegisterId::Line(RegisterName::from(index)))
+        .chain([RegisterId::Hi, RegisterId::Lo])
+}
+
+// Registers (other than pc) that differ between before and after.
+pub fn register_edits(before: &Registers, after: &Registers) -> SmallVec<[RegisterEdit; 2]> {
+    register_ids()
+        .filter_map(|register| {
+            let (old, new) = (register.get(before), register.get(after));
+
+            (old != new).then_some(RegisterEdit { register, old, new })
+        })
+        .collect()
 }
 
 impl HistoryEntry {
-    pub fn apply<Mem: Memory>(self, registers: &mut Registers, memory: &mut Mem) {
-        *registers = self.registers;
+    fn new(before: &Registers, after: &Registers) -> HistoryEntry {
+        let registers = register_edits(before, after);
+
+        HistoryEntry { pc: before.pc, next_pc: after.pc, registers, edits: SmallVec::new(), effects: vec![] }
+    }
+
+    // Undoes the entry, consuming it.
+    pub fn apply<Mem: Memory>(mut self, registers: &mut Registers, memory: &mut Mem) {
+        self.undo(registers, memory)
+    }
+
+    pub fn undo<Mem: Memory>(&mut self, registers: &mut Registers, memory: &mut Mem) {
+        self.undo_effects();
+
+        registers.pc = self.pc;
+
+        for edit in &self.registers {
+            edit.register.set(registers, edit.old)
+        }
+
+        for edit in self.edits.iter().rev() {
+            write_backup(memory, edit.address, &edit.old)
+        }
+    }
+
+    pub fn redo<Mem: Memory>(&mut self, registers: &mut Registers, memory: &mut Mem) {
+        self.redo_effects();
+
+        registers.pc = self.next_pc;
 
-        for entry in self.edits {
-            entry.apply(memory).ok(); // ignore error
+        for edit in &self.registers {
+            edit.register.set(registers, edit.new)
+        }
+
+        for edit in &self.edits {
+            write_backup(memory, edit.address, &edit.new)
+        }
+    }
+
+    fn undo_effects(&mut self) {
+        for effect in self.effects.iter_mut().rev() {
+            effect.undo()
+        }
+    }
+
+    fn redo_effects(&mut self) {
+        for effect in &mut self.effects {
+            effect.redo()
         }
     }
+
+    pub fn changes_register(&self, register: RegisterId) -> bool {
+        register == RegisterId::Pc || self.registers.iter().any(|edit| edit.register == register)
+    }
+
+    fn size(&self) -> usize {
+        let mut size = size_of::<HistoryEntry>();
+
+        if self.registers.spilled() {
+            size += self.registers.capacity() * size_of::<RegisterEdit>()
+        }
+
+        if self.edits.spilled() {
+            size += self.edits.capacity() * size_of::<MemoryEdit>()
+        }
+
+        size += self.effects.capacity() * size_of::<Box<dyn SideEffect>>();
+
+        size
+    }
+}
+
+// An entry without its side effects, for save states.
+#[derive(Clone)]
+pub struct SavedEntry {
+    pub pc: u32,
+    pub next_pc: u32,
+    pub registers: Vec<RegisterEdit>,
+    pub edits: Vec<MemoryEdit>,
+}
+
+#[derive(Clone, Default)]
+pub struct SavedHistory {
+    pub start: u64,
+    pub cursor: usize,
+    pub entries: Vec<SavedEntry>,
 }
 
-pub struct HistoryTracker {
-    buffer: VecDeque<HistoryEntry>,
-    registers: Option<Registers>
+pub struct Checkpoint<Mem: Memory> {
+    pub count: u64,
+    pub registers: Registers,
+    pub memory: Mem,
+}
+
+impl<Mem: Memory> Checkpoint<Mem> {
+    fn size(&self) -> usize {
+        size_of::<Self>() + self.memory.footprint()
+    }
+}
+
+// A timeline of executed instructions. Moving backwards keeps the undone entries,
+// so the executor can seek forward again until a new instruction is executed.
+pub struct HistoryTracker<Mem: Memory + Clone> {
+    entries: VecDeque<HistoryEntry>,
+    start: u64, // instruction count before entries[0]
+    cursor: usize, // entries[..cursor] are applied to the current state
+
+    checkpoints: VecDeque<Checkpoint<Mem>>,
+    checkpoint_interval: u64,
+
+    budget: usize, // bytes
+    used: usize,
+
+    registers: Option<Registers>,
+    effects: Effects, // side effects of the instruction being tracked
 }
 
-impl HistoryTracker {
-    pub fn new(capacity: usize) -> HistoryTracker {
+impl<Mem: Memory + Clone> HistoryTracker<Mem> {
+    pub fn new(budget: usize) -> HistoryTracker<Mem> {
+        Self::with_checkpoint_interval(budget, DEFAULT_CHECKPOINT_INTERVAL)
+    }
+
+    pub fn with_checkpoint_interval(budget: usize, interval: u64) -> HistoryTracker<Mem> {
         HistoryTracker {
-            buffer: VecDeque::with_capacity(capacity),
-            registers: None
+            entries: VecDeque::new(),
+            start: 0,
+            cursor: 0,
+            checkpoints: VecDeque::new(),
+            checkpoint_interval: interval.max(1),
+            budget,
+            used: 0,
+            registers: None,
+            effects: vec![]
         }
     }
 
-    fn push(&mut self, entry: HistoryEntry) {
-        if self.buffer.capacity() == self.buffer.len() {
-            self.buffer.pop_front();
-        }
-        self.buffer.push_back(entry);
+    // Instruction count of the current state.
+    pub fn position(&self) -> u64 {
+        self.start + self.cursor as u64
     }
 
-    pub fn pop(&mut self) -> Option<HistoryEntry> {
-        self.buffer.pop_back()
+    // Earliest instruction count that can still be reached.
+    pub fn earliest(&self) -> u64 {
+        self.start
     }
 
-    pub fn last(&mut self) -> Option<&HistoryEntry> {
-        self.buffer.back()
+    // Latest recorded instruction count.
+    pub fn latest(&self) -> u64 {
+        self.start + self.entries.len() as u64
     }
 
     pub fn len(&self) -> usize {
-        self.buffer.len()
+        self.cursor
     }
 
     pub fn is_empty(&self) -> bool {
-        self.buffer.is_empty()
+        self.cursor == 0
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    // Entry that will be undone next.
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.cursor.checked_sub(1).and_then(|index| self.entries.get(index))
+    }
+
+    // Entry that will be redone next.
+    pub fn next(&self) -> Option<&HistoryEntry> {
+        self.entries.get(self.cursor)
+    }
+
+    pub fn clear(&mut self) {
+        self.start = self.position();
+        self.entries.clear();
+        self.cursor = 0;
+        self.checkpoints.clear();
+        self.used = 0;
+    }
+
+    // Side effects (ex. console reads) can't be saved, stepping back past a restore leaves them in place.
+    pub fn save(&self) -> SavedHistory {
+        let entries = self.entries.iter()
+            .map(|entry| SavedEntry {
+                pc: entry.pc,
+                next_pc: entry.next_pc,
+                registers: entry.registers.to_vec(),
+                edits: entry.edits.to_vec(),
+            })
+            .collect();
+
+        SavedHistory { start: self.start, cursor: self.cursor, entries }
+    }
+
+    // Replaces the timeline. Checkpoints aren't saved, seeking replays entries instead.
+    pub fn restore(&mut self, saved: &SavedHistory) {
+        self.entries = saved.entries.iter()
+            .map(|entry| HistoryEntry {
+                pc: entry.pc,
+                next_pc: entry.next_pc,
+                registers: entry.registers.iter().copied().collect(),
+                edits: entry.edits.iter().cloned().collect(),
+                effects: vec![]
+            })
+            .collect();
+
+        self.start = saved.start;
+        self.cursor = saved.cursor.min(self.entries.len());
+        self.checkpoints.clear();
+        self.used = self.entries.iter().map(HistoryEntry::size).sum();
+        self.registers = None;
+        self.effects.clear();
+
+        self.evict();
+    }
+
+    // Removes the last applied entry and anything after it, apply() it to step back.
+    pub fn pop(&mut self) -> Option<HistoryEntry> {
+        self.cursor.checked_sub(1)?;
+        self.truncate_future();
+
+        let entry = self.entries.pop_back()?;
+
+        self.used -= entry.size();
+        self.cursor = self.entries.len();
+        self.registers = None;
+
+        let position = self.position();
+
+        while self.checkpoints.back().is_some_and(|checkpoint| checkpoint.count > position) {
+            let checkpoint = self.checkpoints.pop_back().unwrap();
+
+            self.used -= checkpoint.size();
+        }
+
+        Some(entry)
+    }
+
+    pub fn undo(&mut self, state: &mut State<WatchedMemory<Mem>>) -> Option<&HistoryEntry> {
+        let index = self.cursor.checked_sub(1)?;
+        let entry = &mut self.entries[index];
+
+        entry.undo(&mut state.registers, &mut state.memory.backing);
+        self.cursor = index;
+        self.registers = None;
+
+        Some(entry)
+    }
+
+    pub fn redo(&mut self, state: &mut State<WatchedMemory<Mem>>) -> Option<&HistoryEntry> {
+        let entry = self.entries.get_mut(self.cursor)?;
+
+        entry.redo(&mut state.registers, &mut state.memory.backing);
+        self.cursor += 1;
+        self.registers = None;
+
+        Some(entry)
+    }
+
+    // Moves to instruction count within [earliest, latest], using checkpoints for long jumps.
+    pub fn seek(&mut self, count: u64, state: &mut State<WatchedMemory<Mem>>) -> bool {
+        if count < self.earliest() || count > self.latest() {
+            return false
+        }
+
+        let distance = count.abs_diff(self.position());
+
+        let checkpoint = self.checkpoints.iter()
+            .filter(|checkpoint| checkpoint.count <= count && checkpoint.count >= self.start)
+            .max_by_key(|checkpoint| checkpoint.count)
+            .filter(|checkpoint| count - checkpoint.count < distance);
+
+        if let Some(checkpoint) = checkpoint {
+            state.registers = checkpoint.registers;
+            state.memory.backing = checkpoint.memory.clone();
+            state.memory.take();
+
+            let index = (checkpoint.count - self.start) as usize;
+
+            // Side effects aren't part of the checkpoint, walk them over to the checkpoint.
+            if index < self.cursor {
+                self.entries.range_mut(index .. self.cursor).rev().for_each(HistoryEntry::undo_effects)
+            } else {
+                self.entries.range_mut(self.cursor .. index).for_each(HistoryEntry::redo_effects)
+            }
+
+            self.cursor = index;
+        }
+
+        while self.position() < count {
+            self.redo(state);
+        }
+
+        while self.position() > count {
+            self.undo(state);
+        }
+
+        true
+    }
+
+    fn truncate_future(&mut self) {
+        let position = self.position();
+
+        for entry in self.entries.drain(self.cursor..) {
+            self.used -= entry.size();
+        }
+
+        while self.checkpoints.back().is_some_and(|checkpoint| checkpoint.count > position) {
+            let checkpoint = self.checkpoints.pop_back().unwrap();
+
+            self.used -= checkpoint.size();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            // Checkpoints only speed up seeking, keep at most half the budget for them.
+            let checkpoint_bytes: usize = self.checkpoints.iter().map(Checkpoint::size).sum();
+
+            if checkpoint_bytes > self.budget / 2 {
+                if let Some(checkpoint) = self.checkpoints.pop_front() {
+                    self.used -= checkpoint.size();
+
+                    continue
+                }
+            }
+
+            // Only applied entries can be dropped, dropping a future one would move position without the state.
+            if self.cursor == 0 {
+                break
+            }
+
+            let Some(entry) = self.entries.pop_front() else { break };
+
+            self.used -= entry.size();
+            self.start += 1;
+            self.cursor -= 1;
+
+            // Checkpoints before the first entry can't be replayed from anymore.
+            while self.checkpoints.front().is_some_and(|checkpoint| checkpoint.count < self.start) {
+                let checkpoint = self.checkpoints.pop_front().unwrap();
+
+                self.used -= checkpoint.size();
+            }
+        }
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        self.used += entry.size();
+        self.entries.push_back(entry);
+        self.cursor = self.entries.len();
+
+        self.evict();
     }
 }
 
-impl<Mem: Memory> Tracker<WatchedMemory<Mem>> for HistoryTracker {
+impl<Mem: Memory + Clone> Tracker<WatchedMemory<Mem>> for HistoryTracker<Mem> {
     fn pre_track(&mut self, state: &mut State<WatchedMemory<Mem>>) {
-        self.registers = Some(state.registers.clone())
+        if self.cursor < self.entries.len() {
+            self.truncate_future();
+        }
+
+        let position = self.position();
+
+        let needs_checkpoint = position.is_multiple_of(self.checkpoint_interval)
+            && self.checkpoints.back().is_none_or(|checkpoint| checkpoint.count != position);
+
+        if needs_checkpoint {
+            let checkpoint = Checkpoint {
+                count: position,
+                registers: state.registers,
+                memory: state.memory.backing.clone()
+            };
+
+            self.used += checkpoint.size();
+            self.checkpoints.push_back(checkpoint);
+        }
+
+        self.registers = Some(state.registers);
+        self.effects.clear();
     }
 
     fn post_track(&mut self, state: &mut State<WatchedMemory<Mem>>) {
-        let Some(registers) = self.registers else { return };
-        let entry = HistoryEntry { registers, edits: state.memory.take() };
+        let Some(registers) = self.registers.take() else { return };
+
+        let mut entry = HistoryEntry::new(&registers, &state.registers);
+
+        entry.edits = state.memory.take().into_iter()
+            .map(|edit| MemoryEdit {
+                address: edit.address,
+                new: read_like(&state.memory.backing, edit.address, &edit.previous),
+                old: edit.previous,
+            })
+            .collect();
+
+        entry.effects = std::mem::take(&mut self.effects);
 
         self.push(entry);
     }
-}
\ No newline at end of file
+
+    fn track_effect(&mut self, effect: Box<dyn SideEffect>) {
+        self.effects.push(effect)
+    }
+
+    fn wants_effects(&self) -> bool {
+        true
+    }
+
+    // Entries before the patch would write old code and registers back over the new code.
+    fn code_patched(&mut self, _: &PcMap) {
+        self.clear()
+    }
+}
+
+// Trackers that keep a history, so the executor can run backwards.
+pub trait WithHistory<Mem: Memory + Clone>: Tracker<WatchedMemory<Mem>> {
+    fn history(&mut self) -> &mut HistoryTracker<Mem>;
+}
+
+impl<Mem: Memory + Clone> WithHistory<Mem> for HistoryTracker<Mem> {
+    fn history(&mut self) -> &mut HistoryTracker<Mem> {
+        self
+    }
+}
+
+// Combined trackers with the history first.
+impl<Mem: Memory + Clone, B: Tracker<WatchedMemory<Mem>>> WithHistory<Mem> for (HistoryTracker<Mem>, B) {
+    fn history(&mut self) -> &mut HistoryTracker<Mem> {
+        &mut self.0
+    }
+}
+
+impl<Mem: Memory + Clone, B: Tracker<WatchedMemory<Mem>>, C: Tracker<WatchedMemory<Mem>>> WithHistory<Mem> for (HistoryTracker<Mem>, B, C) {
+    fn history(&mut self) -> &mut HistoryTracker<Mem> {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory, SECTION_SIZE};
+    use super::*;
+
+    type TestMemory = SectionMemory<DefaultResponder>;
+
+    const ADDRESS: u32 = 0x10010000;
+
+    fn state() -> State<WatchedMemory<TestMemory>> {
+        let mut memory = TestMemory::new();
+        memory.mount_writable((ADDRESS >> 16) as usize, 0);
+
+        State::new(0x400000, WatchedMemory::new(memory))
+    }
+
+    // Stands in for an instruction that writes $t0 and a word of memory.
+    fn step(history: &mut HistoryTracker<TestMemory>, state: &mut State<WatchedMemory<TestMemory>>, value: u32) {
+        history.pre_track(state);
+
+        state.registers.pc += 4;
+        state.registers.line[8] = value;
+        state.memory.set_u32(ADDRESS, value).unwrap();
+
+        history.post_track(state);
+    }
+
+    fn value(state: &State<WatchedMemory<TestMemory>>) -> (u32, u32, u32) {
+        (state.registers.pc, state.registers.line[8], state.memory.backing.get_u32(ADDRESS).unwrap())
+    }
+
+    fn at(count: u32) -> (u32, u32, u32) {
+        (0x400000 + count * 4, count, count)
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = HistoryTracker::new(DEFAULT_BUDGET);
+        let mut state = state();
+
+        for i in 1 ..= 5 {
+            step(&mut history, &mut state, i);
+        }
+
+        assert_eq!(history.position(), 5);
+
+        for count in (2 .. 5).rev() {
+            assert!(history.undo(&mut state).is_some());
+            assert_eq!(value(&state), at(count));
+        }
+
+        assert!(history.redo(&mut state).is_some());
+        assert_eq!(value(&state), at(3));
+        assert_eq!((history.position(), history.latest()), (3, 5));
+
+        for _ in 0 .. 3 {
+            history.undo(&mut state);
+        }
+
+        assert!(history.undo(&mut state).is_none());
+        assert_eq!(value(&state), (0x400000, 0, 0));
+    }
+
+    #[test]
+    fn new_instruction_discards_the_future() {
+        let mut history = HistoryTracker::new(DEFAULT_BUDGET);
+        let mut state = state();
+
+        for i in 1 ..= 5 {
+            step(&mut history, &mut state, i);
+        }
+
+        history.undo(&mut state);
+        history.undo(&mut state);
+        step(&mut history, &mut state, 100);
+
+        assert_eq!((history.position(), history.latest()), (4, 4));
+        assert!(history.redo(&mut state).is_none());
+
+        history.undo(&mut state);
+        assert_eq!(value(&state), at(3));
+    }
+
+    #[test]
+    fn seek_uses_checkpoints() {
+        let mut history = HistoryTracker::with_checkpoint_interval(DEFAULT_BUDGET, 4);
+        let mut state = state();
+
+        for i in 1 ..= 10 {
+            step(&mut history, &mut state, i);
+        }
+
+        // Checkpoints at 0, 4 and 8.
+        assert_eq!(history.checkpoints.iter().map(|c| c.count).collect::<Vec<_>>(), vec![0, 4, 8]);
+
+        assert!(history.seek(5, &mut state));
+        assert_eq!(value(&state), at(5));
+
+        assert!(history.seek(10, &mut state));
+        assert_eq!(value(&state), at(10));
+
+        assert!(history.seek(0, &mut state));
+        assert_eq!(value(&state), (0x400000, 0, 0));
+
+        assert!(!history.seek(11, &mut state));
+        assert_eq!(history.position(), 0);
+    }
+
+    #[test]
+    fn eviction_keeps_position() {
+        let entry = size_of::<HistoryEntry>();
+        let mut history = HistoryTracker::with_checkpoint_interval(entry * 3, u64::MAX);
+        let mut state = state();
+
+        for i in 1 ..= 10 {
+            step(&mut history, &mut state, i);
+        }
+
+        assert_eq!((history.earliest(), history.position()), (7, 10));
+        assert!(history.used_bytes() <= history.budget());
+
+        assert!(history.seek(7, &mut state));
+        assert_eq!(value(&state), at(7));
+        assert!(!history.seek(6, &mut state));
+    }
+
+    #[test]
+    fn checkpoints_stay_within_budget() {
+        let mut history = HistoryTracker::with_checkpoint_interval(4 * 1024 * 1024, 1);
+        let mut state = state();
+
+        for i in 1 ..= 20 {
+            step(&mut history, &mut state, i);
+        }
+
+        // Every checkpoint clones the whole section table, at least a word for each of the 65536 sections.
+        let table = (1 << 16) * size_of::<usize>();
+        let measured: usize = history.checkpoints.iter()
+            .map(|checkpoint| table + checkpoint.memory.resident_pages() * SECTION_SIZE)
+            .sum();
+
+        assert!(measured <= history.budget() / 2);
+        assert!(history.used_bytes() <= history.budget());
+        assert_eq!(history.position(), 20);
+    }
+
+    #[test]
+    fn restore_before_the_first_entry_keeps_position() {
+        let mut history = HistoryTracker::new(DEFAULT_BUDGET);
+        let mut state = state();
+
+        for i in 1 ..= 4 {
+            step(&mut history, &mut state, i);
+        }
+
+        let mut saved = history.save();
+        saved.cursor = 0;
+
+        // Too small for the saved entries, but none are applied so none can go.
+        let mut small = HistoryTracker::<TestMemory>::new(0);
+        small.restore(&saved);
+
+        assert_eq!((small.position(), small.latest()), (0, 4));
+    }
+
+    #[test]
+    fn pop_and_apply() {
+        let mut history = HistoryTracker::new(DEFAULT_BUDGET);
+        let mut state = state();
+
+        for i in 1 ..= 3 {
+            step(&mut history, &mut state, i);
+        }
+
+        history.undo(&mut state);
+
+        let entry = history.pop().unwrap();
+        entry.apply(&mut state.registers, &mut state.memory.backing);
+
+        assert_eq!(value(&state), at(1));
+        assert_eq!((history.position(), history.latest()), (1, 1));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::cpu::error::Error;
+use crate::cpu::state::Registers;
+use crate::cpu::{Memory, State};
+use crate::execution::trackers::Tracker;
+use crate::unit::analysis::{InstructionClass, MemoryAccess};
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+
+// What the executor needs to remember about an instruction to call the tracker hooks once it ran.
+pub struct HookContext {
+    pc: u32,
+    instruction: Option<Instruction>,
+    registers: Registers,
+    access: Option<(MemoryAccess, u32)>, // access and the value before the instruction
+}
+
+impl HookContext {
+    pub fn fetch<Mem: Memory, Track: Tracker<Mem>>(state: &State<Mem>, tracker: &mut Track) -> HookContext {
+        let pc = state.registers.pc;
+        let word = state.memory.get_u32(pc).ok();
+
+        if let Some(word) = word {
+            tracker.on_fetch(pc, word)
+        }
+
+        let instruction = word.and_then(|word| InstructionDecoder::decode(pc, word));
+
+        let access = instruction.as_ref()
+            .and_then(|instruction| instruction.memory_access(&state.registers))
+            .map(|access| (access, access.width.read(&state.memory, access.address).unwrap_or(0)));
+
+        HookContext { pc, instruction, registers: state.registers, access }
+    }
+
+    pub fn finish<Mem: Memory, Track: Tracker<Mem>>(
+        &self, result: Result<(), Error>, state: &State<Mem>, tracker: &mut Track
+    ) {
+        match result {
+            Err(Error::CpuSyscall) => tracker.on_syscall(state),
+            Err(error) => tracker.on_fault(self.pc, error),
+            Ok(()) => self.retired(state, tracker)
+        }
+    }
+
+    fn retired<Mem: Memory, Track: Tracker<Mem>>(&self, state: &State<Mem>, tracker: &mut Track) {
+        if let Some((access, old)) = self.access {
+            let new = access.width.read(&state.memory, access.address).unwrap_or(old);
+
+            if access.store {
+                tracker.on_store(access.address, access.width, old, new)
+            } else {
+                tracker.on_load(access.address, access.width, old)
+            }
+        }
+
+        let Some(instruction) = &self.instruction else { return };
+
+        for register in instruction.writes() {
+            tracker.on_register_write(register, register.get(&self.registers), register.get(&state.registers))
+        }
+
+        if matches!(instruction.class(), InstructionClass::Branch | InstructionClass::Jump) {
+            let next = state.registers.pc;
+            let taken = next != self.pc.wrapping_add(4);
+
+            let target = if taken { next } else { instruction.branch_target().unwrap_or(next) };
+
+            tracker.on_branch(self.pc, taken, target)
+        }
+    }
+}
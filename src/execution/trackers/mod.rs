@@ -1,5 +1,13 @@
 pub mod tracker;
+pub mod hooks;
 pub mod empty;
 pub mod history;
+pub mod call_stack;
+pub mod trace;
+pub mod profile;
+pub mod pipeline;
+pub mod coverage;
+pub mod composite;
+pub mod provenance;
 
 pub use tracker::Tracker;
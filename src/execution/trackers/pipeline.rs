@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::cpu::{Memory, State};
+use crate::execution::stepping::decode_at;
+use crate::execution::trackers::Tracker;
+use crate::unit::analysis::InstructionClass;
+use crate::unit::instruction::Instruction;
+use crate::unit::instruction::Instruction::*;
+use crate::unit::register::{RegisterId, RegisterName};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Fetch,
+    Decode,
+    Execute,
+    Memory,
+    WriteBack,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Fetch, Stage::Decode, Stage::Execute, Stage::Memory, Stage::WriteBack];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Fetch => "IF",
+            Stage::Decode => "ID",
+            Stage::Execute => "EX",
+            Stage::Memory => "MEM",
+            Stage::WriteBack => "WB",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Stage> {
+        Stage::ALL.into_iter().find(|stage| stage.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Paths results can take back to EX (and to ID for branches) before they are written back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Forwarding {
+    None, // operands are read from the register file in ID, written in the first half of WB
+    Memory, // only from the MEM/WB latch
+    Full, // from EX/MEM and MEM/WB
+}
+
+impl Forwarding {
+    pub fn from_name(name: &str) -> Option<Forwarding> {
+        Some(match name {
+            "none" => Forwarding::None,
+            "memory" | "mem" => Forwarding::Memory,
+            "full" => Forwarding::Full,
+            _ => return None
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StallReason {
+    LoadUse, // waiting on a load right before
+    Data, // waiting on any other result that could not be forwarded yet
+    MultiplyDivide, // waiting on hi/lo, a busy multiply/divide unit or a mul result
+    Control, // fetch bubbles after a taken branch or jump, counted against the branch
+}
+
+impl StallReason {
+    pub const ALL: [StallReason; 4] = [StallReason::LoadUse, StallReason::Data, StallReason::MultiplyDivide, StallReason::Control];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StallReason::LoadUse => "load-use",
+            StallReason::Data => "data",
+            StallReason::MultiplyDivide => "mult/div",
+            StallReason::Control => "control",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub forwarding: Forwarding,
+    pub branch_stage: Stage, // where branches and jr/jalr are resolved, j/jal never later than ID
+    pub multiply_latency: u64, // cycles from entering EX until hi/lo (or the mul result) can be used
+    pub divide_latency: u64,
+    pub window: Option<(u64, u64)>, // (first, count) retired instructions to keep for the diagram
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            forwarding: Forwarding::Full,
+            branch_stage: Stage::Decode,
+            multiply_latency: 4,
+            divide_latency: 12,
+            window: None
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn new() -> PipelineConfig {
+        Self::default()
+    }
+
+    pub fn with_forwarding(mut self, forwarding: Forwarding) -> Self {
+        self.forwarding = forwarding;
+
+        self
+    }
+
+    pub fn with_branch_stage(mut self, stage: Stage) -> Self {
+        self.branch_stage = stage;
+
+        self
+    }
+
+    pub fn with_multiply_latency(mut self, cycles: u64) -> Self {
+        self.multiply_latency = cycles.max(1);
+
+        self
+    }
+
+    pub fn with_divide_latency(mut self, cycles: u64) -> Self {
+        self.divide_latency = cycles.max(1);
+
+        self
+    }
+
+    pub fn with_window(mut self, first: u64, count: u64) -> Self {
+        self.window = Some((first, count));
+
+        self
+    }
+}
+
+// Cycle each stage was entered, indexed by Stage.
+pub type StageCycles = [u64; 5];
+
+// A retired instruction inside the window.
+#[derive(Clone, Debug)]
+pub struct PipelineRow {
+    pub index: u64,
+    pub pc: u32,
+    pub instruction: Option<Instruction>,
+    pub stages: StageCycles,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Producer {
+    result: u64, // last cycle of the stage that computes the value
+    write_back: u64,
+    load: bool,
+}
+
+struct PcInfo {
+    instruction: Option<Instruction>,
+    count: u64,
+    stalls: [u64; 4], // indexed by StallReason
+}
+
+struct Pending {
+    pc: u32,
+    instruction: Option<Instruction>,
+}
+
+// Times retired instructions on an in-order IF/ID/EX/MEM/WB pipeline, as they run.
+// Branches are predicted not taken, so only taken ones cost fetch bubbles, and
+// wrong path instructions are never shown since only retired instructions are seen.
+// Multiply and divide run in their own unit beside EX, and only block instructions that need hi/lo or the unit.
+pub struct PipelineTracker {
+    config: PipelineConfig,
+    pcs: HashMap<u32, PcInfo>,
+    producers: HashMap<RegisterName, Producer>,
+    hi_lo_ready: u64,
+    unit_free: u64,
+    last: Option<StageCycles>,
+    redirect: Option<(u64, u32)>, // earliest fetch after a taken branch, and the branch pc
+    first_fetch: u64,
+    instructions: u64,
+    stalls: [u64; 4],
+    rows: Vec<PipelineRow>,
+    pending: Option<Pending>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PcTiming {
+    pub pc: u32,
+    pub instruction: Option<Instruction>,
+    pub count: u64,
+    pub stalls: Vec<(StallReason, u64)>, // only reasons with stalls
+}
+
+impl PcTiming {
+    pub fn total(&self) -> u64 {
+        self.stalls.iter().map(|(_, cycles)| cycles).sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineReport {
+    pub cycles: u64,
+    pub instructions: u64,
+    pub stalls: Vec<(StallReason, u64)>,
+    pub pcs: Vec<PcTiming>, // pcs that stalled, most stall cycles first
+}
+
+impl PipelineReport {
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 { 0.0 } else { self.cycles as f64 / self.instructions as f64 }
+    }
+}
+
+impl Display for PipelineReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(f, "CPI: {:.3}", self.cpi())?;
+
+        writeln!(f, "\nStall cycles:")?;
+
+        for (reason, cycles) in &self.stalls {
+            writeln!(f, "  {cycles:>10}  {}", reason.name())?
+        }
+
+        writeln!(f, "\nStalls by pc:")?;
+
+        for timing in &self.pcs {
+            let instruction = timing.instruction.as_ref().map_or_else(|| "<invalid>".to_string(), |instruction| instruction.to_string());
+
+            let reasons: Vec<String> = timing.stalls.iter()
+                .map(|(reason, cycles)| format!("{} {cycles}", reason.name()))
+                .collect();
+
+            writeln!(
+                f, "  {:>10}  0x{:08x}  {instruction:<28} x{:<8} {}",
+                timing.total(), timing.pc, timing.count, reasons.join(", ")
+            )?
+        }
+
+        Ok(())
+    }
+}
+
+fn is_unit_operation(instruction: &Instruction) -> bool {
+    matches!(instruction, Div { .. } | Divu { .. } | Mult { .. } | Multu { .. }
+        | Madd { .. } | Maddu { .. } | Msub { .. } | Msubu { .. })
+}
+
+fn is_register_jump(instruction: &Instruction) -> bool {
+    matches!(instruction, Jr { .. } | Jalr { .. })
+}
+
+impl PipelineTracker {
+    pub fn new(config: PipelineConfig) -> PipelineTracker {
+        PipelineTracker {
+            config,
+            pcs: HashMap::new(),
+            producers: HashMap::new(),
+            hi_lo_ready: 0,
+            unit_free: 0,
+            last: None,
+            redirect: None,
+            first_fetch: 0,
+            instructions: 0,
+            stalls: [0; 4],
+            rows: vec![],
+            pending: None,
+        }
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    pub fn clear(&mut self) {
+        *self = PipelineTracker::new(self.config);
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // From the first fetch to the last write back.
+    pub fn cycles(&self) -> u64 {
+        self.last.map_or(0, |last| last[Stage::WriteBack as usize] - self.first_fetch + 1)
+    }
+
+    pub fn rows(&self) -> &[PipelineRow] {
+        &self.rows
+    }
+
+    fn latency(&self, instruction: &Instruction) -> u64 {
+        match instruction {
+            Div { .. } | Divu { .. } => self.config.divide_latency,
+            _ => self.config.multiply_latency
+        }
+    }
+
+    // The stage an operand has to be ready by.
+    fn operand_stage(&self, instruction: &Instruction, register: RegisterName) -> Stage {
+        match *instruction {
+            Sb { s, t, .. } | Sh { s, t, .. } | Sw { s, t, .. } if t == register && s != register => Stage::Memory,
+            _ if instruction.class() == InstructionClass::Branch || is_register_jump(instruction) => {
+                self.config.branch_stage.clamp(Stage::Decode, Stage::Execute)
+            }
+            _ => Stage::Execute
+        }
+    }
+
+    // The stage and the earliest cycle the consumer can be in it, to get the value from producer.
+    fn available(&self, producer: &Producer, stage: Stage) -> (Stage, u64) {
+        match self.config.forwarding {
+            Forwarding::Full => (stage, producer.result + 1),
+            Forwarding::Memory if stage != Stage::Decode => (stage, producer.result.max(producer.write_back - 1) + 1),
+            _ => (Stage::Decode, producer.write_back),
+        }
+    }
+
+    fn schedule(&mut self, pc: u32, instruction: Option<Instruction>, next_pc: u32) {
+        use Stage::*;
+
+        // (stage, cycle, reason), the instruction can't enter stage before cycle.
+        let mut requirements: Vec<(Stage, u64, StallReason)> = vec![];
+
+        if let Some(instruction) = &instruction {
+            for register in instruction.reads() {
+                match register {
+                    RegisterId::Hi | RegisterId::Lo => {
+                        requirements.push((Execute, self.hi_lo_ready, StallReason::MultiplyDivide))
+                    }
+                    RegisterId::Line(name) => {
+                        let Some(producer) = self.producers.get(&name) else { continue };
+
+                        let (stage, cycle) = self.available(producer, self.operand_stage(instruction, name));
+                        let reason = if producer.load { StallReason::LoadUse } else { StallReason::Data };
+
+                        requirements.push((stage, cycle, reason))
+                    }
+                    RegisterId::Pc => { }
+                }
+            }
+
+            // mthi/mtlo can't overwrite a result the unit is still computing.
+            if is_unit_operation(instruction) || matches!(instruction, Mthi { .. } | Mtlo { .. }) {
+                requirements.push((Execute, self.unit_free, StallReason::MultiplyDivide))
+            }
+        }
+
+        let last = self.last;
+        let previous = |stage: Stage| last.map(|last| last[stage as usize]);
+
+        let mut stages: StageCycles = [0; 5];
+        let mut gaps: Vec<(u64, StallReason, u32)> = vec![];
+
+        let fetch = previous(Decode).unwrap_or(self.first_fetch);
+        stages[Fetch as usize] = fetch;
+
+        if let Some((cycle, branch)) = self.redirect.take() {
+            if cycle > fetch {
+                stages[Fetch as usize] = cycle;
+                gaps.push((cycle - fetch, StallReason::Control, branch))
+            }
+        }
+
+        for stage in [Decode, Execute, Memory, WriteBack] {
+            let index = stage as usize;
+
+            // One stage per cycle, and in order: the stage has to be empty.
+            let mut base = stages[index - 1] + 1;
+
+            if let Some(cycle) = last.map(|last| if stage == WriteBack { last[index] + 1 } else { last[index + 1] }) {
+                base = base.max(cycle)
+            }
+
+            let mut cycle = base;
+            let mut reason = None;
+
+            for (_, needed, why) in requirements.iter().filter(|(other, _, _)| *other == stage) {
+                if *needed > cycle {
+                    cycle = *needed;
+                    reason = Some(*why);
+                }
+            }
+
+            // mul keeps EX busy until the multiplier is done.
+            if stage == Memory && matches!(instruction, Some(Mul { .. })) {
+                let done = stages[Execute as usize] + self.config.multiply_latency;
+
+                if done > cycle {
+                    cycle = done;
+                    reason = Some(StallReason::MultiplyDivide)
+                }
+            }
+
+            if let Some(reason) = reason {
+                gaps.push((cycle - base, reason, pc))
+            }
+
+            stages[index] = cycle;
+        }
+
+        // Bubbles this instruction added at the end of the pipeline, the latest stalls take the blame first.
+        if let Some(last) = last {
+            let mut extra = stages[WriteBack as usize] - last[WriteBack as usize] - 1;
+
+            for (cycles, reason, blamed) in gaps.into_iter().rev() {
+                let cycles = cycles.min(extra);
+
+                if cycles > 0 {
+                    self.stalls[reason as usize] += cycles;
+                    self.pcs.entry(blamed)
+                        .or_insert_with(|| PcInfo { instruction: None, count: 0, stalls: [0; 4] })
+                        .stalls[reason as usize] += cycles;
+                }
+
+                extra -= cycles;
+            }
+        }
+
+        if let Some(instruction) = &instruction {
+            let execute = stages[Execute as usize];
+
+            for register in instruction.writes() {
+                match register {
+                    RegisterId::Line(name) => {
+                        let load = instruction.class() == InstructionClass::Load;
+                        let result = if load { stages[Memory as usize] } else { stages[Memory as usize] - 1 };
+
+                        self.producers.insert(name, Producer { result, write_back: stages[WriteBack as usize], load });
+                    }
+                    RegisterId::Hi | RegisterId::Lo if is_unit_operation(instruction) => {
+                        self.hi_lo_ready = execute + self.latency(instruction);
+                        self.unit_free = self.hi_lo_ready;
+                    }
+                    RegisterId::Hi | RegisterId::Lo => self.hi_lo_ready = execute + 1,
+                    RegisterId::Pc => { }
+                }
+            }
+
+            let class = instruction.class();
+
+            if next_pc != pc.wrapping_add(4) && matches!(class, InstructionClass::Branch | InstructionClass::Jump) {
+                let stage = if class == InstructionClass::Jump && !is_register_jump(instruction) {
+                    self.config.branch_stage.min(Decode)
+                } else {
+                    self.config.branch_stage
+                };
+
+                self.redirect = Some((stages[stage as usize] + 1, pc))
+            }
+        }
+
+        if let Some((first, count)) = self.config.window {
+            if self.instructions >= first && self.instructions - first < count {
+                self.rows.push(PipelineRow { index: self.instructions, pc, instruction: instruction.clone(), stages })
+            }
+        }
+
+        let info = self.pcs.entry(pc).or_insert_with(|| PcInfo { instruction: None, count: 0, stalls: [0; 4] });
+        info.instruction = instruction;
+        info.count += 1;
+
+        self.last = Some(stages);
+        self.instructions += 1;
+    }
+
+    pub fn report(&self) -> PipelineReport {
+        let stalls = StallReason::ALL.into_iter()
+            .map(|reason| (reason, self.stalls[reason as usize]))
+            .collect();
+
+        let mut pcs: Vec<PcTiming> = self.pcs.iter()
+            .filter(|(_, info)| info.stalls.iter().any(|cycles| *cycles > 0))
+            .map(|(pc, info)| PcTiming {
+                pc: *pc,
+                instruction: info.instruction.clone(),
+                count: info.count,
+                stalls: StallReason::ALL.into_iter()
+                    .map(|reason| (reason, info.stalls[reason as usize]))
+                    .filter(|(_, cycles)| *cycles > 0)
+                    .collect()
+            })
+            .collect();
+
+        pcs.sort_by(|a, b| b.total().cmp(&a.total()).then(a.pc.cmp(&b.pc)));
+
+        PipelineReport { cycles: self.cycles(), instructions: self.instructions, stalls, pcs }
+    }
+
+    // Cell for each cycle of a row: the stage it entered, or "*" while it waits to leave the previous stage.
+    fn cells(row: &PipelineRow, first: u64, last: u64) -> Vec<&'static str> {
+        (first ..= last)
+            .map(|cycle| {
+                let stage = Stage::ALL.iter().rev().find(|stage| row.stages[**stage as usize] <= cycle);
+
+                match stage {
+                    Some(stage) if row.stages[*stage as usize] == cycle => stage.name(),
+                    Some(Stage::WriteBack) | None => "",
+                    Some(_) => "*",
+                }
+            })
+            .collect()
+    }
+
+    fn cycle_range(&self) -> Option<(u64, u64)> {
+        let first = self.rows.first()?.stages[Stage::Fetch as usize];
+        let last = self.rows.iter().map(|row| row.stages[Stage::WriteBack as usize]).max()?;
+
+        Some((first, last))
+    }
+
+    // One line per instruction in the window and one column per cycle. Cycles are numbered from 1.
+    pub fn diagram(&self) -> String {
+        let Some((first, last)) = self.cycle_range() else { return String::new() };
+
+        let labels: Vec<String> = self.rows.iter()
+            .map(|row| {
+                let instruction = row.instruction.as_ref().map_or_else(|| "<invalid>".to_string(), |instruction| instruction.to_string());
+
+                format!("0x{:08x}  {instruction}", row.pc)
+            })
+            .collect();
+
+        let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+
+        let mut result = format!("{:width$}", "");
+
+        for cycle in first ..= last {
+            result.push_str(&format!(" {:>4}", cycle + 1))
+        }
+
+        result.push('\n');
+
+        for (row, label) in self.rows.iter().zip(labels) {
+            let mut line = format!("{label:width$}");
+
+            for cell in Self::cells(row, first, last) {
+                line.push_str(&format!(" {cell:>4}"))
+            }
+
+            result.push_str(line.trim_end());
+            result.push('\n');
+        }
+
+        result
+    }
+
+    // Like diagram, with the instruction index, pc and text in their own columns, and "stall" for "*".
+    pub fn diagram_csv(&self) -> String {
+        let Some((first, last)) = self.cycle_range() else { return String::new() };
+
+        let mut result = "index,pc,instruction".to_string();
+
+        for cycle in first ..= last {
+            result.push_str(&format!(",{}", cycle + 1))
+        }
+
+        result.push('\n');
+
+        for row in &self.rows {
+            let instruction = row.instruction.as_ref().map_or_else(String::new, |instruction| instruction.to_string());
+
+            result.push_str(&format!("{},0x{:08x},\"{}\"", row.index, row.pc, instruction.replace('"', "\"\"")));
+
+            for cell in Self::cells(row, first, last) {
+                result.push(',');
+                result.push_str(if cell == "*" { "stall" } else { cell })
+            }
+
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for PipelineTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        self.pending = Some(Pending { pc: state.registers.pc, instruction: decode_at(state) });
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let Some(pending) = self.pending.take() else { return };
+
+        self.schedule(pending.pc, pending.instruction, state.registers.pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::unit::register::RegisterName::*;
+    use super::*;
+
+    fn lw(t: RegisterName, s: RegisterName) -> Instruction {
+        Lw { s, t, imm: 0 }
+    }
+
+    fn addu(d: RegisterName, s: RegisterName, t: RegisterName) -> Instruction {
+        Addu { s, t, d }
+    }
+
+    // Schedules program from pc 0. The instruction at taken (if any) leaves for 0x1000, the rest fall through.
+    fn run(config: PipelineConfig, program: &[Instruction], taken: Option<usize>) -> PipelineTracker {
+        let mut tracker = PipelineTracker::new(config.with_window(0, program.len() as u64));
+
+        for (index, instruction) in program.iter().enumerate() {
+            let pc = index as u32 * 4;
+            let next_pc = if taken == Some(index) { 0x1000 } else { pc + 4 };
+
+            tracker.schedule(pc, Some(instruction.clone()), next_pc)
+        }
+
+        tracker
+    }
+
+    fn stalls(tracker: &PipelineTracker) -> Vec<(StallReason, u64)> {
+        tracker.report().stalls.into_iter().filter(|(_, cycles)| *cycles > 0).collect()
+    }
+
+    #[test]
+    fn independent_instructions_fill_the_pipeline() {
+        let tracker = run(PipelineConfig::new(), &[addu(T0, T1, T2), addu(T3, T4, T5), addu(T6, T7, T8)], None);
+
+        assert_eq!(tracker.cycles(), 7);
+        assert_eq!(tracker.report().cpi(), 7.0 / 3.0);
+        assert!(stalls(&tracker).is_empty());
+        assert_eq!(tracker.rows()[2].stages, [2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn load_use_stalls_once_with_full_forwarding() {
+        let tracker = run(PipelineConfig::new(), &[lw(T0, T1), addu(T2, T0, T0)], None);
+
+        // The load has t0 at the end of MEM (cycle 3), so the add can't be in EX until 4.
+        assert_eq!(tracker.rows()[1].stages, [1, 2, 4, 5, 6]);
+        assert_eq!(tracker.cycles(), 7);
+        assert_eq!(stalls(&tracker), vec![(StallReason::LoadUse, 1)]);
+
+        let report = tracker.report();
+        assert_eq!(report.pcs.len(), 1);
+        assert_eq!(report.pcs[0].pc, 4);
+    }
+
+    #[test]
+    fn data_stalls_depend_on_forwarding() {
+        let program = [addu(T0, T1, T2), addu(T3, T0, T0)];
+
+        // EX/MEM forwards the result straight into the next EX.
+        let full = run(PipelineConfig::new().with_forwarding(Forwarding::Full), &program, None);
+        assert_eq!(full.cycles(), 6);
+        assert!(stalls(&full).is_empty());
+
+        // MEM/WB only, so EX waits until the producer leaves MEM.
+        let memory = run(PipelineConfig::new().with_forwarding(Forwarding::Memory), &program, None);
+        assert_eq!(memory.rows()[1].stages, [1, 2, 4, 5, 6]);
+        assert_eq!(stalls(&memory), vec![(StallReason::Data, 1)]);
+
+        // Read in ID in the same cycle as the write back.
+        let none = run(PipelineConfig::new().with_forwarding(Forwarding::None), &program, None);
+        assert_eq!(none.rows()[1].stages, [1, 4, 5, 6, 7]);
+        assert_eq!(none.cycles(), 8);
+        assert_eq!(stalls(&none), vec![(StallReason::Data, 2)]);
+    }
+
+    #[test]
+    fn stores_get_their_value_in_mem() {
+        let program = [lw(T0, T1), Sw { s: T1, t: T0, imm: 0 }];
+
+        // The stored value is only needed in MEM, so it comes from MEM/WB without a stall.
+        let tracker = run(PipelineConfig::new(), &program, None);
+        assert_eq!(tracker.cycles(), 6);
+        assert!(stalls(&tracker).is_empty());
+    }
+
+    #[test]
+    fn taken_branches_cost_until_their_stage() {
+        let program = [Beq { s: T0, t: T0, address: 0x1000 }, addu(T1, T2, T3)];
+
+        let decode = run(PipelineConfig::new().with_branch_stage(Stage::Decode), &program, Some(0));
+        assert_eq!(decode.rows()[1].stages, [2, 3, 4, 5, 6]);
+        assert_eq!(stalls(&decode), vec![(StallReason::Control, 1)]);
+        assert_eq!(decode.report().pcs[0].pc, 0);
+
+        let execute = run(PipelineConfig::new().with_branch_stage(Stage::Execute), &program, Some(0));
+        assert_eq!(execute.cycles(), 8);
+        assert_eq!(stalls(&execute), vec![(StallReason::Control, 2)]);
+
+        // Not taken falls through without bubbles.
+        let fallthrough = run(PipelineConfig::new().with_branch_stage(Stage::Execute), &program, None);
+        assert_eq!(fallthrough.cycles(), 6);
+        assert!(stalls(&fallthrough).is_empty());
+    }
+
+    #[test]
+    fn branches_in_decode_wait_for_operands() {
+        let program = [addu(T0, T1, T2), Beq { s: T0, t: T1, address: 0x1000 }];
+
+        // The add has t0 at the end of EX (cycle 2), one cycle after the branch would be in ID.
+        let decode = run(PipelineConfig::new(), &program, None);
+        assert_eq!(decode.rows()[1].stages, [1, 3, 4, 5, 6]);
+        assert_eq!(stalls(&decode), vec![(StallReason::Data, 1)]);
+
+        let execute = run(PipelineConfig::new().with_branch_stage(Stage::Execute), &program, None);
+        assert!(stalls(&execute).is_empty());
+    }
+
+    #[test]
+    fn hi_lo_waits_for_the_multiplier() {
+        let config = PipelineConfig::new().with_multiply_latency(4);
+
+        // mult enters EX at 2, so lo is ready at 6.
+        let mflo = run(config, &[Mult { s: T0, t: T1 }, Mflo { d: T2 }], None);
+        assert_eq!(mflo.rows()[1].stages, [1, 2, 6, 7, 8]);
+        assert_eq!(stalls(&mflo), vec![(StallReason::MultiplyDivide, 3)]);
+
+        // A second mult waits for the unit, an unrelated add doesn't.
+        let busy = run(config, &[Mult { s: T0, t: T1 }, Mult { s: T2, t: T3 }], None);
+        assert_eq!(stalls(&busy), vec![(StallReason::MultiplyDivide, 3)]);
+
+        let free = run(config, &[Mult { s: T0, t: T1 }, addu(T2, T3, T4)], None);
+        assert!(stalls(&free).is_empty());
+
+        let divide = run(config.with_divide_latency(6), &[Div { s: T0, t: T1 }, Mflo { d: T2 }], None);
+        assert_eq!(divide.rows()[1].stages, [1, 2, 8, 9, 10]);
+        assert_eq!(stalls(&divide), vec![(StallReason::MultiplyDivide, 5)]);
+    }
+
+    #[test]
+    fn mul_holds_execute() {
+        let program = [addu(T4, T5, T6), Mul { s: T0, t: T1, d: T2 }, addu(T3, T4, T5)];
+        let tracker = run(PipelineConfig::new().with_multiply_latency(4), &program, None);
+
+        // mul is in EX from 3 to 6, and the add behind it can't enter EX until it leaves.
+        assert_eq!(tracker.rows()[1].stages, [1, 2, 3, 7, 8]);
+        assert_eq!(tracker.rows()[2].stages, [2, 3, 7, 8, 9]);
+        assert_eq!(tracker.cycles(), 10);
+        assert_eq!(stalls(&tracker), vec![(StallReason::MultiplyDivide, 3)]);
+        assert_eq!(tracker.report().pcs[0].pc, 4);
+    }
+}
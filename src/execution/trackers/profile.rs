@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::assembler::binary::Binary;
+use crate::assembler::lexer::Location;
+use crate::assembler::line_details::LineDetails;
+use crate::cpu::{Memory, State};
+use crate::execution::elf::inspection::Inspection;
+use crate::execution::stepping::decode_at;
+use crate::execution::trackers::Tracker;
+use crate::unit::analysis::InstructionClass;
+use crate::unit::register::RegisterName::RA;
+
+#[derive(Copy, Clone, Debug)]
+struct PcInfo {
+    count: u64,
+    class: Option<InstructionClass>, // None if the word did not decode
+    call: bool,
+    jump: bool, // any other jump, might return through a register other than $ra
+}
+
+// One node per distinct call path, the root is the entry point.
+struct Node {
+    function: u32,
+    parent: Option<usize>,
+    count: u64, // instructions retired while this path was the innermost
+    calls: u64,
+    children: HashMap<u32, usize>,
+}
+
+struct Frame {
+    node: usize,
+    return_address: u32,
+}
+
+// Counts retired instructions per pc and per call path.
+// Instructions are decoded the first time their pc runs, self-modifying code keeps its first class.
+pub struct ProfileTracker {
+    pcs: HashMap<u32, PcInfo>,
+    nodes: Vec<Node>,
+    frames: Vec<Frame>,
+    pending: Option<u32>,
+    total: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionProfile {
+    pub address: u32,
+    pub name: String,
+    pub exclusive: u64, // instructions in the function itself
+    pub inclusive: u64, // including everything it called
+    pub calls: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineProfile {
+    pub location: Location,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub total: u64,
+    pub functions: Vec<FunctionProfile>, // by inclusive count, highest first
+    pub labels: Vec<(String, u64)>, // flat count under the closest label, highest first
+    pub lines: Vec<LineProfile>, // highest first
+    pub classes: Vec<(InstructionClass, u64)>, // highest first
+}
+
+fn symbol(binary: &Binary, address: u32) -> String {
+    match binary.label_before(address) {
+        Some((name, 0)) => name.to_string(),
+        Some((name, offset)) => format!("{name}+0x{offset:x}"),
+        None => format!("0x{address:08x}"),
+    }
+}
+
+fn sorted<K>(map: HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut result: Vec<(K, u64)> = map.into_iter().collect();
+    result.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    result
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
+
+impl ProfileTracker {
+    pub fn new(entry: u32) -> ProfileTracker {
+        let root = Node { function: entry, parent: None, count: 0, calls: 0, children: HashMap::new() };
+
+        ProfileTracker { pcs: HashMap::new(), nodes: vec![root], frames: vec![], pending: None, total: 0 }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn count(&self, pc: u32) -> u64 {
+        self.pcs.get(&pc).map_or(0, |info| info.count)
+    }
+
+    pub fn counts(&self) -> impl Iterator<Item=(u32, u64)> + '_ {
+        self.pcs.iter().map(|(pc, info)| (*pc, info.count))
+    }
+
+    pub fn class_mix(&self) -> Vec<(InstructionClass, u64)> {
+        let mut classes = HashMap::new();
+
+        for info in self.pcs.values() {
+            if let Some(class) = info.class.filter(|_| info.count > 0) {
+                *classes.entry(class).or_insert(0) += info.count;
+            }
+        }
+
+        sorted(classes)
+    }
+
+    pub fn clear(&mut self) {
+        let entry = self.nodes[0].function;
+
+        *self = ProfileTracker::new(entry);
+    }
+
+    fn current(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.node)
+    }
+
+    fn child(&mut self, parent: usize, function: u32) -> usize {
+        if let Some(index) = self.nodes[parent].children.get(&function) {
+            return *index
+        }
+
+        let index = self.nodes.len();
+
+        self.nodes.push(Node { function, parent: Some(parent), count: 0, calls: 0, children: HashMap::new() });
+        self.nodes[parent].children.insert(function, index);
+
+        index
+    }
+
+    fn path(&self, mut node: usize) -> Vec<u32> {
+        let mut result = vec![self.nodes[node].function];
+
+        while let Some(parent) = self.nodes[node].parent {
+            result.push(self.nodes[parent].function);
+            node = parent;
+        }
+
+        result.reverse();
+
+        result
+    }
+
+    fn functions(&self, binary: &Binary) -> Vec<FunctionProfile> {
+        let mut exclusive: HashMap<u32, u64> = HashMap::new();
+        let mut inclusive: HashMap<u32, u64> = HashMap::new();
+        let mut calls: HashMap<u32, u64> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            *exclusive.entry(node.function).or_insert(0) += node.count;
+            *calls.entry(node.function).or_insert(0) += node.calls;
+
+            // Recursive functions appear more than once on a path but are only counted once.
+            let mut path = self.path(index);
+            path.sort_unstable();
+            path.dedup();
+
+            for function in path {
+                *inclusive.entry(function).or_insert(0) += node.count;
+            }
+        }
+
+        let mut result: Vec<FunctionProfile> = inclusive.into_iter()
+            .map(|(address, inclusive)| FunctionProfile {
+                address,
+                name: symbol(binary, address),
+                exclusive: exclusive.get(&address).copied().unwrap_or(0),
+                inclusive,
+                calls: calls.get(&address).copied().unwrap_or(0),
+            })
+            .collect();
+
+        result.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(b.exclusive.cmp(&a.exclusive)));
+
+        result
+    }
+
+    pub fn report(&self, binary: &Binary) -> ProfileReport {
+        let mut labels: HashMap<String, u64> = HashMap::new();
+        let mut lines: HashMap<Location, u64> = HashMap::new();
+
+        for (pc, info) in &self.pcs {
+            if info.count == 0 {
+                continue // decoded, but did not retire
+            }
+
+            let label = binary.label_before(*pc)
+                .map_or_else(|| "<unlabeled>".to_string(), |(name, _)| name.to_string());
+
+            *labels.entry(label).or_insert(0) += info.count;
+
+            if let Some(location) = binary.location_for(*pc) {
+                *lines.entry(location).or_insert(0) += info.count;
+            }
+        }
+
+        let mut labels = sorted(labels);
+        labels.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+        let mut lines = sorted(lines);
+        lines.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count)
+            .then(a.source.cmp(&b.source))
+            .then(a.index.cmp(&b.index)));
+
+        let lines = lines.into_iter()
+            .map(|(location, count)| LineProfile { location, count })
+            .collect();
+
+        ProfileReport {
+            total: self.total,
+            functions: self.functions(binary),
+            labels,
+            lines,
+            classes: self.class_mix(),
+        }
+    }
+
+    // One line per call path, "main;loop;helper 120", the input format of flamegraph.pl.
+    pub fn folded(&self, binary: &Binary) -> String {
+        let mut paths: Vec<(String, u64)> = self.nodes.iter().enumerate()
+            .filter(|(_, node)| node.count > 0)
+            .map(|(index, node)| {
+                let names: Vec<String> = self.path(index).into_iter()
+                    .map(|function| symbol(binary, function))
+                    .collect();
+
+                (names.join(";"), node.count)
+            })
+            .collect();
+
+        paths.sort();
+
+        paths.into_iter()
+            .map(|(path, count)| format!("{path} {count}\n"))
+            .collect()
+    }
+
+    // The inspection's disassembly with the retired count in front of each instruction.
+    pub fn annotate(&self, inspection: &Inspection) -> String {
+        let mut counts: HashMap<usize, u64> = HashMap::new();
+
+        for (pc, line) in &inspection.breakpoints {
+            counts.insert(*line, self.count(*pc));
+        }
+
+        let mut result = String::new();
+
+        for (index, line) in inspection.lines.iter().enumerate() {
+            match counts.get(&index) {
+                Some(0) => result.push_str(&format!("{:>10} {:>6}  {line}\n", "-", "")),
+                Some(count) => result.push_str(&format!(
+                    "{count:>10} {:>5.1}%  {line}\n", percent(*count, self.total)
+                )),
+                None => result.push_str(&format!("{:>18}{line}\n", "")),
+            }
+        }
+
+        result
+    }
+}
+
+impl ProfileReport {
+    // Like Display, but adds source lines. source returns the text for a source id.
+    pub fn describe<'a, F: Fn(usize) -> Option<&'a str>>(&self, source: F) -> String {
+        let total = self.total;
+        let mut result = format!("Retired instructions: {total}\n");
+
+        result.push_str("\nFunctions (inclusive, exclusive):\n");
+
+        for function in &self.functions {
+            result.push_str(&format!(
+                "  {:>10} {:>5.1}%  {:>10} {:>5.1}%  {} (0x{:08x})\n",
+                function.inclusive, percent(function.inclusive, total),
+                function.exclusive, percent(function.exclusive, total),
+                function.name, function.address
+            ));
+        }
+
+        result.push_str("\nLabels:\n");
+
+        for (label, count) in &self.labels {
+            result.push_str(&format!("  {count:>10} {:>5.1}%  {label}\n", percent(*count, total)));
+        }
+
+        result.push_str("\nLines:\n");
+
+        for line in &self.lines {
+            let details = source(line.location.source)
+                .map(|text| LineDetails::from_offset(text, line.location.index));
+
+            let description = match details {
+                Some(details) => format!("line {}: {}", details.line_number + 1, details.line_text.trim()),
+                None => format!("source {} offset {}", line.location.source, line.location.index),
+            };
+
+            result.push_str(&format!("  {:>10} {:>5.1}%  {description}\n", line.count, percent(line.count, total)));
+        }
+
+        result.push_str("\nInstruction mix:\n");
+
+        for (class, count) in &self.classes {
+            result.push_str(&format!("  {count:>10} {:>5.1}%  {}\n", percent(*count, total), class.name()));
+        }
+
+        result
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.describe(|_| None))
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for ProfileTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        let pc = state.registers.pc;
+
+        self.pcs.entry(pc).or_insert_with(|| {
+            let instruction = decode_at(state);
+
+            PcInfo {
+                count: 0,
+                class: instruction.as_ref().map(|instruction| instruction.class()),
+                call: instruction.as_ref().is_some_and(|instruction| instruction.is_call()),
+                jump: instruction.as_ref().is_some_and(|instruction| {
+                    instruction.class() == InstructionClass::Jump && !instruction.is_call()
+                }),
+            }
+        });
+
+        self.pending = Some(pc);
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let Some(start) = self.pending.take() else { return };
+        let Some(info) = self.pcs.get_mut(&start) else { return };
+
+        info.count += 1;
+        self.total += 1;
+
+        let info = *info;
+        let current = self.current();
+        self.nodes[current].count += 1;
+
+        let pc = state.registers.pc;
+
+        if info.jump {
+            // Like CallStackTracker, but any jump that lands on a return address counts as a return.
+            if let Some(index) = self.frames.iter().rposition(|frame| frame.return_address == pc) {
+                self.frames.truncate(index);
+            }
+        } else if info.call && pc != start.wrapping_add(4) {
+            let node = self.child(current, pc);
+            self.nodes[node].calls += 1;
+
+            self.frames.push(Frame { node, return_address: state.registers.get(RA) });
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::ops::{BitOr, BitOrAssign, Range};
+use smallvec::SmallVec;
+use crate::assembler::binary::Binary;
+use crate::cpu::memory::Width;
+use crate::cpu::state::Registers;
+use crate::cpu::{Memory, State};
+use crate::execution::stepping::decode_at;
+use crate::execution::trackers::history::register_edits;
+use crate::execution::trackers::Tracker;
+use crate::unit::instruction::Instruction;
+use crate::unit::instruction::Instruction::*;
+use crate::unit::register::RegisterId;
+use crate::unit::register::RegisterName::{SP, V0, Zero};
+
+pub const DEFAULT_DEFINITIONS: usize = 1 << 20;
+
+const SLOTS: usize = 34; // $0-$31, hi, lo
+
+// A set of up to 32 taint labels, one bit each.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Taint(pub u32);
+
+impl Taint {
+    pub const NONE: Taint = Taint(0);
+
+    pub fn label(index: u8) -> Taint {
+        Taint(1u32.checked_shl(index as u32).unwrap_or(0))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: Taint) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item=u8> + '_ {
+        (0 .. 32u8).filter(|index| self.0 & (1 << index) != 0)
+    }
+}
+
+impl BitOr for Taint {
+    type Output = Taint;
+
+    fn bitor(self, rhs: Taint) -> Taint {
+        Taint(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Taint {
+    fn bitor_assign(&mut self, rhs: Taint) {
+        self.0 |= rhs.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefId(u64);
+
+// Where an input of an instruction came from, captured when it ran.
+#[derive(Clone, Debug)]
+pub enum Origin {
+    Register { register: RegisterId, value: u32, def: Option<DefId> },
+    Memory { address: u32, width: Width, value: u32, defs: SmallVec<[Option<DefId>; 4]> }, // one def per byte
+    Immediate(u32),
+    Syscall { code: u32 },
+}
+
+// An instruction that wrote at least one register or memory byte.
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub index: u64, // retired instruction count
+    pub pc: u32,
+    pub instruction: Option<Instruction>,
+    pub origins: SmallVec<[Origin; 2]>,
+    pub taint: Taint,
+}
+
+struct Pending {
+    pc: u32,
+    instruction: Option<Instruction>,
+    origins: SmallVec<[Origin; 2]>,
+    taint: Taint,
+    def: Option<DefId>,
+    syscall: Option<Registers>, // before the handler ran
+}
+
+// Records the last write of every register and memory byte, and spreads taint labels
+// from the inputs of an instruction to everything it writes. Only data is followed,
+// address registers of loads and stores are not inputs.
+pub struct ProvenanceTracker {
+    definitions: VecDeque<Definition>,
+    first: u64, // id of definitions[0]
+    limit: usize,
+    registers: [Option<DefId>; SLOTS],
+    register_taint: [Taint; SLOTS],
+    memory: HashMap<u32, DefId>,
+    memory_taint: HashMap<u32, Taint>,
+    syscall_taint: Taint,
+    count: u64,
+    pending: Option<Pending>,
+}
+
+fn slot(register: RegisterId) -> Option<usize> {
+    match register {
+        RegisterId::Line(name) => Some(name as usize),
+        RegisterId::Hi => Some(32),
+        RegisterId::Lo => Some(33),
+        RegisterId::Pc => None,
+    }
+}
+
+// Value inputs, loads get theirs from on_load.
+fn data_reads(instruction: &Instruction) -> SmallVec<[RegisterId; 3]> {
+    match *instruction {
+        Sb { t, .. } | Sh { t, .. } | Sw { t, .. } => SmallVec::from_slice(&[RegisterId::Line(t)]),
+        Lb { .. } | Lbu { .. } | Lh { .. } | Lhu { .. } | Lw { .. } => SmallVec::new(),
+        _ => instruction.reads()
+    }
+}
+
+fn immediate(instruction: &Instruction) -> Option<u32> {
+    Some(match *instruction {
+        Addi { imm, .. } | Addiu { imm, .. } | Slti { imm, .. } | Sltiu { imm, .. } => imm as i16 as i32 as u32,
+        Andi { imm, .. } | Ori { imm, .. } | Xori { imm, .. } | Llo { imm, .. } => imm as u32,
+        Lui { imm, .. } | Lhi { imm, .. } => (imm as u32) << 16,
+        Sll { sham, .. } | Sra { sham, .. } | Srl { sham, .. } => sham as u32,
+        _ => return None
+    })
+}
+
+fn symbol(binary: &Binary, address: u32) -> String {
+    match binary.label_before(address) {
+        Some((name, 0)) => name.to_string(),
+        Some((name, offset)) => format!("{name}+0x{offset:x}"),
+        None => format!("0x{address:08x}"),
+    }
+}
+
+fn width_name(width: Width) -> &'static str {
+    match width {
+        Width::Byte => "byte",
+        Width::Half => "half",
+        Width::Word => "word",
+    }
+}
+
+struct Query<'a> {
+    binary: &'a Binary,
+    seen: HashSet<DefId>, // definitions already explained, shared inputs are only expanded once
+}
+
+// Answer to "why does this hold that value", one node per input.
+#[derive(Clone, Debug)]
+pub struct Explanation {
+    pub subject: String, // ex. "$t3 = 0x00001234"
+    pub cause: String,
+    pub inputs: Vec<Explanation>,
+}
+
+impl Explanation {
+    fn leaf(subject: String, cause: String) -> Explanation {
+        Explanation { subject, cause, inputs: vec![] }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>, indent: usize) -> std::fmt::Result {
+        writeln!(f, "{:indent$}{} <- {}", "", self.subject, self.cause, indent = indent * 2)?;
+
+        for input in &self.inputs {
+            input.write(f, indent + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl ProvenanceTracker {
+    pub fn new() -> ProvenanceTracker {
+        ProvenanceTracker {
+            definitions: VecDeque::new(),
+            first: 0,
+            limit: DEFAULT_DEFINITIONS,
+            registers: [None; SLOTS],
+            register_taint: [Taint::NONE; SLOTS],
+            memory: HashMap::new(),
+            memory_taint: HashMap::new(),
+            syscall_taint: Taint::NONE,
+            count: 0,
+            pending: None,
+        }
+    }
+
+    // Oldest definitions are forgotten past limit, explanations stop there.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+
+        self
+    }
+
+    // Taint given to everything a syscall handler writes, ex. to follow user input.
+    pub fn with_syscall_taint(mut self, taint: Taint) -> Self {
+        self.syscall_taint = taint;
+
+        self
+    }
+
+    pub fn taint_register(&mut self, register: RegisterId, taint: Taint) {
+        if let Some(slot) = slot(register) {
+            self.register_taint[slot] |= taint
+        }
+    }
+
+    pub fn taint_memory(&mut self, range: Range<u32>, taint: Taint) {
+        for address in range {
+            *self.memory_taint.entry(address).or_default() |= taint
+        }
+    }
+
+    pub fn clear_taint(&mut self) {
+        self.register_taint = [Taint::NONE; SLOTS];
+        self.memory_taint.clear();
+    }
+
+    pub fn register_taint(&self, register: RegisterId) -> Taint {
+        slot(register).map_or(Taint::NONE, |slot| self.register_taint[slot])
+    }
+
+    pub fn memory_taint(&self, address: u32) -> Taint {
+        self.memory_taint.get(&address).copied().unwrap_or_default()
+    }
+
+    pub fn definition(&self, id: DefId) -> Option<&Definition> {
+        let index = id.0.checked_sub(self.first)?;
+
+        self.definitions.get(index as usize)
+    }
+
+    pub fn last_write_register(&self, register: RegisterId) -> Option<&Definition> {
+        slot(register).and_then(|slot| self.registers[slot]).and_then(|id| self.definition(id))
+    }
+
+    pub fn last_write_memory(&self, address: u32) -> Option<&Definition> {
+        self.memory.get(&address).and_then(|id| self.definition(*id))
+    }
+
+    // Syscall handlers call this for memory they fill, ex. the buffer of read string.
+    // Returns false if no syscall is being handled.
+    pub fn record_syscall_write(&mut self, range: Range<u32>) -> bool {
+        if self.pending.as_ref().is_none_or(|pending| pending.syscall.is_none()) {
+            return false
+        }
+
+        let Some(def) = self.define() else { return false };
+        let taint = self.definition(def).map_or(Taint::NONE, |definition| definition.taint);
+
+        for address in range {
+            self.write_memory(address, def, taint)
+        }
+
+        true
+    }
+
+    fn define(&mut self) -> Option<DefId> {
+        let pending = self.pending.as_mut()?;
+
+        if let Some(def) = pending.def {
+            return Some(def)
+        }
+
+        let id = DefId(self.first + self.definitions.len() as u64);
+
+        self.definitions.push_back(Definition {
+            index: self.count,
+            pc: pending.pc,
+            instruction: pending.instruction.clone(),
+            origins: pending.origins.clone(),
+            taint: pending.taint,
+        });
+
+        pending.def = Some(id);
+
+        while self.definitions.len() > self.limit {
+            self.definitions.pop_front();
+            self.first += 1;
+        }
+
+        Some(id)
+    }
+
+    fn write_register(&mut self, register: RegisterId) {
+        let Some(slot) = slot(register) else { return };
+        let Some(def) = self.define() else { return };
+
+        self.registers[slot] = Some(def);
+        self.register_taint[slot] = self.pending.as_ref().map_or(Taint::NONE, |pending| pending.taint);
+    }
+
+    fn write_memory(&mut self, address: u32, def: DefId, taint: Taint) {
+        self.memory.insert(address, def);
+
+        if taint.is_empty() {
+            self.memory_taint.remove(&address);
+        } else {
+            self.memory_taint.insert(address, taint);
+        }
+    }
+
+    pub fn explain_register(&self, register: RegisterId, value: u32, binary: &Binary, depth: usize) -> Explanation {
+        let def = slot(register).and_then(|slot| self.registers[slot]);
+
+        let mut query = Query { binary, seen: HashSet::new() };
+
+        self.explain(format!("{register} = 0x{value:08x}"), def, Self::initial_register(register), depth, &mut query)
+    }
+
+    pub fn explain_memory(&self, address: u32, width: Width, value: u32, binary: &Binary, depth: usize) -> Explanation {
+        let defs = (0 .. width.bytes())
+            .map(|offset| self.memory.get(&address.wrapping_add(offset)).copied())
+            .collect();
+
+        let mut query = Query { binary, seen: HashSet::new() };
+
+        self.explain_bytes(address, width, value, &defs, depth, &mut query)
+    }
+
+    fn initial_register(register: RegisterId) -> String {
+        match register {
+            RegisterId::Line(Zero) => "always zero".into(),
+            RegisterId::Line(SP) => "initial stack pointer".into(),
+            _ => "initial value".into(),
+        }
+    }
+
+    fn initial_memory(address: u32, binary: &Binary) -> String {
+        match binary.label_before(address) {
+            Some(_) => format!("initial data at {}", symbol(binary, address)),
+            None => "initial memory".into(),
+        }
+    }
+
+    fn explain_bytes(
+        &self, address: u32, width: Width, value: u32, defs: &SmallVec<[Option<DefId>; 4]>,
+        depth: usize, query: &mut Query
+    ) -> Explanation {
+        let subject = format!("{} at 0x{address:08x} = 0x{value:x}", width_name(width));
+
+        let mut distinct: Vec<(u32, Option<DefId>)> = vec![];
+
+        for (offset, def) in defs.iter().enumerate() {
+            if !distinct.iter().any(|(_, other)| other == def) {
+                distinct.push((address.wrapping_add(offset as u32), *def))
+            }
+        }
+
+        match distinct.as_slice() {
+            [(_, def)] => self.explain(subject, *def, Self::initial_memory(address, query.binary), depth, query),
+            _ => {
+                let inputs = distinct.into_iter()
+                    .map(|(byte, def)| {
+                        let subject = format!("byte at 0x{byte:08x}");
+
+                        self.explain(subject, def, Self::initial_memory(byte, query.binary), depth, query)
+                    })
+                    .collect();
+
+                Explanation { subject, cause: "bytes written separately".into(), inputs }
+            }
+        }
+    }
+
+    fn explain(&self, subject: String, def: Option<DefId>, initial: String, depth: usize, query: &mut Query) -> Explanation {
+        let Some(id) = def else { return Explanation::leaf(subject, initial) };
+
+        let Some(definition) = self.definition(id) else {
+            return Explanation::leaf(subject, "written before the recorded history".into())
+        };
+
+        let instruction = definition.instruction.as_ref()
+            .map_or_else(|| "<invalid>".to_string(), |instruction| instruction.to_string());
+
+        let mut cause = format!(
+            "{instruction} at 0x{:08x} ({}), instruction #{}",
+            definition.pc, symbol(query.binary, definition.pc), definition.index
+        );
+
+        if !definition.taint.is_empty() {
+            let labels: Vec<String> = definition.taint.labels().map(|label| label.to_string()).collect();
+
+            cause.push_str(&format!(" [taint {}]", labels.join(", ")));
+        }
+
+        if !query.seen.insert(id) {
+            cause.push_str(" (see above)");
+
+            return Explanation::leaf(subject, cause)
+        }
+
+        if depth == 0 {
+            return Explanation::leaf(subject, cause)
+        }
+
+        let inputs = definition.origins.iter()
+            .map(|origin| match origin {
+                Origin::Register { register, value, def } => self.explain(
+                    format!("{register} = 0x{value:08x}"), *def, Self::initial_register(*register), depth - 1, query
+                ),
+                Origin::Memory { address, width, value, defs } => {
+                    self.explain_bytes(*address, *width, *value, defs, depth - 1, query)
+                }
+                Origin::Immediate(value) => Explanation::leaf(format!("0x{value:x}"), "immediate".into()),
+                Origin::Syscall { code } => Explanation::leaf(format!("syscall {code}"), "input".into()),
+            })
+            .collect();
+
+        Explanation { subject, cause, inputs }
+    }
+}
+
+impl Default for ProvenanceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for ProvenanceTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        let instruction = decode_at(state);
+
+        let mut origins = SmallVec::new();
+        let mut taint = Taint::NONE;
+        let mut syscall = None;
+
+        if let Some(instruction) = &instruction {
+            for register in data_reads(instruction) {
+                let Some(slot) = slot(register).filter(|_| register != RegisterId::Line(Zero)) else { continue };
+
+                taint |= self.register_taint[slot];
+
+                origins.push(Origin::Register {
+                    register,
+                    value: register.get(&state.registers),
+                    def: self.registers[slot]
+                })
+            }
+
+            if let Some(value) = immediate(instruction) {
+                origins.push(Origin::Immediate(value))
+            }
+
+            if *instruction == Syscall {
+                origins.push(Origin::Syscall { code: state.registers.get(V0) });
+                taint |= self.syscall_taint;
+                syscall = Some(state.registers);
+            }
+        }
+
+        self.pending = Some(Pending { pc: state.registers.pc, instruction, origins, taint, def: None, syscall });
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        // Syscall handlers change registers without hooks, see what they did.
+        if let Some(before) = self.pending.as_ref().and_then(|pending| pending.syscall) {
+            for edit in register_edits(&before, &state.registers) {
+                self.write_register(edit.register)
+            }
+        }
+
+        self.pending = None;
+        self.count += 1;
+    }
+
+    fn wants_hooks(&self) -> bool {
+        true
+    }
+
+    fn on_load(&mut self, address: u32, width: Width, value: u32) {
+        let defs: SmallVec<[Option<DefId>; 4]> = (0 .. width.bytes())
+            .map(|offset| self.memory.get(&address.wrapping_add(offset)).copied())
+            .collect();
+
+        let taint = (0 .. width.bytes())
+            .fold(Taint::NONE, |taint, offset| taint | self.memory_taint(address.wrapping_add(offset)));
+
+        if let Some(pending) = &mut self.pending {
+            pending.taint |= taint;
+            pending.origins.push(Origin::Memory { address, width, value, defs });
+        }
+    }
+
+    fn on_store(&mut self, address: u32, width: Width, _: u32, _: u32) {
+        let Some(def) = self.define() else { return };
+        let taint = self.pending.as_ref().map_or(Taint::NONE, |pending| pending.taint);
+
+        for offset in 0 .. width.bytes() {
+            self.write_memory(address.wrapping_add(offset), def, taint)
+        }
+    }
+
+    fn on_register_write(&mut self, register: RegisterId, _: u32, _: u32) {
+        self.write_register(register)
+    }
+}
@@ -0,0 +1,505 @@
+use std::io;
+use std::io::{Read, Write};
+use std::ops::Range;
+use serde_json::json;
+use smallvec::SmallVec;
+use crate::assembler::binary::Binary;
+use crate::cpu::memory::Width;
+use crate::cpu::state::Registers;
+use crate::cpu::{Memory, State};
+use crate::execution::trackers::history::{register_edits, RegisterEdit};
+use crate::execution::trackers::Tracker;
+use crate::unit::analysis::MemoryAccess;
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::unit::register::{RegisterId, RegisterName};
+
+const BINARY_MAGIC: &[u8; 4] = b"TTRC";
+const BINARY_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+    Binary, // see write_binary for the layout
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceAccess {
+    pub address: u32,
+    pub width: Width,
+    pub store: bool,
+    pub old: u32,
+    pub new: u32, // same as old for loads
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    pub index: u64, // retired instruction count, starting at 0
+    pub pc: u32,
+    pub word: u32,
+    pub instruction: Option<Instruction>,
+    pub registers: SmallVec<[RegisterEdit; 2]>,
+    pub access: Option<TraceAccess>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub ranges: Vec<Range<u32>>, // pc ranges, empty for any address
+    pub window: Option<Range<u64>>, // instruction count window
+}
+
+impl TraceFilter {
+    pub fn accepts(&self, index: u64, pc: u32) -> bool {
+        self.window.as_ref().is_none_or(|window| window.contains(&index))
+            && (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)))
+    }
+}
+
+// Addresses from a label up to the next label, or the end of its region.
+pub fn label_range(binary: &Binary, name: &str) -> Option<Range<u32>> {
+    let start = *binary.labels.get(name)?;
+
+    let region_end = binary.regions.iter()
+        .find(|region| start >= region.address && start < region.wrapping_pc())
+        .map_or(u32::MAX, |region| region.wrapping_pc());
+
+    let end = binary.labels.values()
+        .copied()
+        .filter(|address| *address > start)
+        .min()
+        .map_or(region_end, |address| address.min(region_end));
+
+    Some(start .. end)
+}
+
+struct Pending {
+    pc: u32,
+    word: u32,
+    instruction: Option<Instruction>,
+    registers: Registers,
+    access: Option<(MemoryAccess, u32)>, // access and the value before the instruction
+}
+
+// Writes a record for every retired instruction that passes the filter.
+// Write errors stop the trace, see take_error.
+pub struct TraceTracker<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    count: u64,
+    started: bool,
+    pending: Option<Pending>,
+    error: Option<io::Error>,
+}
+
+fn register_code(register: RegisterId) -> u8 {
+    match register {
+        RegisterId::Line(name) => name as u8,
+        RegisterId::Hi => 32,
+        RegisterId::Lo => 33,
+        RegisterId::Pc => 34,
+    }
+}
+
+fn register_from_code(code: u8) -> Option<RegisterId> {
+    match code {
+        0 ..= 31 => Some(RegisterId::Line(RegisterName::from(code))),
+        32 => Some(RegisterId::Hi),
+        33 => Some(RegisterId::Lo),
+        34 => Some(RegisterId::Pc),
+        _ => None
+    }
+}
+
+fn width_from_bytes(bytes: u8) -> Option<Width> {
+    match bytes {
+        1 => Some(Width::Byte),
+        2 => Some(Width::Half),
+        4 => Some(Width::Word),
+        _ => None
+    }
+}
+
+impl<W: Write> TraceTracker<W> {
+    pub fn new(writer: W, format: TraceFormat) -> TraceTracker<W> {
+        TraceTracker {
+            writer,
+            format,
+            filter: TraceFilter::default(),
+            count: 0,
+            started: false,
+            pending: None,
+            error: None
+        }
+    }
+
+    // Only trace instructions within range. Can be called more than once.
+    pub fn with_range(mut self, range: Range<u32>) -> Self {
+        self.filter.ranges.push(range);
+
+        self
+    }
+
+    pub fn with_window(mut self, window: Range<u64>) -> Self {
+        self.filter.window = Some(window);
+
+        self
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+
+        self
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", format_text(entry)),
+            TraceFormat::JsonLines => writeln!(self.writer, "{}", format_json(entry)),
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.writer.write_all(BINARY_MAGIC)?;
+                    self.writer.write_all(&[BINARY_VERSION])?;
+                }
+
+                write_binary(&mut self.writer, entry)
+            }
+        }
+    }
+}
+
+pub fn format_text(entry: &TraceEntry) -> String {
+    let disassembly = entry.instruction.as_ref()
+        .map_or_else(|| "<invalid>".to_string(), |instruction| instruction.to_string());
+
+    let mut result = format!("{:>8} {:08x}: {:08x}  {disassembly:<28}", entry.index, entry.pc, entry.word);
+
+    for edit in &entry.registers {
+        result.push_str(&format!(" {} 0x{:08x} -> 0x{:08x}", edit.register, edit.old, edit.new));
+    }
+
+    if let Some(access) = entry.access {
+        let bytes = access.width.bytes();
+
+        if access.store {
+            result.push_str(&format!(" [store {bytes} @ 0x{:08x}: 0x{:08x} -> 0x{:08x}]", access.address, access.old, access.new));
+        } else {
+            result.push_str(&format!(" [load {bytes} @ 0x{:08x}: 0x{:08x}]", access.address, access.old));
+        }
+    }
+
+    result.trim_end().to_string()
+}
+
+pub fn format_json(entry: &TraceEntry) -> serde_json::Value {
+    let registers: Vec<serde_json::Value> = entry.registers.iter()
+        .map(|edit| json!({ "register": edit.register.to_string(), "old": edit.old, "new": edit.new }))
+        .collect();
+
+    let access = entry.access.map(|access| json!({
+        "address": access.address,
+        "bytes": access.width.bytes(),
+        "kind": if access.store { "store" } else { "load" },
+        "old": access.old,
+        "new": access.new,
+    }));
+
+    json!({
+        "index": entry.index,
+        "pc": entry.pc,
+        "word": entry.word,
+        "instruction": entry.instruction.as_ref().map(|instruction| instruction.to_string()),
+        "registers": registers,
+        "memory": access,
+    })
+}
+
+// Little endian. A "TTRC" header and version byte, then for each entry:
+// index u64, pc u32, word u32, register count u8, then per register (code u8, old u32, new u32),
+// access tag u8 (0 none, 1 load, 2 store), then if present (bytes u8, address u32, old u32, new u32).
+// Register codes are 0-31 for $0-$31, 32 hi, 33 lo, 34 pc.
+pub fn write_binary<W: Write>(writer: &mut W, entry: &TraceEntry) -> io::Result<()> {
+    let mut record = Vec::with_capacity(32);
+
+    record.extend_from_slice(&entry.index.to_le_bytes());
+    record.extend_from_slice(&entry.pc.to_le_bytes());
+    record.extend_from_slice(&entry.word.to_le_bytes());
+
+    record.push(entry.registers.len() as u8);
+
+    for edit in &entry.registers {
+        record.push(register_code(edit.register));
+        record.extend_from_slice(&edit.old.to_le_bytes());
+        record.extend_from_slice(&edit.new.to_le_bytes());
+    }
+
+    match entry.access {
+        None => record.push(0),
+        Some(access) => {
+            record.push(if access.store { 2 } else { 1 });
+            record.push(access.width.bytes() as u8);
+            record.extend_from_slice(&access.address.to_le_bytes());
+            record.extend_from_slice(&access.old.to_le_bytes());
+            record.extend_from_slice(&access.new.to_le_bytes());
+        }
+    }
+
+    writer.write_all(&record)
+}
+
+// Reads back a trace written in the binary format.
+pub struct TraceReader<R: Read> {
+    reader: R,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<TraceReader<R>> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != BINARY_MAGIC || header[4] != BINARY_VERSION {
+            return Err(invalid("not a binary trace"))
+        }
+
+        Ok(TraceReader { reader })
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut buffer = [0u8; 1];
+        self.reader.read_exact(&mut buffer)?;
+
+        Ok(buffer[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buffer = [0u8; 4];
+        self.reader.read_exact(&mut buffer)?;
+
+        Ok(u32::from_le_bytes(buffer))
+    }
+
+    // None at the end of the trace.
+    pub fn read_entry(&mut self) -> io::Result<Option<TraceEntry>> {
+        let mut index = [0u8; 8];
+
+        match self.reader.read_exact(&mut index) {
+            Ok(()) => { }
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error)
+        }
+
+        let index = u64::from_le_bytes(index);
+        let (pc, word) = (self.u32()?, self.u32()?);
+
+        let mut registers = SmallVec::new();
+
+        for _ in 0 .. self.u8()? {
+            let register = register_from_code(self.u8()?).ok_or_else(|| invalid("bad register"))?;
+
+            registers.push(RegisterEdit { register, old: self.u32()?, new: self.u32()? })
+        }
+
+        let access = match self.u8()? {
+            0 => None,
+            tag => {
+                let width = width_from_bytes(self.u8()?).ok_or_else(|| invalid("bad access width"))?;
+
+                Some(TraceAccess { address: self.u32()?, width, store: tag == 2, old: self.u32()?, new: self.u32()? })
+            }
+        };
+
+        let instruction = InstructionDecoder::decode(pc, word);
+
+        Ok(Some(TraceEntry { index, pc, word, instruction, registers, access }))
+    }
+}
+
+impl<Mem: Memory, W: Write> Tracker<Mem> for TraceTracker<W> {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        let pc = state.registers.pc;
+
+        if self.error.is_some() || !self.filter.accepts(self.count, pc) {
+            self.pending = None;
+
+            return
+        }
+
+        self.pending = state.memory.get_u32(pc).ok().map(|word| {
+            let instruction = InstructionDecoder::decode(pc, word);
+
+            let access = instruction.as_ref()
+                .and_then(|instruction| instruction.memory_access(&state.registers))
+                .map(|access| (access, access.width.read(&state.memory, access.address).unwrap_or(0)));
+
+            Pending { pc, word, instruction, registers: state.registers, access }
+        });
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let index = self.count;
+        self.count += 1;
+
+        let Some(pending) = self.pending.take() else { return };
+
+        let access = pending.access.map(|(access, old)| TraceAccess {
+            address: access.address,
+            width: access.width,
+            store: access.store,
+            old,
+            new: access.width.read(&state.memory, access.address).unwrap_or(old)
+        });
+
+        let entry = TraceEntry {
+            index,
+            pc: pending.pc,
+            word: pending.word,
+            instruction: pending.instruction,
+            registers: register_edits(&pending.registers, &state.registers),
+            access
+        };
+
+        if let Err(error) = self.write(&entry) {
+            self.error = Some(error)
+        }
+
+        self.started = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::{Mountable, Region};
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::execution::executor::ExecutorMode;
+    use crate::execution::Executor;
+    use super::*;
+
+    const SOURCE: &str = "main:
+    li $t0, 1
+    li $t1, 2
+    jal f
+    break
+f:
+    addu $t2, $t0, $t1
+    jr $ra
+";
+
+    fn entries(bytes: &[u8]) -> Vec<TraceEntry> {
+        let mut reader = TraceReader::new(bytes).unwrap();
+        let mut result = vec![];
+
+        while let Some(entry) = reader.read_entry().unwrap() {
+            result.push(entry)
+        }
+
+        result
+    }
+
+    // Runs SOURCE with tracker until the break, returning what it wrote.
+    fn run(tracker: impl FnOnce(&Binary) -> TraceTracker<Vec<u8>>) -> Vec<u8> {
+        let binary = assemble_from(SOURCE).unwrap();
+        let mut memory = SectionMemory::<DefaultResponder>::new();
+
+        for region in &binary.regions {
+            memory.mount(Region { start: region.address, data: region.data.clone() })
+        }
+
+        let executor = Executor::new(State::new(binary.entry, memory), tracker(&binary));
+        executor.override_mode(ExecutorMode::Running);
+        executor.run(false);
+
+        executor.with_tracker(|tracker| tracker.writer.clone())
+    }
+
+    fn indices(bytes: &[u8]) -> Vec<u64> {
+        entries(bytes).iter().map(|entry| entry.index).collect()
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let written = [
+            TraceEntry {
+                index: 0, pc: 0x400000, word: 0x24080001, instruction: None,
+                registers: SmallVec::from_slice(&[RegisterEdit { register: RegisterId::Line(RegisterName::T0), old: 0, new: 1 }]),
+                access: None
+            },
+            TraceEntry {
+                index: 7, pc: 0x400004, word: 0xAFA80000, instruction: None,
+                registers: SmallVec::from_slice(&[
+                    RegisterEdit { register: RegisterId::Hi, old: 2, new: 3 },
+                    RegisterEdit { register: RegisterId::Pc, old: 0x400004, new: 0x400008 },
+                ]),
+                access: Some(TraceAccess { address: 0x7FFFFFF0, width: Width::Half, store: true, old: 5, new: 0xFFFF })
+            },
+            TraceEntry {
+                index: u64::MAX, pc: 0, word: 0, instruction: None, registers: SmallVec::new(),
+                access: Some(TraceAccess { address: 0x10010000, width: Width::Byte, store: false, old: 9, new: 9 })
+            },
+        ];
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.push(BINARY_VERSION);
+
+        for entry in &written {
+            write_binary(&mut bytes, entry).unwrap();
+        }
+
+        let read = entries(&bytes);
+
+        assert_eq!(read.len(), written.len());
+
+        for (read, written) in read.iter().zip(&written) {
+            assert_eq!((read.index, read.pc, read.word, read.access), (written.index, written.pc, written.word, written.access));
+            assert_eq!(format!("{:?}", read.registers), format!("{:?}", written.registers));
+            assert_eq!(read.instruction, InstructionDecoder::decode(written.pc, written.word));
+        }
+    }
+
+    #[test]
+    fn tracker_writes_readable_binary() {
+        let bytes = run(|_| TraceTracker::new(vec![], TraceFormat::Binary));
+        let entries = entries(&bytes);
+
+        // The break fails, so it is not traced.
+        assert_eq!(entries.iter().map(|entry| entry.index).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(entries[3].instruction.as_ref().map(ToString::to_string), Some("addu $t2, $t0, $t1".into()));
+
+        let edits: Vec<_> = entries[3].registers.iter().map(|edit| (edit.register, edit.old, edit.new)).collect();
+        assert_eq!(edits, vec![(RegisterId::Line(RegisterName::T2), 0, 3)]);
+
+        assert!(TraceReader::new(&b"TTRC\x02"[..]).is_err());
+    }
+
+    #[test]
+    fn filters_by_window_and_range() {
+        assert_eq!(indices(&run(|_| TraceTracker::new(vec![], TraceFormat::Binary).with_window(1 .. 4))), vec![1, 2, 3]);
+
+        let range = |binary: &Binary| label_range(binary, "f").unwrap();
+
+        assert_eq!(indices(&run(|binary| TraceTracker::new(vec![], TraceFormat::Binary).with_range(range(binary)))), vec![3, 4]);
+        assert_eq!(indices(&run(|binary| {
+            TraceTracker::new(vec![], TraceFormat::Binary).with_range(range(binary)).with_window(1 .. 4)
+        })), vec![3]);
+    }
+}
@@ -1,6 +1,40 @@
 use crate::cpu::{Memory, State};
+use crate::cpu::error::Error;
+use crate::cpu::memory::Width;
+use crate::cpu::memory::effect::SideEffect;
+use crate::execution::patch::PcMap;
+use crate::unit::register::RegisterId;
 
 pub trait Tracker<Mem: Memory> {
     fn pre_track(&mut self, state: &mut State<Mem>);
     fn post_track(&mut self, state: &mut State<Mem>);
+
+    // Called between pre_track and post_track for each side effect of the instruction.
+    fn track_effect(&mut self, _: Box<dyn SideEffect>) { }
+
+    // Effects can only be undone by one owner, combined trackers pass them to the first that wants them.
+    fn wants_effects(&self) -> bool { false }
+
+    // Called after post_track. Returning true pauses the executor after the instruction,
+    // ex. when a coverage goal is reached.
+    fn pause_requested(&mut self) -> bool { false }
+
+    // Called by Executor::hot_patch once the new code is in place. Code addresses can be moved with map,
+    // anything that only makes sense for the old code (ex. history) should be dropped.
+    fn code_patched(&mut self, _map: &PcMap) { }
+
+    // The on_ hooks are only called if this returns true, since the executor has to decode
+    // every instruction for them. Trackers that keep the default cost nothing.
+    fn wants_hooks(&self) -> bool { false }
+
+    // In order: on_fetch after pre_track, then once the instruction ran, on_load or on_store,
+    // on_register_write for each register it wrote (even with the same value) and on_branch.
+    // A failed instruction gets on_syscall or on_fault instead.
+    fn on_fetch(&mut self, _pc: u32, _word: u32) { }
+    fn on_load(&mut self, _address: u32, _width: Width, _value: u32) { }
+    fn on_store(&mut self, _address: u32, _width: Width, _old: u32, _new: u32) { }
+    fn on_register_write(&mut self, _register: RegisterId, _old: u32, _new: u32) { }
+    fn on_branch(&mut self, _pc: u32, _taken: bool, _target: u32) { } // target is the branch address if not taken
+    fn on_syscall(&mut self, _state: &State<Mem>) { } // before the frontend handles it
+    fn on_fault(&mut self, _pc: u32, _error: Error) { }
 }
@@ -0,0 +1,158 @@
+use crate::cpu::memory::Width;
+use num_derive::{ToPrimitive, FromPrimitive};
+use crate::cpu::state::Registers;
+use crate::unit::instruction::Instruction;
+use crate::unit::instruction::Instruction::*;
+use smallvec::{smallvec, SmallVec};
+use crate::unit::register::{RegisterId, RegisterName};
+use crate::unit::register::RegisterName::{RA, Zero};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive)]
+pub enum InstructionClass {
+    Alu,
+    MultiplyDivide, // anything that reads or writes hi/lo
+    Load,
+    Store,
+    Branch,
+    Jump,
+    Syscall,
+    Trap,
+}
+
+impl InstructionClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstructionClass::Alu => "alu",
+            InstructionClass::MultiplyDivide => "mult/div",
+            InstructionClass::Load => "load",
+            InstructionClass::Store => "store",
+            InstructionClass::Branch => "branch",
+            InstructionClass::Jump => "jump",
+            InstructionClass::Syscall => "syscall",
+            InstructionClass::Trap => "trap",
+        }
+    }
+}
+
+impl Instruction {
+    pub fn class(&self) -> InstructionClass {
+        match self {
+            Div { .. } | Divu { .. } | Mult { .. } | Multu { .. }
+                | Madd { .. } | Maddu { .. } | Msub { .. } | Msubu { .. }
+                | Mfhi { .. } | Mflo { .. } | Mthi { .. } | Mtlo { .. } => InstructionClass::MultiplyDivide,
+            Lb { .. } | Lbu { .. } | Lh { .. } | Lhu { .. } | Lw { .. } => InstructionClass::Load,
+            Sb { .. } | Sh { .. } | Sw { .. } => InstructionClass::Store,
+            Beq { .. } | Bne { .. } | Bgtz { .. } | Blez { .. }
+                | Bltz { .. } | Bgez { .. } | Bltzal { .. } | Bgezal { .. } => InstructionClass::Branch,
+            J { .. } | Jal { .. } | Jr { .. } | Jalr { .. } => InstructionClass::Jump,
+            Syscall => InstructionClass::Syscall,
+            Trap | Break => InstructionClass::Trap,
+            _ => InstructionClass::Alu,
+        }
+    }
+
+    // Instructions that write a return address to $ra.
+    pub fn is_call(&self) -> bool {
+        matches!(self, Jal { .. } | Jalr { .. } | Bltzal { .. } | Bgezal { .. })
+    }
+
+    pub fn is_return(&self) -> bool {
+        matches!(self, Jr { s: RA })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u32,
+    pub width: Width,
+    pub store: bool,
+}
+
+impl MemoryAccess {
+    pub fn overlaps(&self, start: u32, length: u32) -> bool {
+        let end = self.address as u64 + self.width.bytes() as u64;
+
+        (self.address as u64) < start as u64 + length as u64 && (start as u64) < end
+    }
+}
+
+fn effective_address(registers: &Registers, s: RegisterName, imm: u16) -> u32 {
+    (registers.get(s) as i32).wrapping_add(imm as i16 as i32) as u32
+}
+
+impl Instruction {
+    // Destination of a branch or a direct jump, None for jr and jalr.
+    pub fn branch_target(&self) -> Option<u32> {
+        match *self {
+            Beq { address, .. } | Bne { address, .. } | Bgtz { address, .. } | Blez { address, .. }
+                | Bltz { address, .. } | Bgez { address, .. } | Bltzal { address, .. } | Bgezal { address, .. }
+                | J { address } | Jal { address } => Some(address),
+            _ => None
+        }
+    }
+
+    // Registers the instruction reads, including address registers of loads and stores.
+    pub fn reads(&self) -> SmallVec<[RegisterId; 3]> {
+        let line = RegisterId::Line;
+
+        match *self {
+            Add { s, t, .. } | Addu { s, t, .. } | And { s, t, .. } | Nor { s, t, .. } | Or { s, t, .. }
+                | Sllv { s, t, .. } | Srav { s, t, .. } | Srlv { s, t, .. } | Sub { s, t, .. } | Subu { s, t, .. }
+                | Xor { s, t, .. } | Slt { s, t, .. } | Sltu { s, t, .. } | Mul { s, t, .. }
+                | Div { s, t } | Divu { s, t } | Mult { s, t } | Multu { s, t }
+                | Beq { s, t, .. } | Bne { s, t, .. }
+                | Sb { s, t, .. } | Sh { s, t, .. } | Sw { s, t, .. } => smallvec![line(s), line(t)],
+            Madd { s, t } | Maddu { s, t } | Msub { s, t } | Msubu { s, t } => {
+                smallvec![line(s), line(t), RegisterId::Hi, RegisterId::Lo]
+            }
+            Sll { t, .. } | Sra { t, .. } | Srl { t, .. } | Lhi { t, .. } | Llo { t, .. } => smallvec![line(t)],
+            Addi { s, .. } | Addiu { s, .. } | Andi { s, .. } | Ori { s, .. } | Xori { s, .. }
+                | Slti { s, .. } | Sltiu { s, .. }
+                | Bgtz { s, .. } | Blez { s, .. } | Bltz { s, .. } | Bgez { s, .. } | Bltzal { s, .. } | Bgezal { s, .. }
+                | Jr { s } | Jalr { s } | Mthi { s } | Mtlo { s }
+                | Lb { s, .. } | Lbu { s, .. } | Lh { s, .. } | Lhu { s, .. } | Lw { s, .. } => smallvec![line(s)],
+            Mfhi { .. } => smallvec![RegisterId::Hi],
+            Mflo { .. } => smallvec![RegisterId::Lo],
+            _ => smallvec![]
+        }
+    }
+
+    // Registers the instruction writes, even if the value does not change. $zero is left out.
+    pub fn writes(&self) -> SmallVec<[RegisterId; 2]> {
+        let result: SmallVec<[RegisterId; 2]> = match *self {
+            Add { d, .. } | Addu { d, .. } | And { d, .. } | Nor { d, .. } | Or { d, .. }
+                | Sll { d, .. } | Sllv { d, .. } | Sra { d, .. } | Srav { d, .. } | Srl { d, .. } | Srlv { d, .. }
+                | Sub { d, .. } | Subu { d, .. } | Xor { d, .. } | Slt { d, .. } | Sltu { d, .. }
+                | Mul { d, .. } | Mfhi { d } | Mflo { d } => smallvec![RegisterId::Line(d)],
+            Addi { t, .. } | Addiu { t, .. } | Andi { t, .. } | Ori { t, .. } | Xori { t, .. }
+                | Slti { t, .. } | Sltiu { t, .. } | Lhi { t, .. } | Llo { t, .. }
+                | Lb { t, .. } | Lbu { t, .. } | Lh { t, .. } | Lhu { t, .. } | Lw { t, .. } => smallvec![RegisterId::Line(t)],
+            Lui { s, .. } => smallvec![RegisterId::Line(s)],
+            Div { .. } | Divu { .. } | Mult { .. } | Multu { .. }
+                | Madd { .. } | Maddu { .. } | Msub { .. } | Msubu { .. } => smallvec![RegisterId::Hi, RegisterId::Lo],
+            Mthi { .. } => smallvec![RegisterId::Hi],
+            Mtlo { .. } => smallvec![RegisterId::Lo],
+            Jal { .. } | Jalr { .. } | Bltzal { .. } | Bgezal { .. } => smallvec![RegisterId::Line(RA)],
+            _ => smallvec![]
+        };
+
+        result.into_iter()
+            .filter(|register| *register != RegisterId::Line(Zero))
+            .collect()
+    }
+
+    // Memory touched by the instruction, given the registers before it executes.
+    pub fn memory_access(&self, registers: &Registers) -> Option<MemoryAccess> {
+        let (s, imm, width, store) = match *self {
+            Lb { s, imm, .. } | Lbu { s, imm, .. } => (s, imm, Width::Byte, false),
+            Lh { s, imm, .. } | Lhu { s, imm, .. } => (s, imm, Width::Half, false),
+            Lw { s, imm, .. } => (s, imm, Width::Word, false),
+            Sb { s, imm, .. } => (s, imm, Width::Byte, true),
+            Sh { s, imm, .. } => (s, imm, Width::Half, true),
+            Sw { s, imm, .. } => (s, imm, Width::Word, true),
+            _ => return None
+        };
+
+        Some(MemoryAccess { address: effective_address(registers, s, imm), width, store })
+    }
+}
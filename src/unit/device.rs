@@ -2,11 +2,11 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::{fs, thread};
+use std::io::{Read, Write};
 use std::panic::{catch_unwind, RefUnwindSafe};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
 use crate::assembler::string::{assemble_from_path, SourceError};
 use crate::cpu::memory::{Mountable, Region};
@@ -15,12 +15,17 @@ use crate::cpu::memory::watched::WatchedMemory;
 use crate::cpu::{Memory, State};
 use crate::cpu::state::Registers;
 use crate::execution::executor::{DebugFrame, Executor, ExecutorMode};
-use crate::execution::trackers::history::HistoryTracker;
+use crate::execution::trackers::history::{HistoryTracker, DEFAULT_BUDGET};
 use crate::unit::device::MakeUnitDeviceError::{CompileFailed, FileMissing};
-use crate::unit::device::UnitDeviceError::{ExecutionTimedOut, InvalidInstruction, MissingLabel, ProgramCompleted};
+use crate::unit::device::UnitDeviceError::{ExecutionTimedOut, InvalidInstruction, LimitExceeded, MissingInput, MissingLabel, ProgramCompleted};
 use num::{ToPrimitive, FromPrimitive};
 use StopCondition::{Label, MaybeLabel};
-use crate::execution::executor::ExecutorMode::{Invalid, Running};
+use crate::execution::executor::ExecutorMode::{Invalid, LimitReached, Running};
+use crate::execution::console::{Console, ConsoleSyscall};
+use crate::execution::diff::StateDiff;
+use crate::execution::limits::{Limit, Limits};
+use crate::execution::save::{ConsoleImage, MachineImage, SaveError};
+use crate::execution::termination::{Termination, EXIT2_SYSCALL, EXIT_SYSCALL};
 use crate::unit::device::StopCondition::{Address, Steps, Timeout};
 use crate::cpu::error::Error as CpuError;
 use crate::unit::instruction::{Instruction, InstructionDecoder};
@@ -28,7 +33,7 @@ use crate::unit::register::RegisterName;
 use crate::unit::register::RegisterName::{A0, RA, V0};
 
 pub type MemoryType = WatchedMemory<SectionMemory<DefaultResponder>>;
-pub type TrackerType = HistoryTracker;
+pub type TrackerType = HistoryTracker<SectionMemory<DefaultResponder>>;
 
 #[derive(Debug)]
 pub enum MakeUnitDeviceError {
@@ -50,8 +55,9 @@ impl Error for MakeUnitDeviceError { }
 pub struct UnitDevice {
     pub executor: Arc<Executor<MemoryType, TrackerType>>,
     pub binary: Binary,
-    pub finished_pcs: Vec<u32>,
     pub syscall_handler: Option<Box<dyn Fn()>>,
+    pub console: Console, // print and read syscalls without a handler use it
+    pub finished_pcs: Vec<u32>, // reaching one completes the program, ex. the end of a region
     handlers: HashMap<u32, Box<dyn Fn ()>>,
 }
 
@@ -152,7 +158,9 @@ pub enum UnitDeviceError {
     MissingLabel(String),
     ExecutionTimedOut,
     InvalidInstruction(CpuError),
-    ProgramCompleted
+    ProgramCompleted,
+    LimitExceeded(Limit),
+    MissingInput, // a read syscall found the console empty
 }
 
 impl Display for UnitDeviceError {
@@ -161,32 +169,19 @@ impl Display for UnitDeviceError {
             MissingLabel(label) => write!(f, "Could not find label {} in program", label),
             ExecutionTimedOut => write!(f, "Execution timed out (by stop condition)"),
             InvalidInstruction(error) => write!(f, "Cpu execution failed with error {}", error),
-            ProgramCompleted => write!(f, "Program completed and this was not caught")
+            ProgramCompleted => write!(f, "Program completed and this was not caught"),
+            MissingInput => write!(f, "Program tried to read input but the console has none, push some with console.push_input"),
+            LimitExceeded(limit) => write!(f, "Execution stopped after exceeding the {} limit", match limit {
+                Limit::Instructions => "instruction",
+                Limit::Pages => "memory page",
+                Limit::OutputBytes => "output",
+                Limit::Syscalls => "syscall",
+                Limit::Time => "time"
+            })
         }
     }
 }
 
-fn make_timeout<F: FnOnce () + Send + 'static>(f: F, duration: Duration) -> Arc<AtomicBool> {
-    let stop = Arc::new(AtomicBool::new(false));
-    let result = stop.clone();
-
-    let start = Instant::now();
-
-    thread::spawn(move || {
-        while start.elapsed() < duration {
-            if stop.load(Ordering::Relaxed) {
-                return
-            }
-
-            thread::sleep(Duration::from_millis(100));
-        }
-
-        f()
-    });
-
-    result
-}
-
 impl Error for UnitDeviceError { }
 
 impl Binary {
@@ -329,10 +324,12 @@ impl UnitDevice {
         let mut state = State::new(binary.entry, memory);
         state.registers.line[29] = heap_end;
 
-        let tracker = HistoryTracker::new(1000);
+        let tracker = HistoryTracker::new(DEFAULT_BUDGET);
 
         let executor = Arc::new(Executor::new(state, tracker));
 
+        executor.set_termination(Termination::for_binary(&binary));
+
         let finished_pcs = binary
             .regions
             .iter()
@@ -343,6 +340,7 @@ impl UnitDevice {
             executor,
             binary,
             syscall_handler: None,
+            console: Console::new(),
             handlers: HashMap::new(),
             finished_pcs
         }
@@ -443,15 +441,62 @@ impl UnitDevice {
         self.executor.with_state(|s| s.clone())
     }
 
+    // What changed since before (ex. a snapshot taken ahead of a call), with memory named by data labels.
+    pub fn diff(&self, before: &State<MemoryType>) -> StateDiff {
+        self.executor.with_state(|state| StateDiff::new(before, state)).with_labels(&self.binary)
+    }
+
     pub fn restore(&self, state: State<MemoryType>) {
         self.executor.with_state(|s| *s = state)
     }
 
+    // Writes a save state that load_state can pick up again, ex. to skip a long setup in later tests.
+    pub fn save_state<W: Write>(&self, writer: &mut W, history: bool) -> std::io::Result<()> {
+        let mut image = self.executor.save_image();
+
+        let (input, cursor, output) = self.console.contents();
+        image.console = Some(ConsoleImage { input, cursor, output });
+
+        if history {
+            image.history = Some(self.executor.with_tracker(|tracker| tracker.save()))
+        }
+
+        image.write(writer)
+    }
+
+    // History and the console are replaced with the saved ones, or cleared if the save has none.
+    pub fn load_state<R: Read>(&self, reader: &mut R) -> Result<(), SaveError> {
+        let image = MachineImage::read(reader)?;
+
+        self.executor.load_image(&image);
+
+        let console = image.console.clone().unwrap_or_default();
+        self.console.restore(console.input, console.cursor, console.output);
+
+        self.executor.with_tracker(|tracker| match &image.history {
+            Some(history) => tracker.restore(history),
+            None => tracker.clear()
+        });
+
+        Ok(())
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        self.executor.set_limits(limits)
+    }
+
+    // Exit syscalls end the run as completed, unless a handler is set for that syscall.
     pub fn handle_syscall<F: Fn() + 'static>(&mut self, v0: u32, f: F) {
+        if v0 == EXIT_SYSCALL || v0 == EXIT2_SYSCALL {
+            self.executor.set_termination(self.executor.termination().with_exit_syscall(v0, false))
+        }
+
         self.handlers.insert(v0, Box::new(f));
     }
 
+    // Also receives exit syscalls that have no handler of their own.
     pub fn handle_any_syscall<F: Fn() + 'static>(&mut self, f: F) {
+        self.executor.set_termination(self.executor.termination().with_exit_syscalls(false));
         self.syscall_handler = Some(Box::new(f))
     }
 
@@ -464,17 +509,18 @@ impl UnitDevice {
                     if let Some(handler) = self.handlers.get(&v0) {
                         handler();
 
-                        self.executor.syscall_handled();
-
-                        Ok(false)
+                        self.syscall_handled(complete_error)
                     } else if let Some(handler) = &self.syscall_handler {
                         handler();
 
-                        self.executor.syscall_handled();
-
-                        Ok(false)
+                        self.syscall_handled(complete_error)
                     } else {
-                        Err(InvalidInstruction(error))
+                        match self.console.syscall(&self.executor) {
+                            ConsoleSyscall::Handled | ConsoleSyscall::LimitReached(_) => self.syscall_handled(complete_error),
+                            ConsoleSyscall::NeedsInput => Err(MissingInput),
+                            ConsoleSyscall::Fault(error) => Err(InvalidInstruction(error)),
+                            ConsoleSyscall::Unsupported(_) => Err(InvalidInstruction(error))
+                        }
                     }
                 }
 
@@ -491,24 +537,40 @@ impl UnitDevice {
                 }
             },
 
+            ExecutorMode::Exited { .. } | ExecutorMode::Halted => {
+                if complete_error {
+                    Err(ProgramCompleted)
+                } else {
+                    Ok(true)
+                }
+            }
+
+            LimitReached(Limit::Time) => Err(ExecutionTimedOut),
+            LimitReached(limit) => Err(LimitExceeded(limit)),
+
             _ => Ok(true)
         }
     }
 
+    // The syscall can still stop the run, ex. by going over the output limit or writing to a watched buffer.
+    fn syscall_handled(&self, complete_error: bool) -> Result<bool, UnitDeviceError> {
+        self.executor.syscall_handled();
+
+        let frame = self.executor.frame();
+
+        if frame.mode == Running {
+            Ok(false)
+        } else {
+            self.handle_frame(&frame, complete_error)
+        }
+    }
+
     pub fn step(&self) -> Result<(), UnitDeviceError> {
         self.execute_until([Steps(1)])
     }
 
     pub fn backstep(&self) -> bool {
-        let Some(entry) = self.executor.with_tracker(|tracker| tracker.pop()) else {
-            return false
-        };
-
-        self.executor.with_state(|state| {
-            entry.apply(&mut state.registers, &mut state.memory.backing);
-        });
-
-        true
+        self.executor.reverse_step()
     }
 
     pub fn load_params(&self, params: &[u32]) {
@@ -564,21 +626,23 @@ impl UnitDevice {
 
         self.executor.set_breakpoints(parameters.breakpoints.into_iter().collect());
 
-        let did_timeout = Arc::new(AtomicBool::new(false));
-        let did_timeout_clone = did_timeout.clone();
+        // The run loop checks the deadline itself, so a late timer can not pause a later run.
+        let limits = self.executor.limits();
+
+        if let Some(duration) = parameters.timeout {
+            self.executor.set_limits(limits.with_timeout(duration))
+        }
 
-        let cancel = parameters.timeout.map(move |duration| {
-            let executor = self.executor.clone();
+        let result = self.run_until(parameters.steps, parameters.complete_error);
 
-            make_timeout(move || {
-                did_timeout_clone.store(true, Ordering::Relaxed);
+        self.executor.set_limits(limits);
 
-                executor.pause();
-            }, duration)
-        });
+        result
+    }
 
+    fn run_until(&self, steps: Option<usize>, complete_error: bool) -> Result<(), UnitDeviceError> {
         loop {
-            let frame = if let Some(count) = parameters.steps {
+            let frame = if let Some(count) = steps {
                 self.executor.override_mode(Running);
 
                 let result = self.executor.run_batched(count, true, true);
@@ -589,23 +653,16 @@ impl UnitDevice {
                 
                 self.executor.frame()
             } else {
-                self.executor.run(self.executor.is_breakpoint())
+                let skip_breakpoint = self.executor.is_breakpoint();
+
+                self.executor.override_mode(Running);
+                self.executor.run(skip_breakpoint)
             };
 
-            if self.handle_frame(&frame, parameters.complete_error)? {
-                break
+            if self.handle_frame(&frame, complete_error)? {
+                return Ok(())
             }
         }
-
-        if let Some(cancel) = cancel {
-            cancel.store(true, Ordering::Relaxed)
-        }
-
-        if did_timeout.load(Ordering::Relaxed) {
-            return Err(ExecutionTimedOut)
-        }
-
-        Ok(())
     }
 
     pub fn execute_until<const N: usize>(&self, conditions: [StopCondition; N]) -> Result<(), UnitDeviceError> {
@@ -681,3 +738,60 @@ impl UnitDevice {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::execution::breakpoints::{WatchKind, Watchpoint};
+    use super::*;
+
+    fn device(source: &str) -> UnitDevice {
+        UnitDevice::new(assemble_from(source).unwrap())
+    }
+
+    #[test]
+    fn output_limit_stops_at_the_print() {
+        let device = device("
+            .data
+            message: .asciiz \"hello world\"
+            .text
+            la $a0, message
+            li $v0, 4
+            syscall
+            li $t0, 1
+            syscall
+            li $v0, 10
+            syscall
+        ");
+
+        device.set_limits(Limits::new().with_output_bytes(4));
+
+        assert_eq!(device.execute_until([StopCondition::Complete]), Err(LimitExceeded(Limit::OutputBytes)));
+        assert_eq!(device.console.output(), b"hello world");
+        assert_eq!(device.get(RegisterName::T0), 0);
+        assert_eq!(device.executor.frame().mode, LimitReached(Limit::OutputBytes));
+    }
+
+    #[test]
+    fn syscall_write_stops_at_a_watchpoint() {
+        let device = device("
+            .data
+            buffer: .space 4
+            .text
+            la $a0, buffer
+            li $a1, 16
+            li $v0, 8
+            syscall
+            li $t0, 1
+        ");
+
+        let buffer = device.binary.labels["buffer"];
+
+        device.console.push_input(b"overflowing\n");
+        device.executor.add_watchpoint(Watchpoint::Memory { address: buffer + 4, length: 4, kind: WatchKind::Write });
+
+        assert_eq!(device.execute_until([StopCondition::Complete]), Ok(()));
+        assert_eq!(device.executor.frame().mode, ExecutorMode::Breakpoint);
+        assert_eq!(device.get(RegisterName::T0), 0);
+    }
+}
@@ -68,6 +68,7 @@ pub enum Instruction {
     Mtlo { s: RegisterName },
     Trap,
     Syscall,
+    Break,
 }
 
 pub fn sig(imm: u16) -> String {
@@ -362,6 +363,10 @@ impl Decoder<Instruction> for InstructionDecoder {
     fn syscall(&mut self) -> Instruction {
         Instruction::Syscall
     }
+
+    fn r#break(&mut self) -> Instruction {
+        Instruction::Break
+    }
 }
 
 pub enum InstructionParameter {
@@ -441,6 +446,7 @@ impl Instruction {
             Instruction::Mtlo { .. } => "mtlo",
             Instruction::Trap { .. } => "trap",
             Instruction::Syscall { .. } => "syscall",
+            Instruction::Break => "break",
         }
     }
 
@@ -507,6 +513,7 @@ impl Instruction {
             Instruction::Mtlo { s } => vec![s.into()],
             Instruction::Trap => vec![],
             Instruction::Syscall => vec![],
+            Instruction::Break => vec![],
         }
     }
 }
@@ -575,6 +582,7 @@ impl Display for Instruction {
             Instruction::Mtlo { s } => write!(f, "mtlo {}", s),
             Instruction::Trap => write!(f, "trap"),
             Instruction::Syscall => write!(f, "syscall"),
+            Instruction::Break => write!(f, "break"),
         }
     }
 }
@@ -1,3 +1,4 @@
+pub mod analysis;
 pub mod device;
 pub mod instruction;
 pub mod register;
@@ -1,9 +1,9 @@
 use std::fmt::{Display, Formatter};
 use crate::cpu::state::Registers;
 use num_derive::{ToPrimitive, FromPrimitive};
-use num_traits::ToPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, ToPrimitive, FromPrimitive)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive)]
 pub enum RegisterName {
     Zero = 0, AT = 1,
     V0 = 2, V1 = 3, A0 = 4, A1 = 5, A2 = 6, A3 = 7,
@@ -71,3 +71,100 @@ impl Registers {
         self.line[index] = value
     }
 }
+
+impl RegisterName {
+    // Accepts names with or without the leading $, ex. "$t0", "sp" or "$31".
+    pub fn from_name(name: &str) -> Option<RegisterName> {
+        let name = name.strip_prefix('$').unwrap_or(name);
+
+        if let Ok(index) = name.parse::<u8>() {
+            return FromPrimitive::from_u8(index)
+        }
+
+        Some(match name {
+            "zero" => RegisterName::Zero,
+            "at" => RegisterName::AT,
+            "v0" => RegisterName::V0,
+            "v1" => RegisterName::V1,
+            "a0" => RegisterName::A0,
+            "a1" => RegisterName::A1,
+            "a2" => RegisterName::A2,
+            "a3" => RegisterName::A3,
+            "t0" => RegisterName::T0,
+            "t1" => RegisterName::T1,
+            "t2" => RegisterName::T2,
+            "t3" => RegisterName::T3,
+            "t4" => RegisterName::T4,
+            "t5" => RegisterName::T5,
+            "t6" => RegisterName::T6,
+            "t7" => RegisterName::T7,
+            "s0" => RegisterName::S0,
+            "s1" => RegisterName::S1,
+            "s2" => RegisterName::S2,
+            "s3" => RegisterName::S3,
+            "s4" => RegisterName::S4,
+            "s5" => RegisterName::S5,
+            "s6" => RegisterName::S6,
+            "s7" => RegisterName::S7,
+            "t8" => RegisterName::T8,
+            "t9" => RegisterName::T9,
+            "k0" => RegisterName::K0,
+            "k1" => RegisterName::K1,
+            "gp" => RegisterName::GP,
+            "sp" => RegisterName::SP,
+            "fp" | "s8" => RegisterName::FP,
+            "ra" => RegisterName::RA,
+
+            _ => return None
+        })
+    }
+}
+
+// Any register a debugger can look at, including the ones outside the register line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RegisterId {
+    Line(RegisterName),
+    Pc,
+    Hi,
+    Lo,
+}
+
+impl RegisterId {
+    pub fn from_name(name: &str) -> Option<RegisterId> {
+        match name.strip_prefix('$').unwrap_or(name) {
+            "pc" => Some(RegisterId::Pc),
+            "hi" => Some(RegisterId::Hi),
+            "lo" => Some(RegisterId::Lo),
+            _ => RegisterName::from_name(name).map(RegisterId::Line)
+        }
+    }
+
+    pub fn get(&self, registers: &Registers) -> u32 {
+        match self {
+            RegisterId::Line(name) => registers.get(*name),
+            RegisterId::Pc => registers.pc,
+            RegisterId::Hi => registers.hi,
+            RegisterId::Lo => registers.lo,
+        }
+    }
+
+    pub fn set(&self, registers: &mut Registers, value: u32) {
+        match self {
+            RegisterId::Line(name) => registers.set(*name, value),
+            RegisterId::Pc => registers.pc = value,
+            RegisterId::Hi => registers.hi = value,
+            RegisterId::Lo => registers.lo = value,
+        }
+    }
+}
+
+impl Display for RegisterId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterId::Line(name) => Display::fmt(name, f),
+            RegisterId::Pc => write!(f, "$pc"),
+            RegisterId::Hi => write!(f, "$hi"),
+            RegisterId::Lo => write!(f, "$lo"),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::{Display, Formatter};
 use crate::cpu::state::Registers;
+use crate::execution::trackers::call_stack::Backtrace;
 use crate::unit::instruction::{Instruction, sig, sig_u32};
 use crate::unit::instruction::Instruction::{Add, Addi, Div, Divu, Lb, Lbu, Lh, Lhu, Lw, Sb, Sh, Sub, Sw};
 use crate::unit::register::RegisterName;
@@ -15,7 +16,8 @@ pub struct MemoryErrorDescription {
     pub reason: MemoryErrorReason,
     pub alignment: u32,
     pub source: RegisterValue,
-    pub immediate: u16
+    pub immediate: u16,
+    pub backtrace: Option<Backtrace> // filled in by frontends that track calls, ex. run --backtrace
 }
 
 pub struct RegisterValue {
@@ -49,6 +51,7 @@ pub struct TrapErrorDescription {
     pub reason: TrapErrorReason,
     pub source: RegisterValue,
     pub temp: RegisterImmediate,
+    pub backtrace: Option<Backtrace> // see MemoryErrorDescription
 }
 
 impl MemoryErrorDescription {
@@ -60,7 +63,8 @@ impl MemoryErrorDescription {
             reason,
             alignment,
             source: registers.value(source),
-            immediate
+            immediate,
+            backtrace: None
         }
     }
 }
@@ -73,7 +77,8 @@ impl TrapErrorDescription {
             instruction,
             reason,
             source: registers.value(source),
-            temp: RegisterImmediate::Value(registers.value(temp))
+            temp: RegisterImmediate::Value(registers.value(temp)),
+            backtrace: None
         }
     }
 
@@ -84,7 +89,8 @@ impl TrapErrorDescription {
             instruction,
             reason,
             source: registers.value(source),
-            temp: RegisterImmediate::Immediate(imm)
+            temp: RegisterImmediate::Immediate(imm),
+            backtrace: None
         }
     }
 }
@@ -153,20 +159,29 @@ impl RegisterImmediate {
     }
 }
 
+fn write_backtrace(f: &mut Formatter<'_>, backtrace: &Option<Backtrace>) -> std::fmt::Result {
+    match backtrace {
+        Some(backtrace) => write!(f, "Backtrace:\n{backtrace}"),
+        None => Ok(())
+    }
+}
+
 impl Display for MemoryErrorDescription {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self.reason {
             MemoryErrorReason::Unmapped => {
                 writeln!(f, "Memory access to 0x{:08x} is prohibited,", self.address())?;
                 writeln!(f, " > {} ({} + {} = 0x{:08x} is unmapped)", self.instruction, self.source.hex_string(), sig(self.immediate), self.address())?;
-                writeln!(f, "Double check to make sure you meant to access this location.")
+                writeln!(f, "Double check to make sure you meant to access this location.")?;
             },
             MemoryErrorReason::Alignment => {
                 writeln!(f, "Memory access to 0x{:08x} must be a multiple of {} for this instruction.", self.address(), self.alignment)?;
                 writeln!(f, " > {} ({} + {} = 0x{:08x} is not a multiple of {})", self.instruction, self.source.hex_string(), sig(self.immediate), self.address(), self.alignment)?;
-                writeln!(f, "Ensure that the data you are accessing is aligned by {}, or use lb/sb to load/store unaligned bytes.", self.alignment)
+                writeln!(f, "Ensure that the data you are accessing is aligned by {}, or use lb/sb to load/store unaligned bytes.", self.alignment)?;
             }
         }
+
+        write_backtrace(f, &self.backtrace)
     }
 }
 
@@ -191,6 +206,8 @@ impl Display for TrapErrorDescription {
             }
         }
 
-        writeln!(f, "If you expected overflow behaviour, use unsigned instructions (addu, subu, multu, etc.)")
+        writeln!(f, "If you expected overflow behaviour, use unsigned instructions (addu, subu, multu, etc.)")?;
+
+        write_backtrace(f, &self.backtrace)
     }
 }
@@ -1,33 +1,151 @@
 use std::fs;
 use std::fs::File;
+use std::io;
+use std::io::BufWriter;
 use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use std::time::Instant;
 use clap::{Parser, Subcommand};
 use titan::elf::Elf;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use titan::assembler::binary::Binary;
 use titan::assembler::string::assemble_from_path;
 use titan::cpu::memory::section::{DefaultResponder, SectionMemory};
+use titan::cpu::memory::watched::WatchedMemory;
 use titan::cpu::State;
+use titan::cpu::state::Registers;
+use titan::cpu::error::Error as CpuError;
+use titan::cpu::Memory;
+use titan::debug::dap::DapServer;
+use titan::debug::gdb::GdbServer;
 use titan::execution::Executor;
 use titan::execution::elf::setup::create_simple_state;
+use titan::execution::elf::inspection::Inspection;
+use titan::execution::executor::ExecutorMode;
+use titan::execution::expression::Watch;
+use titan::execution::save::MachineImage;
+use titan::execution::speed::Speed;
+use titan::execution::termination::Termination;
+use titan::execution::trackers::call_stack::{Backtrace, CallStackTracker};
+use titan::execution::trackers::composite::TrackerSet;
 use titan::execution::trackers::empty::EmptyTracker;
+use titan::execution::trackers::Tracker;
+use titan::execution::trackers::coverage::CoverageTracker;
+use titan::execution::trackers::history::{HistoryTracker, DEFAULT_BUDGET};
+use titan::execution::trackers::pipeline::{Forwarding, PipelineConfig, PipelineTracker, Stage};
+use titan::execution::trackers::profile::ProfileTracker;
+use titan::execution::trackers::trace::{TraceFormat, TraceTracker};
+use titan::unit::instruction::InstructionDecoder;
+use titan::unit::suggestions::{MemoryErrorDescription, MemoryErrorReason, TrapErrorDescription};
 
 #[derive(Subcommand, Debug)]
 enum Command {
     Build { filename: String },
-    Run { filename: String },
-    Test { filename: String }
+    Run {
+        filename: String,
+
+        // Write a text trace of every instruction to this file.
+        #[arg(long)]
+        trace: Option<String>,
+
+        // Instructions per second, as fast as possible if not given.
+        #[arg(long)]
+        speed: Option<u32>,
+
+        // Write a save state here when the program stops, ex. to attach a crash to a bug report.
+        #[arg(long)]
+        save: Option<String>,
+
+        // Start from a save state instead of the entry point.
+        #[arg(long)]
+        load: Option<String>,
+
+        // Print an expression when the program stops, ex. --watch 'word[arr]@10, d'. Can be repeated.
+        #[arg(long, allow_hyphen_values = true)]
+        watch: Vec<String>,
+
+        // Track calls, so an error prints the functions that led to it. Slows the run down.
+        #[arg(long)]
+        backtrace: bool
+    },
+    Test { filename: String },
+    // Serves the GDB remote protocol over TCP, or over stdin/stdout without a port.
+    Gdb {
+        filename: String,
+
+        #[arg(short, long)]
+        port: Option<u16>
+    },
+    // Serves the Debug Adapter Protocol over stdin/stdout, the program comes from the launch request.
+    Dap,
+    // Runs the program and prints where its instructions went.
+    Profile {
+        filename: String,
+
+        // Write folded stacks for flamegraph.pl to this file.
+        #[arg(long)]
+        folded: Option<String>,
+
+        // Print the disassembly with a count for each instruction.
+        #[arg(long)]
+        annotate: bool
+    },
+    // Runs the program and prints the source with execution counts.
+    Coverage {
+        filename: String,
+
+        // Write an LCOV tracefile to this path.
+        #[arg(long)]
+        lcov: Option<String>
+    },
+    // Runs the program on a five-stage pipeline model and prints cycles, CPI and stalls.
+    Pipeline {
+        filename: String,
+
+        // none, memory (MEM/WB only) or full.
+        #[arg(long, default_value = "full")]
+        forwarding: String,
+
+        // Stage that resolves branches, ex. id or ex.
+        #[arg(long, default_value = "id")]
+        branch_stage: String,
+
+        #[arg(long, default_value_t = 4)]
+        multiply_latency: u64,
+
+        #[arg(long, default_value_t = 12)]
+        divide_latency: u64,
+
+        // Print a pipeline diagram of these retired instructions, as first:count (ex. 0:20).
+        #[arg(long)]
+        window: Option<String>,
+
+        // Write the diagram as CSV to this file instead.
+        #[arg(long)]
+        csv: Option<String>
+    }
 }
 
 impl Command {
-    fn filename(&self) -> &str {
+    fn filename(&self) -> Option<&str> {
         match self {
-            Command::Build { filename } => filename,
-            Command::Run { filename } => filename,
-            Command::Test { filename } => filename,
+            Command::Build { filename } => Some(filename),
+            Command::Run { filename, .. } => Some(filename),
+            Command::Test { filename } => Some(filename),
+            Command::Gdb { filename, .. } => Some(filename),
+            Command::Dap => None,
+            Command::Profile { filename, .. } => Some(filename),
+            Command::Coverage { filename, .. } => Some(filename),
+            Command::Pipeline { filename, .. } => Some(filename),
         }
     }
+
+    // Stdout belongs to the protocol when serving GDB over stdio.
+    fn quiet(&self) -> bool {
+        matches!(self, Command::Gdb { port: None, .. })
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -39,14 +157,53 @@ struct Args {
     emit: Option<String>
 }
 
+// Without a backtrace (see run --backtrace) only the error is described.
+fn report_error(error: CpuError, instruction: Option<u32>, state: &State<SectionMemory<DefaultResponder>>, backtrace: Option<Backtrace>, text: &str) {
+    let pc = state.registers.pc;
+    let instruction = instruction.and_then(|value| InstructionDecoder::decode(pc, value));
+
+    let description = instruction.and_then(|instruction| match error {
+        CpuError::MemoryUnmapped(_) => instruction
+            .describe_memory_error(MemoryErrorReason::Unmapped, &state.registers)
+            .map(|description| MemoryErrorDescription { backtrace: backtrace.clone(), ..description }.to_string()),
+        CpuError::MemoryAlign(_) => instruction
+            .describe_memory_error(MemoryErrorReason::Alignment, &state.registers)
+            .map(|description| MemoryErrorDescription { backtrace: backtrace.clone(), ..description }.to_string()),
+        CpuError::CpuTrap => instruction
+            .describe_trap_error(&state.registers)
+            .map(|description| TrapErrorDescription { backtrace: backtrace.clone(), ..description }.to_string()),
+        _ => None
+    });
+
+    match description {
+        Some(description) => print!("{description}"),
+        None => {
+            println!("{error}");
+
+            if let Some(backtrace) = backtrace {
+                print!("Backtrace:\n{}", backtrace.describe(|id| (id == 0).then_some(text)));
+            }
+        }
+    }
+}
+
 fn run(args: Args) -> Result<()> {
-    let filename = args.command.filename();
-    println!("Building {}...", filename);
+    let Some(filename) = args.command.filename() else {
+        return Ok(DapServer::new(io::stdout()).serve(io::stdin().lock())?)
+    };
+
+    let quiet = args.command.quiet();
+
+    if !quiet {
+        println!("Building {}...", filename);
+    }
 
     let text = fs::read_to_string(filename)?;
-    let binary = assemble_from_path(text, PathBuf::from(filename))?;
+    let binary = assemble_from_path(text.clone(), PathBuf::from(filename))?;
 
-    println!("Binary built!");
+    if !quiet {
+        println!("Binary built!");
+    }
 
     if let Some(emit) = args.emit {
         let elf: Elf = binary.create_elf();
@@ -57,26 +214,256 @@ fn run(args: Args) -> Result<()> {
     }
 
     match args.command {
-        Command::Build { filename: _ } => {}
-        Command::Run { filename: _ } | Command::Test { filename: _ } => {
+        Command::Build { filename: _ } | Command::Dap => {}
+        Command::Gdb { filename: _, port } => {
+            let elf: Elf = binary.create_elf();
+
+            let State { registers, memory, .. } = create_simple_state::<DefaultResponder>(&elf, 0x100000);
+
+            let mut state = State::new(registers.pc, WatchedMemory::new(memory));
+            state.registers = registers;
+
+            let executor = Arc::new(Executor::new(state, HistoryTracker::new(DEFAULT_BUDGET)));
+            executor.set_termination(Termination::for_binary(&binary));
+
+            let mut server = GdbServer::new(executor);
+
+            match port {
+                Some(port) => {
+                    println!("Waiting for GDB on port {port}...");
+
+                    server.listen(("127.0.0.1", port))?
+                }
+                None => server.serve(io::stdin(), io::stdout())?
+            }
+        }
+        Command::Profile { filename, folded, annotate } => {
             let elf: Elf = binary.create_elf();
 
+            let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x100000);
+            let debugger = Executor::new(state, ProfileTracker::new(elf.header.program_entry));
+            debugger.set_termination(Termination::for_binary(&binary));
+
+            debugger.override_mode(ExecutorMode::Running);
+            let frame = debugger.run(false);
+
+            println!("Running finished with mode: {:?}.\n", frame.mode);
+
+            debugger.with_tracker(|tracker| -> Result<()> {
+                print!("{}", tracker.report(&binary).describe(|id| (id == 0).then_some(text.as_str())));
+
+                if annotate {
+                    println!("\n{}", tracker.annotate(&Inspection::new(Some(&filename), &elf)));
+                }
+
+                if let Some(folded) = folded {
+                    fs::write(folded, tracker.folded(&binary))?;
+                }
+
+                Ok(())
+            })?;
+        }
+        Command::Pipeline { forwarding, branch_stage, multiply_latency, divide_latency, window, csv, .. } => {
+            let forwarding = Forwarding::from_name(&forwarding)
+                .ok_or_else(|| anyhow!("Unknown forwarding {forwarding}, expected none, memory or full"))?;
+            let branch_stage = Stage::from_name(&branch_stage)
+                .ok_or_else(|| anyhow!("Unknown stage {branch_stage}, expected if, id, ex, mem or wb"))?;
+
+            let mut config = PipelineConfig::new()
+                .with_forwarding(forwarding)
+                .with_branch_stage(branch_stage)
+                .with_multiply_latency(multiply_latency)
+                .with_divide_latency(divide_latency);
+
+            if let Some(window) = &window {
+                let (first, count) = window.split_once(':')
+                    .and_then(|(first, count)| Some((first.parse().ok()?, count.parse().ok()?)))
+                    .ok_or_else(|| anyhow!("Expected the window as first:count, ex. 0:20"))?;
+
+                config = config.with_window(first, count);
+            }
+
+            let elf: Elf = binary.create_elf();
+
+            let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x100000);
+            let debugger = Executor::new(state, PipelineTracker::new(config));
+            debugger.set_termination(Termination::for_binary(&binary));
+
+            debugger.override_mode(ExecutorMode::Running);
+            let frame = debugger.run(false);
+
+            println!("Running finished with mode: {:?}.\n", frame.mode);
+
+            debugger.with_tracker(|tracker| -> Result<()> {
+                print!("{}", tracker.report());
+
+                match &csv {
+                    Some(csv) => fs::write(csv, tracker.diagram_csv())?,
+                    None if window.is_some() => print!("\n{}", tracker.diagram()),
+                    None => { }
+                }
+
+                Ok(())
+            })?;
+        }
+        Command::Coverage { filename, lcov } => {
+            let elf: Elf = binary.create_elf();
+
+            let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x100000);
+            let debugger = Executor::new(state, CoverageTracker::new());
+            debugger.set_termination(Termination::for_binary(&binary));
+
+            debugger.override_mode(ExecutorMode::Running);
+            let frame = debugger.run(false);
+
+            println!("Running finished with mode: {:?}.\n", frame.mode);
+
+            let report = debugger.with_tracker(|tracker| tracker.report(&binary, |id| (id == 0).then_some(text.as_str())));
+
+            if let Some(file) = report.file(0) {
+                print!("{}\n{}", file.annotate(&text), report.summary(|_| filename.clone()));
+            }
+
+            if let Some(lcov) = lcov {
+                fs::write(lcov, report.lcov(|_| filename.clone()))?;
+            }
+        }
+        Command::Run { .. } | Command::Test { .. } => {
+            let elf: Elf = binary.create_elf();
+
+            let watches = match &args.command {
+                Command::Run { watch, .. } => watch.iter()
+                    .map(|text| Watch::parse(text, |name| binary.labels.get(name).copied())
+                        .map(|watch| (text.clone(), watch))
+                        .map_err(|error| anyhow!("{text}: {error}")))
+                    .collect::<Result<Vec<_>>>()?,
+                _ => vec![]
+            };
+
             let instant = Instant::now();
 
             let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x100000);
-            let debugger = Executor::new(state, EmptyTracker { });
 
-            let frame = debugger.run();
+            let (trace, backtrace) = match &args.command {
+                Command::Run { trace, backtrace, .. } => (trace.as_ref(), *backtrace),
+                _ => (None, false)
+            };
+
+            // Trackers only run when an option asks for them, so the timing matches a plain run.
+            if trace.is_none() && !backtrace {
+                let debugger = Executor::new(state, EmptyTracker { });
+
+                return run_program(&args.command, &binary, &text, &watches, instant, debugger, |_, _| None)
+            }
+
+            let mut trackers = TrackerSet::new();
+
+            if let Some(trace) = trace {
+                trackers.add(TraceTracker::new(BufWriter::new(File::create(trace)?), TraceFormat::Text));
+            }
 
-            let end = instant.elapsed();
+            let call_stack = backtrace.then(|| trackers.add(CallStackTracker::new(elf.header.program_entry)));
 
-            println!("Running finished in {}ms with mode: {:?}.", end.as_millis(), frame.mode);
+            let debugger = Executor::new(state, trackers);
+
+            run_program(&args.command, &binary, &text, &watches, instant, debugger, |trackers, registers| {
+                call_stack
+                    .and_then(|id| trackers.get::<CallStackTracker>(id))
+                    .map(|tracker| tracker.backtrace(registers, &binary))
+            })?
         }
     }
 
     Ok(())
 }
 
+// Runs a program for run and test, then prints how it ended. Exits with the program's status if it failed.
+fn run_program<Track: Tracker<SectionMemory<DefaultResponder>>>(
+    command: &Command,
+    binary: &Binary,
+    text: &str,
+    watches: &[(String, Watch)],
+    instant: Instant,
+    debugger: Executor<SectionMemory<DefaultResponder>, Track>,
+    backtrace: impl FnOnce(&mut Track, &Registers) -> Option<Backtrace>
+) -> Result<()> {
+    debugger.set_termination(Termination::for_binary(binary));
+
+    if let Command::Run { speed: Some(rate), .. } = command {
+        debugger.set_speed(Speed::PerSecond(*rate));
+    }
+
+    let loaded = match command {
+        Command::Run { load: Some(load), .. } => {
+            let image = MachineImage::read(&mut File::open(load)?)?;
+            debugger.load_image(&image);
+
+            Some(image.mode)
+        }
+        _ => None
+    };
+
+    // A program that finished before it was saved stays finished, anything else picks up where it stopped.
+    if !matches!(loaded, Some(ExecutorMode::Exited { .. } | ExecutorMode::Halted)) {
+        debugger.override_mode(ExecutorMode::Running);
+    }
+
+    let frame = debugger.run(false);
+
+    let end = instant.elapsed();
+
+    println!("Running finished in {}ms with mode: {:?}.", end.as_millis(), frame.mode);
+
+    if let Command::Run { save: Some(save), .. } = command {
+        debugger.save_image().write(&mut File::create(save)?)?;
+
+        println!("Saved state to {save}.");
+    }
+
+    debugger.with_state(|state| {
+        for (text, watch) in watches {
+            match watch.evaluate(state) {
+                Ok(value) => println!("{text} = {value}"),
+                Err(error) => println!("{text}: {error}")
+            }
+        }
+    });
+
+    let status = match frame.mode {
+        ExecutorMode::Exited { code } => {
+            println!("Program exited with code {code}.");
+
+            code as i32
+        }
+        ExecutorMode::Halted => {
+            println!("Program halted.");
+
+            0
+        }
+        ExecutorMode::Invalid(error) => {
+            let backtrace = debugger.with_tracker(|tracker| backtrace(tracker, &frame.registers));
+
+            debugger.with_state(|state| {
+                let instruction = state.memory.get_u32(state.registers.pc).ok();
+
+                report_error(error, instruction, state, backtrace, text)
+            });
+
+            1
+        }
+        _ => 0
+    };
+
+    // Flushes the trace before exiting.
+    drop(debugger);
+
+    if status != 0 {
+        process::exit(status)
+    }
+
+    Ok(())
+}
+
 fn main() {
     let args = Args::parse();
 